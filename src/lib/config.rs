@@ -0,0 +1,243 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{ArgMatches, ValueSource};
+use serde::Deserialize;
+
+use crate::tools::{
+    extract::Opts as ExtractOpts,
+    index::{Opts as IndexOpts, NTH_ENV},
+};
+
+/// The config file that is read from the current directory when `--config` is not given
+pub const DEFAULT_CONFIG_PATH: &str = "fqme.toml";
+
+/// Defaults for the command line options, read from a TOML config file, e.g.:
+///
+/// ```toml
+/// [index]
+/// nth = 1000
+/// checksum = true
+///
+/// [extract]
+/// threads = 8
+/// ```
+///
+/// The precedence is: options given on the command line, then their environment variables (e.g.
+/// `FQME_NTH` for `--nth`), then the config file, then the built-in defaults.  As flags (e.g.
+/// `checksum`) can only be turned on from the command line, a flag set in the config file cannot be
+/// turned off for a single invocation.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub index: IndexDefaults,
+    pub extract: ExtractDefaults,
+}
+
+/// Defaults for `fqme index`.  Each field corresponds to the option of the same name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexDefaults {
+    pub nth: Option<u64>,
+    pub raw: bool,
+    pub checksum: bool,
+}
+
+/// Defaults for `fqme extract`.  Each field corresponds to the option of the same name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractDefaults {
+    pub threads: Option<usize>,
+    pub mmap: bool,
+    pub strict: bool,
+}
+
+impl Config {
+    /// Reads the config file at the given path
+    pub fn read(path: &Path) -> Result<Config> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file: {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    /// Reads the given config file, or if none is given, `fqme.toml` in the current directory if
+    /// it exists.  Returns the empty config (the built-in defaults) if there is no config file.
+    pub fn find(path: Option<&Path>) -> Result<Config> {
+        match path {
+            Some(path) => Config::read(path),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if path.exists() {
+                    Config::read(&path)
+                } else {
+                    Ok(Config::default())
+                }
+            }
+        }
+    }
+
+    /// Sets the index options that were not given on the command line from the config.
+    pub fn apply_index(&self, opts: &mut IndexOpts) {
+        let defaults = &self.index;
+        // --nth-bytes and `FQME_NTH` both take precedence over the config's nth
+        if opts.nth.is_none() && opts.nth_bytes.is_none() && std::env::var_os(NTH_ENV).is_none() {
+            opts.nth = defaults.nth;
+        }
+        opts.raw |= defaults.raw;
+        opts.checksum |= defaults.checksum;
+    }
+
+    /// Sets the extract options that were not given on the command line (per `matches`) from the
+    /// config.
+    pub fn apply_extract(&self, opts: &mut ExtractOpts, matches: &ArgMatches) {
+        let defaults = &self.extract;
+        if let Some(threads) = defaults.threads.filter(|_| !is_given(matches, "threads")) {
+            opts.threads = threads;
+        }
+        opts.mmap |= defaults.mmap;
+        opts.strict |= defaults.strict;
+    }
+}
+
+/// Returns true if the option was given on the command line, rather than taking its default value
+fn is_given(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsString, path::Path};
+
+    use clap::{CommandFactory, FromArgMatches};
+    use tempfile::TempDir;
+
+    use crate::tools::{
+        extract::Opts as ExtractOpts,
+        fastq_index::CheckpointSpacing::{Bytes, Records},
+        index::Opts as IndexOpts,
+    };
+
+    use super::{Config, ExtractDefaults, IndexDefaults};
+
+    /// Restores an environment variable to its original value when dropped, including when a test
+    /// panics
+    struct EnvGuard {
+        name: &'static str,
+        value: Option<OsString>,
+    }
+
+    impl EnvGuard {
+        fn new(name: &'static str) -> EnvGuard {
+            EnvGuard { name, value: std::env::var_os(name) }
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            match &self.value {
+                Some(value) => std::env::set_var(self.name, value),
+                None => std::env::remove_var(self.name),
+            }
+        }
+    }
+
+    fn index_opts(config: &Config, args: &[&str]) -> IndexOpts {
+        let matches = IndexOpts::command().get_matches_from(args);
+        let mut opts = IndexOpts::from_arg_matches(&matches).unwrap();
+        config.apply_index(&mut opts);
+        opts
+    }
+
+    #[test]
+    fn test_config_read() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fqme.toml");
+        std::fs::write(&path, "[index]\nnth = 10\nchecksum = true\n\n[extract]\nthreads = 2\n")
+            .unwrap();
+        let expected = Config {
+            index: IndexDefaults { nth: Some(10), raw: false, checksum: true },
+            extract: ExtractDefaults { threads: Some(2), mmap: false, strict: false },
+        };
+        assert_eq!(Config::read(&path).unwrap(), expected);
+        assert_eq!(Config::find(Some(&path)).unwrap(), expected);
+
+        // unknown options are an error
+        std::fs::write(&path, "[index]\nbuffer_size = 10\n").unwrap();
+        let err = Config::read(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid config file"));
+
+        // a missing config file is only an error when given explicitly
+        let err = Config::find(Some(Path::new("missing.toml"))).unwrap_err();
+        assert!(format!("{:#}", err).contains("Could not read config file: missing.toml"));
+    }
+
+    #[test]
+    fn test_config_apply_index() {
+        // the environment variable is cleared, as it would take precedence over the config
+        let _guard = EnvGuard::new("FQME_NTH");
+        std::env::remove_var("FQME_NTH");
+        let config = Config {
+            index: IndexDefaults { nth: Some(10), raw: false, checksum: true },
+            ..Config::default()
+        };
+
+        // the config is used when the option is omitted
+        let opts = index_opts(&config, &["index", "--output", "test.fqi"]);
+        assert_eq!(opts.spacing().unwrap(), Records(10));
+        assert!(opts.checksum);
+
+        // and ignored when it is given, even if it is the built-in default
+        let opts = index_opts(&config, &["index", "--output", "test.fqi", "--nth", "3"]);
+        assert_eq!(opts.spacing().unwrap(), Records(3));
+        let opts = index_opts(&config, &["index", "--output", "test.fqi", "-n", "100000"]);
+        assert_eq!(opts.spacing().unwrap(), Records(100_000));
+
+        // the built-in default without a config
+        let opts = index_opts(&Config::default(), &["index", "--output", "test.fqi"]);
+        assert_eq!(opts.spacing().unwrap(), Records(100_000));
+        assert!(!opts.checksum);
+
+        // the environment variable takes precedence over the config, but not the command line
+        std::env::set_var("FQME_NTH", "7");
+        let opts = index_opts(&config, &["index", "--output", "test.fqi"]);
+        assert_eq!(opts.spacing().unwrap(), Records(7));
+        let opts = index_opts(&Config::default(), &["index", "--output", "test.fqi"]);
+        assert_eq!(opts.spacing().unwrap(), Records(7));
+        let opts = index_opts(&config, &["index", "--output", "test.fqi", "--nth", "3"]);
+        assert_eq!(opts.spacing().unwrap(), Records(3));
+
+        // --nth-bytes takes precedence over both, without setting --nth
+        let opts = index_opts(&config, &["index", "--output", "test.fqi", "--nth-bytes", "64"]);
+        assert_eq!(opts.spacing().unwrap(), Bytes(64));
+        assert_eq!(opts.nth, None);
+
+        // an invalid environment variable is an error
+        std::env::set_var("FQME_NTH", "many");
+        let opts = index_opts(&Config::default(), &["index", "--output", "test.fqi"]);
+        let err = opts.spacing().unwrap_err();
+        assert_eq!(format!("{:#}", err), "Invalid FQME_NTH: many: invalid digit found in string");
+    }
+
+    #[test]
+    fn test_config_apply_extract() {
+        let config = Config {
+            extract: ExtractDefaults { threads: Some(8), mmap: true, strict: false },
+            ..Config::default()
+        };
+        for (args, threads) in [
+            (&["extract", "--input", "test.fastq.gz"][..], 8),
+            (&["extract", "--input", "test.fastq.gz", "--threads", "2"][..], 2),
+        ] {
+            let matches = ExtractOpts::command().get_matches_from(args);
+            let mut opts = ExtractOpts::from_arg_matches(&matches).unwrap();
+            config.apply_extract(&mut opts, &matches);
+            assert_eq!(opts.threads, threads);
+            assert!(opts.mmap);
+            assert!(!opts.strict);
+        }
+    }
+}
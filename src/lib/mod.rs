@@ -7,8 +7,18 @@
 )]
 pub mod tools {
     pub mod bgzf_index;
+    pub mod diff;
     pub mod extract;
     pub mod fastq_index;
     pub mod index;
+    pub mod info;
+    pub mod locate;
+    pub mod stats;
+    pub mod upgrade;
+    pub mod verify;
 }
+pub mod config;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod types;
 pub mod utils;
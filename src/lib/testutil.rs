@@ -0,0 +1,262 @@
+//! Utilities for building test inputs.
+//!
+//! Enabled outside of the crate's tests with the `testutil` feature, e.g. for benchmarks.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+    thread::{self, ThreadId},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use gzp::{deflate::Bgzf, BlockFormatSpec};
+use libdeflater::{CompressionLvl, Compressor, Crc};
+use log::{LevelFilter, Log, Metadata, Record};
+use seq_io::{fastq::OwnedRecord, BaseRecord};
+
+use crate::tools::{extract::BGZF_EOF, fastq_index::FastqIndex};
+
+/// A record named `read-<index>`, with the sequence `GATTACA`, which is 26 bytes as FASTQ for a
+/// single-digit index
+pub fn record(index: usize) -> OwnedRecord {
+    OwnedRecord {
+        head: format!("read-{}", index).into_bytes(),
+        seq: b"GATTACA".to_vec(),
+        qual: b"IIIIIII".to_vec(),
+    }
+}
+
+/// The records `read-1` to `read-<num_records>` (see `record`)
+pub fn records(num_records: usize) -> Vec<OwnedRecord> {
+    (1..=num_records).map(record).collect()
+}
+
+/// A record named `some-read-name`, with the sequence `GATTACA`, which is 34 bytes as FASTQ
+pub fn fixed_record() -> OwnedRecord {
+    OwnedRecord {
+        head: b"some-read-name".to_vec(),
+        seq: b"GATTACA".to_vec(),
+        qual: b"IIIIIII".to_vec(),
+    }
+}
+
+/// An index of `num_records` copies of `fixed_record`, with an entry every third record
+pub fn index(num_records: usize) -> FastqIndex {
+    FastqIndex::from(vec![fixed_record(); num_records].into_iter().map(Ok), 3, &mut None).unwrap()
+}
+
+/// Serializes the records as FASTQ
+pub fn to_fastq(records: &[OwnedRecord]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    for rec in records {
+        rec.write(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+/// BGZF compresses the data into blocks of at most `block_size` uncompressed bytes, returning
+/// the compressed bytes and the corresponding GZI index bytes.
+pub fn bgzip(data: &[u8], block_size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut compressor = Compressor::new(CompressionLvl::default());
+    let mut bgzf: Vec<u8> = vec![];
+    let mut offsets: Vec<(u64, u64)> = vec![];
+    for (index, chunk) in data.chunks(block_size).enumerate() {
+        if index > 0 {
+            offsets.push((bgzf.len() as u64, (index * block_size) as u64));
+        }
+        let mut compressed = vec![0; compressor.deflate_compress_bound(chunk.len())];
+        let size = compressor.deflate_compress(chunk, &mut compressed).unwrap();
+        compressed.truncate(size);
+        let mut crc = Crc::new();
+        crc.update(chunk);
+        let block_size = Bgzf::HEADER_SIZE + compressed.len() + 8;
+        bgzf.extend_from_slice(&[31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, b'B', b'C', 2, 0]);
+        bgzf.write_u16::<LittleEndian>((block_size - 1) as u16).unwrap();
+        bgzf.extend_from_slice(&compressed);
+        bgzf.write_u32::<LittleEndian>(crc.sum()).unwrap();
+        bgzf.write_u32::<LittleEndian>(chunk.len() as u32).unwrap();
+    }
+    bgzf.extend_from_slice(&BGZF_EOF);
+
+    let mut gzi: Vec<u8> = vec![];
+    gzi.write_u64::<LittleEndian>(offsets.len() as u64).unwrap();
+    for (compressed_offset, uncompressed_offset) in offsets {
+        gzi.write_u64::<LittleEndian>(compressed_offset).unwrap();
+        gzi.write_u64::<LittleEndian>(uncompressed_offset).unwrap();
+    }
+    (bgzf, gzi)
+}
+
+/// Writes the BGZF compressed FASTQ, along with its FASTQ and BGZF indexes, to the given
+/// directory, returning the path to the FASTQ.
+pub fn write_fastq(dir: &Path, records: &[OwnedRecord], nth: u64, block_size: usize) -> PathBuf {
+    let input = dir.join("test.fastq.gz");
+    let fastq = to_fastq(records);
+    let (bgzf, gzi) = bgzip(&fastq, block_size);
+    fs::write(&input, bgzf).unwrap();
+    fs::write(dir.join("test.fastq.gz.gzi"), gzi).unwrap();
+    let index = FastqIndex::from_raw_reader(&fastq[..], nth, &mut None).unwrap();
+    index.write(&dir.join("test.fastq.gz.fqi"));
+    input
+}
+
+/// Writes `num_records` FASTQ records of `read_len` bases, with random bases and qualities
+/// generated deterministically from the seed, so that large inputs can be reproduced.  The records
+/// are named `synthetic-<N>`, counting from 1.
+pub fn write_synthetic_fastq<W: Write>(
+    mut writer: W,
+    num_records: usize,
+    read_len: usize,
+    seed: u64,
+) -> io::Result<()> {
+    let mut state = seed;
+    let mut seq = vec![0u8; read_len];
+    let mut qual = vec![0u8; read_len];
+    for record_number in 1..=num_records {
+        random_bases(&mut state, &mut seq, &mut qual);
+        writeln!(writer, "@synthetic-{}", record_number)?;
+        writer.write_all(&seq)?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(&qual)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Writes `num_records` FASTQ records as for `write_synthetic_fastq`, but with random read lengths
+/// from 1 to `max_read_len` bases, and a comment on about half of the `+` lines (either the
+/// record's name or random text), e.g. to check that the byte offsets in an index are exact.
+pub fn write_varied_synthetic_fastq<W: Write>(
+    mut writer: W,
+    num_records: usize,
+    max_read_len: usize,
+    seed: u64,
+) -> io::Result<()> {
+    let mut state = seed;
+    for record_number in 1..=num_records {
+        let read_len = 1 + (splitmix64(&mut state) % max_read_len as u64) as usize;
+        let (mut seq, mut qual) = (vec![0u8; read_len], vec![0u8; read_len]);
+        random_bases(&mut state, &mut seq, &mut qual);
+        writeln!(writer, "@synthetic-{}", record_number)?;
+        writer.write_all(&seq)?;
+        match splitmix64(&mut state) % 4 {
+            0 => writeln!(writer, "\n+synthetic-{}", record_number)?,
+            1 => writeln!(writer, "\n+comment {:x}", splitmix64(&mut state))?,
+            _ => writer.write_all(b"\n+\n")?,
+        }
+        writer.write_all(&qual)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Fills the sequence and qualities with random bases and Phred+33 qualities from 0 to 40
+fn random_bases(state: &mut u64, seq: &mut [u8], qual: &mut [u8]) {
+    for (base, quality) in seq.iter_mut().zip(qual.iter_mut()) {
+        let value = splitmix64(state);
+        *base = b"ACGT"[(value & 3) as usize];
+        *quality = b'!' + ((value >> 2) % 41) as u8;
+    }
+}
+
+/// The SplitMix64 pseudo-random number generator, which is enough for reproducible test data
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut value = *state;
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+/// A logger that captures log messages along with the thread that logged them, so that tests
+/// running in parallel only see their own messages.
+struct CaptureLogger {
+    messages: Mutex<Vec<(ThreadId, String)>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = (thread::current().id(), record.args().to_string());
+        self.messages.lock().unwrap().push(message);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger { messages: Mutex::new(Vec::new()) };
+
+static LOGGER_INIT: Once = Once::new();
+
+/// Installs the capturing logger.  Must be called before the code under test logs.
+pub fn capture_logs() {
+    LOGGER_INIT.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// Removes and returns the messages logged so far by the current thread.
+pub fn captured_logs() -> Vec<String> {
+    let current = thread::current().id();
+    let mut messages = LOGGER.messages.lock().unwrap();
+    let (mine, others): (Vec<_>, Vec<_>) =
+        messages.drain(..).partition(|(thread_id, _)| *thread_id == current);
+    *messages = others;
+    mine.into_iter().map(|(_, message)| message).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tools::fastq_index::FastqIndex;
+
+    use super::{write_synthetic_fastq, write_varied_synthetic_fastq};
+
+    #[test]
+    fn test_write_synthetic_fastq() {
+        let generate = |seed| {
+            let mut bytes: Vec<u8> = vec![];
+            write_synthetic_fastq(&mut bytes, 100, 50, seed).unwrap();
+            bytes
+        };
+        let fastq = generate(42);
+        assert_eq!(fastq, generate(42));
+        assert_ne!(fastq, generate(43));
+        assert!(fastq.starts_with(b"@synthetic-1\n"));
+
+        let index = FastqIndex::from_raw_reader(&fastq[..], 10, &mut None).unwrap();
+        assert_eq!(index.total_records, 100);
+        assert_eq!(index.entries.last().unwrap().total_bytes, fastq.len() as u64);
+        let records = seq_io::fastq::Reader::new(&fastq[..]).into_records();
+        assert_eq!(FastqIndex::from(records, 10, &mut None).unwrap().entries, index.entries);
+    }
+
+    #[test]
+    fn test_write_varied_synthetic_fastq() {
+        let generate = |seed| {
+            let mut bytes: Vec<u8> = vec![];
+            write_varied_synthetic_fastq(&mut bytes, 100, 50, seed).unwrap();
+            bytes
+        };
+        let fastq = generate(42);
+        assert_eq!(fastq, generate(42));
+        assert_ne!(fastq, generate(43));
+
+        // the reads vary in length, and some '+' lines have a comment
+        let records: Vec<_> = seq_io::fastq::Reader::new(&fastq[..])
+            .into_records()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 100);
+        assert!(records.iter().all(|rec| (1..=50).contains(&rec.seq.len())));
+        assert!(records.iter().any(|rec| rec.seq.len() != records[0].seq.len()));
+        let lines: Vec<&[u8]> = fastq.split(|&byte| byte == b'\n').collect();
+        assert!(lines.iter().skip(2).step_by(4).any(|line| line.len() > 1));
+        assert!(lines.iter().skip(2).step_by(4).any(|line| *line == b"+"));
+    }
+}
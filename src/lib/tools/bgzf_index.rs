@@ -1,33 +1,384 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::{fs::File, io::BufReader};
+use anyhow::{ensure, Context, Result};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use std::{
+    fs,
+    io::{self, Write},
+};
 
-use crate::utils::BUFFERSIZE;
+use crate::types::{CompressedOffset, UncompressedOffset};
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct BgzfIndex {
     pub num_entries: u64,
     pub entries: Vec<BgzfIndexOffset>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct BgzfIndexOffset {
-    pub compressed_offset: u64,
-    pub uncompressed_offset: u64,
+    pub compressed_offset: CompressedOffset,
+    pub uncompressed_offset: UncompressedOffset,
+}
+
+/// A BGZF block, with its sizes found from consecutive entries of the index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BgzfBlock {
+    pub compressed_offset: CompressedOffset,
+    pub compressed_size: u64,
+    pub uncompressed_offset: UncompressedOffset,
+    pub uncompressed_size: u64,
 }
 
 impl BgzfIndex {
-    pub fn from(gzi_index: String) -> BgzfIndex {
-        let mut reader = BufReader::with_capacity(BUFFERSIZE, File::open(gzi_index).unwrap());
+    /// Builds an index from the given entries, e.g. for offsets that were not read from a `.gzi`.
+    /// The entries must be in increasing order, and may start with the synthetic first entry (see
+    /// `from`).
+    pub fn new(entries: Vec<BgzfIndexOffset>) -> BgzfIndex {
+        BgzfIndex { num_entries: entries.len() as u64, entries }
+    }
+
+    /// Reads the BGZF index, prepending a synthetic entry for the first block (at offset zero),
+    /// which the `.gzi` does not store.
+    pub fn from(gzi_index: String) -> Result<BgzfIndex> {
+        BgzfIndex::from_with_options(gzi_index, true)
+    }
+
+    /// Reads the BGZF index, prepending a synthetic entry for the first block (at offset zero) if
+    /// `prepend_zero` is true.  Otherwise the entries are exactly those stored in the `.gzi`, and
+    /// the first block is implied.
+    ///
+    /// Two layouts of the `.gzi` are read.  Every htslib release that writes a `.gzi` (`bgzip -i`
+    /// or `-r`, and `bgzf_index_dump`) starts it with the number of entries (a little-endian `u64`)
+    /// followed by that many pairs of compressed and uncompressed offsets.  No htslib release
+    /// writes the pairs alone, but as a `.gzi` without the count may come from other tools, if the
+    /// size of the file does not match the entry count, the file is read as pairs until the end.
+    /// Errors if the file cannot be read, or is not a whole number of entries (e.g. if truncated).
+    pub fn from_with_options(gzi_index: String, prepend_zero: bool) -> Result<BgzfIndex> {
+        let bytes = fs::read(&gzi_index)
+            .with_context(|| format!("Could not read the BGZF index: {}", gzi_index))?;
 
-        let num_entries = reader.read_u64::<LittleEndian>().unwrap();
+        let pairs = match bytes.get(..8).map(LittleEndian::read_u64) {
+            Some(num_entries) if num_entries.checked_mul(16) == Some((bytes.len() - 8) as u64) => {
+                &bytes[8..]
+            }
+            _ => &bytes[..],
+        };
+        ensure!(
+            pairs.len().is_multiple_of(16),
+            "The BGZF index is not a whole number of entries, so may be truncated: {} ({} bytes)",
+            gzi_index,
+            bytes.len()
+        );
 
-        let mut entries = vec![BgzfIndexOffset { compressed_offset: 0, uncompressed_offset: 0 }];
-        for _ in 0..num_entries {
-            let compressed_offset = reader.read_u64::<LittleEndian>().unwrap();
-            let uncompressed_offset = reader.read_u64::<LittleEndian>().unwrap();
+        let mut entries = Vec::with_capacity(pairs.len() / 16 + 1);
+        if prepend_zero {
+            entries.push(BgzfIndexOffset::default());
+        }
+        for pair in pairs.chunks_exact(16) {
+            let compressed_offset = CompressedOffset(LittleEndian::read_u64(&pair[..8]));
+            let uncompressed_offset = UncompressedOffset(LittleEndian::read_u64(&pair[8..]));
             let entry = BgzfIndexOffset { compressed_offset, uncompressed_offset };
             entries.push(entry);
         }
 
-        BgzfIndex { num_entries: num_entries + 1, entries }
+        Ok(BgzfIndex::new(entries))
+    }
+
+    /// Writes the index in the layout written by `bgzip` (see `from_with_options`).  The synthetic
+    /// first entry, if any, is not written.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let entries: Vec<&BgzfIndexOffset> =
+            self.entries.iter().filter(|entry| entry.uncompressed_offset.0 > 0).collect();
+        writer.write_u64::<LittleEndian>(entries.len() as u64)?;
+        for entry in entries {
+            writer.write_u64::<LittleEndian>(entry.compressed_offset.0)?;
+            writer.write_u64::<LittleEndian>(entry.uncompressed_offset.0)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the entry for the block that contains the given uncompressed offset,
+    /// i.e. the last entry at or before the offset.  Returns zero if there is no such entry, so
+    /// use [`BgzfIndex::block_start`] when the index may lack the synthetic first entry.
+    pub fn entry_index(&self, uncompressed_offset: UncompressedOffset) -> usize {
+        self.entries
+            .partition_point(|entry| entry.uncompressed_offset <= uncompressed_offset)
+            .saturating_sub(1)
+    }
+
+    /// Returns the offsets of the start of the block that contains the given uncompressed offset.
+    /// This is the last entry at or before the offset, or the start of the file if there is none
+    /// (e.g. the first block when the synthetic first entry was not prepended).
+    pub fn block_start(&self, uncompressed_offset: UncompressedOffset) -> BgzfIndexOffset {
+        let num_before =
+            self.entries.partition_point(|entry| entry.uncompressed_offset <= uncompressed_offset);
+        if num_before == 0 {
+            BgzfIndexOffset::default()
+        } else {
+            self.entries[num_before - 1]
+        }
+    }
+
+    /// Returns the compressed offset of the first block, the compressed offset of the last block,
+    /// and the number of blocks that span the uncompressed bytes `[uncompressed_start,
+    /// uncompressed_end)`.  Returns `None` if the span is empty.
+    pub fn compressed_range(
+        &self,
+        uncompressed_start: UncompressedOffset,
+        uncompressed_end: UncompressedOffset,
+    ) -> Option<(CompressedOffset, CompressedOffset, usize)> {
+        if uncompressed_start >= uncompressed_end {
+            return None;
+        }
+        // Count the entries at or before each end, so the first block is handled the same whether
+        // or not it has an entry
+        let num_before = |offset: UncompressedOffset| {
+            self.entries.partition_point(|entry| entry.uncompressed_offset <= offset)
+        };
+        let num_blocks = num_before(uncompressed_end - 1) - num_before(uncompressed_start) + 1;
+        Some((
+            self.block_start(uncompressed_start).compressed_offset,
+            self.block_start(uncompressed_end - 1).compressed_offset,
+            num_blocks,
+        ))
+    }
+
+    /// Returns the blocks that start within the compressed bytes `[start, end)`, where the size
+    /// of each block is the difference between its entry and the next.  The last block has no
+    /// next entry, so it ends at `eof`: the compressed offset of the end-of-file block and the
+    /// total number of uncompressed bytes.  The first block is included whether or not the index
+    /// has the synthetic first entry.
+    pub fn blocks(
+        &self,
+        start: CompressedOffset,
+        end: CompressedOffset,
+        eof: BgzfIndexOffset,
+    ) -> Vec<BgzfBlock> {
+        let mut offsets: Vec<BgzfIndexOffset> = Vec::with_capacity(self.entries.len() + 2);
+        if self.entries.first().is_none_or(|entry| entry.compressed_offset.0 > 0) {
+            offsets.push(BgzfIndexOffset::default());
+        }
+        offsets.extend(&self.entries);
+        offsets.push(eof);
+        offsets
+            .windows(2)
+            .filter(|pair| start <= pair[0].compressed_offset && pair[0].compressed_offset < end)
+            .map(|pair| BgzfBlock {
+                compressed_offset: pair[0].compressed_offset,
+                compressed_size: pair[1].compressed_offset.0 - pair[0].compressed_offset.0,
+                uncompressed_offset: pair[0].uncompressed_offset,
+                uncompressed_size: pair[1].uncompressed_offset.0 - pair[0].uncompressed_offset.0,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use tempfile::TempDir;
+
+    use crate::types::{CompressedOffset, UncompressedOffset};
+
+    use super::{BgzfBlock, BgzfIndex, BgzfIndexOffset};
+
+    /// Builds an index with the synthetic first entry and blocks of 100 uncompressed bytes that
+    /// each compress to 40 bytes.
+    fn index(num_blocks: u64) -> BgzfIndex {
+        let entries: Vec<BgzfIndexOffset> = (0..num_blocks)
+            .map(|i| BgzfIndexOffset {
+                compressed_offset: CompressedOffset(i * 40),
+                uncompressed_offset: UncompressedOffset(i * 100),
+            })
+            .collect();
+        BgzfIndex::new(entries)
+    }
+
+    #[test]
+    fn test_new() {
+        // blocks of varying sizes, without the synthetic first entry
+        let entries: Vec<BgzfIndexOffset> = [(30, 100), (50, 250), (95, 300)]
+            .iter()
+            .map(|&(compressed_offset, uncompressed_offset)| BgzfIndexOffset {
+                compressed_offset: CompressedOffset(compressed_offset),
+                uncompressed_offset: UncompressedOffset(uncompressed_offset),
+            })
+            .collect();
+        let gzi = BgzfIndex::new(entries.clone());
+        assert_eq!(gzi.num_entries, 3);
+        assert_eq!(gzi.entries, entries);
+        assert_eq!(compressed_range(&gzi, 0, 100), Some((0, 0, 1)));
+        assert_eq!(compressed_range(&gzi, 99, 101), Some((0, 30, 2)));
+        assert_eq!(compressed_range(&gzi, 120, 260), Some((30, 50, 2)));
+        assert_eq!(compressed_range(&gzi, 0, 1000), Some((0, 95, 4)));
+        assert_eq!(compressed_range(&gzi, 300, 1000), Some((95, 95, 1)));
+
+        // the same with the synthetic first entry
+        let mut with_zero = vec![BgzfIndexOffset::default()];
+        with_zero.extend(entries);
+        let with_zero = BgzfIndex::new(with_zero);
+        for (start, end) in [(0, 100), (99, 101), (120, 260), (0, 1000), (300, 1000)] {
+            assert_eq!(
+                compressed_range(&with_zero, start, end),
+                compressed_range(&gzi, start, end)
+            );
+        }
+    }
+
+    #[test]
+    fn test_entry_index() {
+        let gzi = index(4);
+        assert_eq!(gzi.entry_index(UncompressedOffset(0)), 0);
+        assert_eq!(gzi.entry_index(UncompressedOffset(99)), 0);
+        assert_eq!(gzi.entry_index(UncompressedOffset(100)), 1);
+        assert_eq!(gzi.entry_index(UncompressedOffset(250)), 2);
+        assert_eq!(gzi.entry_index(UncompressedOffset(10_000)), 3);
+    }
+
+    /// Returns the compressed range of the uncompressed bytes `[start, end)` as plain offsets
+    fn compressed_range(gzi: &BgzfIndex, start: u64, end: u64) -> Option<(u64, u64, usize)> {
+        gzi.compressed_range(UncompressedOffset(start), UncompressedOffset(end))
+            .map(|(first, last, num_blocks)| (first.0, last.0, num_blocks))
+    }
+
+    #[test]
+    fn test_compressed_range() {
+        let gzi = index(4);
+        // starts mid-block and ends mid-block
+        assert_eq!(compressed_range(&gzi, 120, 250), Some((40, 80, 2)));
+        assert_eq!(compressed_range(&gzi, 150, 160), Some((40, 40, 1)));
+        // block boundaries: the end is exclusive
+        assert_eq!(compressed_range(&gzi, 0, 100), Some((0, 0, 1)));
+        assert_eq!(compressed_range(&gzi, 100, 201), Some((40, 80, 2)));
+        // into the last block
+        assert_eq!(compressed_range(&gzi, 50, 350), Some((0, 120, 4)));
+        // empty spans
+        assert_eq!(compressed_range(&gzi, 100, 100), None);
+        assert_eq!(compressed_range(&gzi, 200, 100), None);
+        // without any entries, everything is in the first block
+        assert_eq!(compressed_range(&index(0), 0, 100), Some((0, 0, 1)));
+    }
+
+    /// Returns the blocks that start within the compressed bytes `[start, end)` as plain offsets
+    /// and sizes, where the index ends at compressed offset 160 and uncompressed offset 400
+    fn blocks(gzi: &BgzfIndex, start: u64, end: u64) -> Vec<(u64, u64, u64, u64)> {
+        let eof = BgzfIndexOffset {
+            compressed_offset: CompressedOffset(160),
+            uncompressed_offset: UncompressedOffset(400),
+        };
+        gzi.blocks(CompressedOffset(start), CompressedOffset(end), eof)
+            .into_iter()
+            .map(|block: BgzfBlock| {
+                (
+                    block.compressed_offset.0,
+                    block.compressed_size,
+                    block.uncompressed_offset.0,
+                    block.uncompressed_size,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks() {
+        for gzi in [index(4), index_without_zero(4)] {
+            // the middle two blocks
+            assert_eq!(blocks(&gzi, 40, 81), vec![(40, 40, 100, 100), (80, 40, 200, 100)]);
+            // the first block, and the last block, which ends at the end-of-file block
+            assert_eq!(blocks(&gzi, 0, 1), vec![(0, 40, 0, 100)]);
+            assert_eq!(blocks(&gzi, 120, 160), vec![(120, 40, 300, 100)]);
+            assert_eq!(blocks(&gzi, 0, 160).len(), 4);
+            // no block starts within the span
+            assert_eq!(blocks(&gzi, 41, 80), vec![]);
+        }
+    }
+
+    /// Builds an index like [`index`] but without the synthetic first entry
+    fn index_without_zero(num_blocks: u64) -> BgzfIndex {
+        let mut gzi = index(num_blocks);
+        gzi.entries.remove(0);
+        gzi.num_entries -= 1;
+        gzi
+    }
+
+    #[test]
+    fn test_without_zero_entry() {
+        let gzi = index_without_zero(4);
+        let zero = BgzfIndexOffset::default();
+        assert_eq!(gzi.block_start(UncompressedOffset(0)), zero);
+        assert_eq!(gzi.block_start(UncompressedOffset(99)), zero);
+        assert_eq!(gzi.block_start(UncompressedOffset(100)), gzi.entries[0]);
+        assert_eq!(gzi.block_start(UncompressedOffset(250)), gzi.entries[1]);
+
+        // the same ranges as with the synthetic first entry
+        let with_zero = index(4);
+        for (start, end) in [(0, 50), (0, 100), (0, 150), (50, 350), (100, 201), (120, 250)] {
+            assert_eq!(
+                compressed_range(&gzi, start, end),
+                compressed_range(&with_zero, start, end),
+                "{}-{}",
+                start,
+                end
+            );
+            assert_eq!(
+                gzi.block_start(UncompressedOffset(start)),
+                with_zero.block_start(UncompressedOffset(start))
+            );
+        }
+    }
+
+    /// Writes the offsets as a `.gzi`, with or without the leading entry count, and reads it back
+    fn read_gzi(offsets: &[(u64, u64)], count_prefix: bool) -> BgzfIndex {
+        let mut bytes: Vec<u8> = vec![];
+        if count_prefix {
+            bytes.write_u64::<LittleEndian>(offsets.len() as u64).unwrap();
+        }
+        for (compressed_offset, uncompressed_offset) in offsets {
+            bytes.write_u64::<LittleEndian>(*compressed_offset).unwrap();
+            bytes.write_u64::<LittleEndian>(*uncompressed_offset).unwrap();
+        }
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.gzi");
+        std::fs::write(&path, bytes).unwrap();
+        BgzfIndex::from(path.to_string_lossy().to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_from_layouts() {
+        let expected = index(4);
+        let offsets: Vec<(u64, u64)> = expected.entries[1..]
+            .iter()
+            .map(|entry| (entry.compressed_offset.0, entry.uncompressed_offset.0))
+            .collect();
+        for count_prefix in [true, false] {
+            let gzi = read_gzi(&offsets, count_prefix);
+            assert_eq!(gzi.num_entries, expected.num_entries);
+            assert_eq!(gzi.entries, expected.entries);
+
+            let mut bytes: Vec<u8> = vec![];
+            gzi.write_to(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), 8 + 16 * offsets.len());
+            assert_eq!(&bytes[0..8], &(offsets.len() as u64).to_le_bytes());
+
+            let gzi = read_gzi(&[], count_prefix);
+            assert_eq!(gzi.num_entries, 1);
+            assert_eq!(gzi.entries, vec![BgzfIndexOffset::default()]);
+        }
+    }
+
+    #[test]
+    fn test_from_invalid() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.gzi");
+        let err = BgzfIndex::from(path.to_string_lossy().to_string()).unwrap_err();
+        assert!(err.to_string().starts_with("Could not read the BGZF index"), "{}", err);
+
+        // an entry truncated part way through its uncompressed offset
+        let mut bytes: Vec<u8> = vec![];
+        bytes.write_u64::<LittleEndian>(1).unwrap();
+        bytes.write_u64::<LittleEndian>(100).unwrap();
+        bytes.write_u32::<LittleEndian>(200).unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        let err = BgzfIndex::from(path.to_string_lossy().to_string()).unwrap_err();
+        assert!(err.to_string().contains("not a whole number of entries"), "{}", err);
     }
 }
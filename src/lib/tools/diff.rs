@@ -0,0 +1,153 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use env_logger::Env;
+use log::info;
+
+use crate::{tools::fastq_index::FastqIndex, utils::built_info};
+
+/// Compares two FASTQ indexes
+///
+/// Reports whether the nth, total number of records, and entries of the two indexes match.  On a
+/// mismatch, each difference is printed, including the first entry whose number of records or
+/// bytes differs, and the command exits with an error.
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The first FASTQ index file.
+    #[clap(short = 'a', long, display_order = 1)]
+    pub first: PathBuf,
+
+    /// The second FASTQ index file.
+    #[clap(short = 'b', long, display_order = 2)]
+    pub second: PathBuf,
+}
+
+// Run diff
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    let first = FastqIndex::read(&opts.first)?;
+    let second = FastqIndex::read(&opts.second)?;
+    let differences = diff(&first, &second);
+    if differences.is_empty() {
+        info!("The FASTQ indexes match: {} {}", opts.first.display(), opts.second.display());
+        return Ok(());
+    }
+    let mut writer = io::stdout().lock();
+    for difference in &differences {
+        writeln!(writer, "{}", difference)?;
+    }
+    writer.flush()?;
+    bail!("The FASTQ indexes differ: {} {}", opts.first.display(), opts.second.display())
+}
+
+/// Returns the differences between the two indexes, or an empty list if they match.  Entries are
+/// compared by their number of records and bytes, and only the first entry that differs is
+/// reported.
+pub fn diff(first: &FastqIndex, second: &FastqIndex) -> Vec<String> {
+    let mut differences: Vec<String> = vec![];
+    if first.nth != second.nth {
+        differences.push(format!("nth differs: {} vs {}", first.nth, second.nth));
+    }
+    if first.nth_bytes != second.nth_bytes {
+        let nth_bytes =
+            |index: &FastqIndex| index.nth_bytes.map_or("none".to_string(), |n| n.to_string());
+        differences.push(format!(
+            "nth-bytes differs: {} vs {}",
+            nth_bytes(first),
+            nth_bytes(second)
+        ));
+    }
+    if first.total_records != second.total_records {
+        differences.push(format!(
+            "The total number of records differs: {} vs {}",
+            first.total_records, second.total_records
+        ));
+    }
+    let entry = |index: &FastqIndex, i: usize| {
+        index.entries.get(i).map_or_else(
+            || "none".to_string(),
+            |entry| format!("({}, {})", entry.total_records, entry.total_bytes),
+        )
+    };
+    let num_entries = std::cmp::max(first.entries.len(), second.entries.len());
+    if let Some(i) = (0..num_entries).find(|i| entry(first, *i) != entry(second, *i)) {
+        differences.push(format!(
+            "Entry #{} differs (total records, total bytes): {} vs {}",
+            i + 1,
+            entry(first, i),
+            entry(second, i)
+        ));
+    }
+    if first.entries.len() != second.entries.len() {
+        differences.push(format!(
+            "The number of entries differs: {} vs {}",
+            first.entries.len(),
+            second.entries.len()
+        ));
+    }
+    differences
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{testutil::index, tools::fastq_index::FastqIndex};
+
+    use super::{diff, run, Opts};
+
+    #[test]
+    fn test_diff() {
+        let dir = TempDir::new().unwrap();
+        let first = dir.path().join("first.fqi");
+        let second = dir.path().join("second.fqi");
+        let opts = Opts { first: first.clone(), second: second.clone() };
+        let index = index(8);
+
+        // an index matches itself
+        index.clone().write(&first);
+        index.clone().write(&second);
+        assert!(diff(&index, &index).is_empty());
+        run(&opts).unwrap();
+
+        // an entry that differs is reported with both entries
+        let mut entries = index.entries.clone();
+        entries[2].total_bytes += 1;
+        let perturbed = FastqIndex { entries, ..index.clone() };
+        assert_eq!(
+            diff(&index, &perturbed),
+            vec!["Entry #3 differs (total records, total bytes): (6, 204) vs (6, 205)"]
+        );
+        perturbed.write(&second);
+        let err = run(&opts).unwrap_err();
+        assert!(err.to_string().starts_with("The FASTQ indexes differ"));
+
+        // as are the header and the number of entries
+        let mut entries = index.entries.clone();
+        entries.pop();
+        let truncated = FastqIndex { total_records: 6, nth: 2, entries, ..index.clone() };
+        assert_eq!(
+            diff(&index, &truncated),
+            vec![
+                "nth differs: 3 vs 2",
+                "The total number of records differs: 8 vs 6",
+                "Entry #4 differs (total records, total bytes): (8, 272) vs none",
+                "The number of entries differs: 4 vs 3",
+            ]
+        );
+    }
+}
@@ -1,256 +1,4761 @@
 use std::{
-    fs::File,
-    io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom},
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 
-use anyhow::{bail, ensure, Result};
-use clap::Parser;
+use anyhow::{bail, ensure, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use clap::{ArgAction, ArgGroup, Parser, ValueEnum};
 use env_logger::Env;
-use libdeflater::Decompressor;
-use seq_io::BaseRecord;
+use flate2::{write::GzEncoder, Compression};
+use libdeflater::{CompressionLvl, Compressor, Crc, Decompressor};
+use log::{debug, error, info, warn};
+use memmap2::Mmap;
+use seq_io::{
+    fastq::{OwnedRecord, Record, RefRecord},
+    BaseRecord,
+};
 
 use crate::{
     tools::{
         bgzf_index::{BgzfIndex, BgzfIndexOffset},
-        fastq_index::FastqIndex,
+        fastq_index::{
+            parse_error, CheckpointSpacing, FastqBaseRange, FastqIndex, FastqIndexRange,
+            IndexWriter, RawRecord, RecordIndex, UTF8_BOM,
+        },
+    },
+    types::{CompressedOffset, RecordNum, RecordPosition, UncompressedOffset},
+    utils::{
+        built_info, crc32_combine, read_fully, tmp_path, ByteCountingWriter, TrailingNewlineWriter,
+        BUFFERSIZE,
     },
-    utils::{built_info, BUFFERSIZE},
 };
 
 use bytes::BytesMut;
-use gzp::{deflate::Bgzf, BlockFormatSpec, FooterValues, FormatSpec, GzpError, BUFSIZE};
+use gzp::{deflate::Bgzf, BlockFormatSpec, FooterValues, FormatSpec, GzpError};
 
+/// The initial capacity of the buffer for a compressed block, being the largest block written by
+/// `bgzip`.  Other BGZF writers may write larger blocks, so the buffer is resized to the size in
+/// each block's header.  This is also the most uncompressed data `BgzfWriter` puts in a block, as
+/// `bgzip` does.
 const BGZF_BLOCK_SIZE: usize = 65280;
 
+/// The BGZF end-of-file marker block
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
 /// Finds the (uncompressed) offset and length to use for bgzip -b <OFFSET> -s <LENGTH>
 #[derive(Parser, Debug)]
 #[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+#[clap(allow_negative_numbers = true)]
+#[clap(group(ArgGroup::new("record-range").args(&["start", "end"]).multiple(true)))]
+#[clap(group(ArgGroup::new("byte-range").args(&["byte-start", "byte-end"]).multiple(true)))]
+#[clap(group(ArgGroup::new("base-range").args(&["base-start", "base-end"]).multiple(true)))]
 pub struct Opts {
     /// The input bgzip'ed FASTQ file.
     #[clap(short = 'f', long, display_order = 1)]
     pub input: PathBuf,
 
-    /// The first record to display (1-based).
-    #[clap(short = 's', long, display_order = 2)]
-    pub start: Option<u64>,
+    /// The first record to display, counting records (not bytes) from 1.  A negative record counts
+    /// back from the last record, so `-1` is the last record.
+    #[clap(short = 's', long, visible_alias = "start-record", display_order = 2)]
+    pub start: Option<i64>,
+
+    /// The last record to display, counting records (not bytes) from 1.  The range is inclusive,
+    /// so `-s 3 -e 5` displays three records, and `-s -5 -e -1` displays the last five records.
+    #[clap(short = 'e', long, visible_alias = "end-record", display_order = 2)]
+    pub end: Option<i64>,
+
+    /// A comma-separated list of records to display (1-based), or `@<path>` to read
+    /// newline-delimited record numbers from a file.  Records are output in file order, unless
+    /// --sort-output is false.
+    #[clap(long, display_order = 3, conflicts_with = "record-range")]
+    pub records: Option<String>,
+
+    /// Output the --records in file order (true), once each, or in the order given (false),
+    /// including any repeats.  The records are read in file order either way, so output in the
+    /// order given holds every requested record in memory until all have been read.
+    #[clap(
+        long,
+        display_order = 3,
+        default_value_t = true,
+        action = ArgAction::Set,
+        value_name = "BOOL"
+    )]
+    pub sort_output: bool,
+
+    /// Extract every Nth record in the range, i.e. records start, start + N, start + 2N, ... up to
+    /// end.  The default of 1 extracts every record.
+    #[clap(long, default_value = "1", display_order = 2, conflicts_with_all = &["records", "target-bases"])]
+    pub step: u64,
+
+    /// Extract approximately this many bases, starting at --start (default: 1).  Records are
+    /// output until the total number of bases first reaches or exceeds this value, so the last
+    /// record may cause more bases than requested to be output.
+    #[clap(long, display_order = 4, conflicts_with_all = &["end", "records"])]
+    pub target_bases: Option<u64>,
+
+    /// Extract the complete records within the uncompressed bytes starting at this 0-based offset,
+    /// without using the FASTQ index.  Requires --byte-end.  A partial record at either end of the
+    /// byte range is dropped.
+    #[clap(
+        long,
+        display_order = 5,
+        requires = "byte-end",
+        conflicts_with_all = &["record-range", "records", "target-bases"]
+    )]
+    pub byte_start: Option<u64>,
+
+    /// The end (0-based exclusive) of the uncompressed byte range to extract.  Requires
+    /// --byte-start.
+    #[clap(long, display_order = 5, requires = "byte-start")]
+    pub byte_end: Option<u64>,
+
+    /// Extract the records that contain any of the bases starting at this 0-based offset, counting
+    /// the bases of every record's sequence in file order.  Requires --base-end.  Whole records
+    /// are output, so the output may include bases before and after the base range.
+    #[clap(
+        long,
+        display_order = 5,
+        requires = "base-end",
+        conflicts_with_all = &["record-range", "records", "target-bases", "byte-range", "region"]
+    )]
+    pub base_start: Option<u64>,
+
+    /// The end (0-based exclusive) of the base range to extract.  Requires --base-start.
+    #[clap(long, display_order = 5, requires = "base-start")]
+    pub base_end: Option<u64>,
+
+    /// True to decompress the whole bgzip'ed FASTQ, like `bgzip -d`, without using the FASTQ index.
+    #[clap(
+        long,
+        display_order = 5,
+        conflicts_with_all = &[
+            "record-range", "records", "target-bases", "byte-range", "base-range", "region"
+        ]
+    )]
+    pub all: bool,
+
+    /// True to output the records as FASTA (`>name` followed by the sequence), dropping the
+    /// qualities.  The same as `--output-format fasta`.
+    #[clap(long, display_order = 6, conflicts_with_all = &["all", "byte-start"])]
+    pub fasta: bool,
+
+    /// The format of the output records: `fastq` writes each record as four lines, dropping any
+    /// comment on its '+' line, `fasta` as for --fasta, and `tsv` a tab-separated name, sequence,
+    /// and qualities per record (e.g. for loading into a data frame).
+    #[clap(long, value_enum, default_value = "fastq", display_order = 6)]
+    pub output_format: OutputFormat,
+
+    /// Wrap FASTA sequences to this many bases per line.  By default sequences are not wrapped.
+    /// Requires FASTA output.
+    #[clap(long, display_order = 6)]
+    pub wrap: Option<usize>,
+
+    /// True to copy the compressed BGZF blocks that span the records verbatim, followed by an
+    /// end-of-file block, rather than decompressing the records.  The output is bgzip'ed, and
+    /// includes the records before and after the requested records within the first and last
+    /// blocks.
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &[
+            "records", "target-bases", "byte-start", "all", "fasta", "step"
+        ]
+    )]
+    pub raw_blocks: bool,
+
+    /// True to output the number of uncompressed bytes that would be read (`num_bytes`) and the
+    /// number of records that would be output (`selected_records`) for the range given by --start
+    /// and --end, without extracting.  Only the FASTQ index is read.  The number of bytes is an
+    /// over-estimate of the output, as it includes the records before and after the range within
+    /// the first and last indexed chunks (see `index --nth`), so the size of the output is also
+    /// estimated from the mean record size (`output_bytes`).
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &["records", "target-bases", "byte-start", "all", "region", "raw-blocks"]
+    )]
+    pub estimate: bool,
+
+    /// True to output the BGZF blocks that would be read for the range given by --start and --end,
+    /// without extracting, as a tab-separated `compressed_offset`, `compressed_size`,
+    /// `uncompressed_offset`, and `uncompressed_size` per block, after a header line.  Only the
+    /// FASTQ and BGZF indexes are read.
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &[
+            "records", "target-bases", "byte-start", "all", "region", "raw-blocks", "estimate"
+        ]
+    )]
+    pub blocks: bool,
+
+    /// Write the 0-based offset in the output at which each extracted record starts to this file,
+    /// as a tab-separated `record_number` and `offset` per record, after a header line.
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &["all", "byte-start", "raw-blocks", "estimate", "blocks", "region"]
+    )]
+    pub offsets: Option<PathBuf>,
+
+    /// Write a summary of the extraction to this file once the records are written, as a
+    /// tab-separated name and value per line: the number of records (`records_written`) and
+    /// uncompressed bytes (`bytes_written`) written, and the uncompressed bytes of the source that
+    /// were read (`source_start_byte` and `source_end_byte`, or `NA` if none were read).
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &["all", "byte-start", "raw-blocks", "estimate", "blocks", "region"]
+    )]
+    pub stats: Option<PathBuf>,
+
+    /// True to write the record number (counting from 1) of each extracted record on its '+' line,
+    /// as `+record=<N>`, to trace records back to the input.  The sequence and qualities are
+    /// unchanged.
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &[
+            "all", "byte-start", "raw-blocks", "estimate", "blocks", "fasta"
+        ]
+    )]
+    pub annotate_record_number: bool,
+
+    /// True to omit the newline at the end of the last record, so that the output does not end
+    /// with a newline.  By default every record, including the last, ends with a newline.  An
+    /// empty output is empty either way.
+    #[clap(
+        long,
+        display_order = 6,
+        conflicts_with_all = &["all", "byte-start", "raw-blocks", "estimate", "blocks", "reindex"]
+    )]
+    pub no_trailing_newline: bool,
+
+    /// True to memory map the bgzip'ed FASTQ rather than reading it with file system calls.  The
+    /// file must not be modified while extracting.
+    #[clap(long, display_order = 6)]
+    pub mmap: bool,
+
+    /// True to skip checking the CRC32 of each decompressed BGZF block against its footer, which
+    /// is faster when the bgzip'ed FASTQ is trusted.  Corrupt blocks may then go undetected.
+    #[clap(long, display_order = 6)]
+    pub no_verify_crc: bool,
+
+    /// True to skip checking that the uncompressed bytes at the start of the records read from the
+    /// FASTQ index begin with '@', which catches a stale or misaligned index.  Every record is
+    /// still parsed, so must begin with '@'.
+    #[clap(long, display_order = 6)]
+    pub no_sanity_check: bool,
+
+    /// True to fail when a requested record is past the last record in the FASTQ, rather than
+    /// clamping the range to the last record (or skipping the record, for --records).
+    #[clap(long, display_order = 7, conflicts_with_all = &["all", "byte-range", "base-range"])]
+    pub strict_bounds: bool,
+
+    /// Fail before extracting if more than this many records would be extracted by --start and
+    /// --end (every --step record), or by every --region together, e.g. to guard against
+    /// accidentally extracting a whole large FASTQ.  The number of records is found from the FASTQ
+    /// index alone, so cannot be used with --all or --byte-start/--byte-end, which bypass it.  See
+    /// --yes.
+    #[clap(long, display_order = 7, conflicts_with_all = &["all", "byte-range"])]
+    pub max_output_records: Option<u64>,
+
+    /// True to warn, rather than fail, when more records would be extracted than
+    /// --max-output-records, e.g. once the number of records has been confirmed.
+    #[clap(long, display_order = 7, requires = "max-output-records")]
+    pub yes: bool,
+
+    /// True to fail, rather than warn, when the bgzip'ed FASTQ does not match the CRC32 of the
+    /// source FASTQ stored in the index (see `index --checksum`).  Implies --verify-source.
+    #[clap(long, display_order = 7)]
+    pub strict: bool,
+
+    /// True to check, before extracting, that the bgzip'ed FASTQ matches the CRC32 of the source
+    /// FASTQ stored in the index (see `index --checksum`), warning if not (see --strict).  The
+    /// check reads the footer of every BGZF block, rather than only the blocks spanning the
+    /// records, so is slow for a large FASTQ; `fqme verify` performs the same check.
+    #[clap(long, display_order = 7)]
+    pub verify_source: bool,
+
+    /// True to log the resolved uncompressed byte range and BGZF blocks to stderr.
+    #[clap(short = 'v', long, display_order = 8)]
+    pub verbose: bool,
+
+    /// A range of records to extract to the corresponding --output file, either `<start>-<end>`
+    /// (1-based inclusive) or a single record.  May be given multiple times, in which case the
+    /// regions are extracted in parallel (see --threads).
+    #[clap(
+        long,
+        display_order = 9,
+        multiple_occurrences = true,
+        requires = "output",
+        conflicts_with_all = &["record-range", "records", "target-bases", "byte-range"]
+    )]
+    pub region: Vec<String>,
+
+    /// The output file for each --region, given in the same order as the regions.  Without
+    /// --region, the output file for the extracted records (by default, standard output), which is
+    /// gzip-compressed if it ends in `.gz` or `.bgz`.  Each output is written to a temporary file
+    /// and renamed once complete.  Plain gzip cannot be extracted from, so use --compress for a
+    /// bgzip'ed (BGZF) output that can be indexed.
+    #[clap(long, display_order = 9, multiple_occurrences = true)]
+    pub output: Vec<PathBuf>,
+
+    /// True to BGZF compress the output, like `bgzip`.  With --region, each region's output is
+    /// compressed.
+    #[clap(long, display_order = 10, conflicts_with_all = &["raw-blocks", "estimate", "blocks"])]
+    pub compress: bool,
+
+    /// True to also write a FASTQ index (`.fqi`) and BGZF index (`.gzi`) alongside the --output,
+    /// so that records may be extracted from the output.  Requires --compress and --output.  With
+    /// --region, the indexes are written alongside each region's output, so that each may be
+    /// extracted from independently.  The FASTQ index entries are spaced as in the input's index
+    /// (see `index --nth` and `--nth-bytes`).
+    #[clap(
+        long,
+        display_order = 10,
+        requires_all = &["compress", "output"],
+        conflicts_with_all = &["all", "byte-range", "fasta"]
+    )]
+    pub reindex: bool,
+
+    /// The maximum number of regions to extract concurrently.
+    #[clap(long, default_value = "4", display_order = 11)]
+    pub threads: usize,
+
+    /// True to log each --region that fails (e.g. an invalid region, or an output that cannot be
+    /// created) and continue extracting the other regions, rather than stopping at the first
+    /// failure.  Exits with an error if any region failed.
+    #[clap(long, display_order = 11, requires = "region")]
+    pub keep_going: bool,
+
+    /// True to output the plan for extracting each --region, without extracting, as a
+    /// tab-separated `start_record`, `end_record`, `start_byte`, `end_byte`, and `output` per
+    /// region, after a header line.  The last record is clamped to the last in the FASTQ, and the
+    /// bytes are the uncompressed bytes that would be read (see --estimate), or `NA` if the region
+    /// has no records.  Only the FASTQ index is read.
+    #[clap(long, display_order = 11, requires = "region")]
+    pub plan: bool,
+
+    /// True to skip each --region whose output already exists, e.g. to continue extracting after
+    /// an interruption.  Each region is written to a temporary file that is renamed over its
+    /// output once the region is extracted, so an output that exists is complete.
+    #[clap(long, display_order = 11, requires = "region")]
+    pub resume: bool,
+}
+
+/// The configuration for extracting records, which may be built without the command line options.
+/// Each field corresponds to the option of the same (or similar) name in [`Opts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractConfig {
+    /// The input bgzip'ed FASTQ file, alongside which the indexes are found
+    pub input: PathBuf,
+    /// The first record to extract, which may count back from the last record
+    pub start: Option<RecordPosition>,
+    /// The last record to extract (inclusive), which may count back from the last record
+    pub end: Option<RecordPosition>,
+    /// Extract every Nth record in the range
+    pub step: u64,
+    /// The records to extract
+    pub records: Option<Vec<RecordNum>>,
+    /// Output the records in file order, rather than in the order given
+    pub sort_output: bool,
+    /// Extract approximately this many bases, starting at `start`
+    pub target_bases: Option<u64>,
+    /// Extract the complete records within these uncompressed bytes (end exclusive)
+    pub byte_range: Option<(UncompressedOffset, UncompressedOffset)>,
+    /// Extract the records that contain any of these bases (end exclusive)
+    pub base_range: Option<(u64, u64)>,
+    /// Decompress the whole bgzip'ed FASTQ
+    pub all: bool,
+    /// The format in which the records are written
+    pub format: RecordFormat,
+    /// Wrap FASTA sequences to this many bases per line
+    pub wrap: Option<usize>,
+    /// Copy the compressed BGZF blocks that span the records verbatim
+    pub raw_blocks: bool,
+    /// Only estimate the size of the range from the FASTQ index
+    pub estimate: bool,
+    /// Only output the BGZF blocks that span the range
+    pub blocks: bool,
+    /// Write the offset in the output at which each record starts to this file
+    pub offsets: Option<PathBuf>,
+    /// Write a summary of the records and bytes written to this file
+    pub stats: Option<PathBuf>,
+    /// Write the record number of each record on its `+` line
+    pub annotate_record_number: bool,
+    /// End the last record with a newline
+    pub trailing_newline: bool,
+    /// Memory map the bgzip'ed FASTQ
+    pub mmap: bool,
+    /// Check the CRC32 of each decompressed BGZF block
+    pub verify_crc: bool,
+    /// Check that the records read from the FASTQ index start at a record boundary
+    pub sanity_check: bool,
+    /// Fail when a requested record is past the last record, rather than clamping
+    pub strict_bounds: bool,
+    /// Fail if more than this many records would be extracted from a range or regions
+    pub max_output_records: Option<u64>,
+    /// Warn, rather than fail, when more than `max_output_records` would be extracted
+    pub yes: bool,
+    /// Fail, rather than warn, when the bgzip'ed FASTQ does not match its index
+    pub strict: bool,
+    /// Check that the bgzip'ed FASTQ matches its index before extracting
+    pub verify_source: bool,
+    /// Log the resolved byte range and BGZF blocks
+    pub verbose: bool,
+    /// Ranges of records to extract (1-based inclusive), each to the output at the same position
+    /// in `outputs`
+    pub regions: Vec<(RecordNum, RecordNum)>,
+    /// The output file for each region, or without regions, the output to which `run` writes
+    pub outputs: Vec<PathBuf>,
+    /// BGZF compress the output
+    pub compress: bool,
+    /// Write a FASTQ index and BGZF index alongside the (single, compressed) output
+    pub reindex: bool,
+    /// The maximum number of regions to extract concurrently
+    pub threads: usize,
+    /// Continue extracting the other regions when a region fails
+    pub keep_going: bool,
+    /// Only output the records and bytes of each region
+    pub plan: bool,
+    /// Skip the regions whose output already exists
+    pub resume: bool,
+}
+
+impl Default for ExtractConfig {
+    fn default() -> Self {
+        ExtractConfig {
+            input: PathBuf::new(),
+            start: None,
+            end: None,
+            step: 1,
+            records: None,
+            sort_output: true,
+            target_bases: None,
+            byte_range: None,
+            base_range: None,
+            all: false,
+            format: RecordFormat::Fastq,
+            wrap: None,
+            raw_blocks: false,
+            estimate: false,
+            blocks: false,
+            offsets: None,
+            stats: None,
+            annotate_record_number: false,
+            trailing_newline: true,
+            mmap: false,
+            verify_crc: true,
+            sanity_check: true,
+            strict_bounds: false,
+            max_output_records: None,
+            yes: false,
+            strict: false,
+            verify_source: false,
+            verbose: false,
+            regions: vec![],
+            outputs: vec![],
+            compress: false,
+            reindex: false,
+            threads: 4,
+            keep_going: false,
+            plan: false,
+            resume: false,
+        }
+    }
+}
+
+/// Builds the configuration from the command line options, parsing the --records and --region
+/// values.
+impl TryFrom<&Opts> for ExtractConfig {
+    type Error = anyhow::Error;
 
-    /// The last record to display (1-based inclusive).
-    #[clap(short = 'e', long, display_order = 2)]
-    pub end: Option<u64>,
+    fn try_from(opts: &Opts) -> Result<Self> {
+        let byte_range = match (opts.byte_start, opts.byte_end) {
+            (Some(byte_start), Some(byte_end)) => {
+                Some((UncompressedOffset(byte_start), UncompressedOffset(byte_end)))
+            }
+            _ => None,
+        };
+        let base_range = match (opts.base_start, opts.base_end) {
+            (Some(base_start), Some(base_end)) => Some((base_start, base_end)),
+            _ => None,
+        };
+        // --output-format defaults to FASTQ, so only an explicit TSV disagrees with --fasta
+        let format = match (opts.fasta, opts.output_format) {
+            (true, OutputFormat::Tsv) => bail!("--fasta cannot be used with --output-format tsv"),
+            (true, _) | (_, OutputFormat::Fasta) => RecordFormat::Fasta,
+            (false, OutputFormat::Fastq) => RecordFormat::Fastq,
+            (false, OutputFormat::Tsv) => RecordFormat::Tsv,
+        };
+        let records = opts.records.as_deref().map(parse_records).transpose()?;
+        let regions =
+            opts.region.iter().map(|region| parse_region(region)).collect::<Result<_>>()?;
+        Ok(ExtractConfig {
+            input: opts.input.clone(),
+            start: opts.start.map(RecordPosition::from),
+            end: opts.end.map(RecordPosition::from),
+            step: opts.step,
+            records,
+            sort_output: opts.sort_output,
+            target_bases: opts.target_bases,
+            byte_range,
+            base_range,
+            all: opts.all,
+            format,
+            wrap: opts.wrap,
+            raw_blocks: opts.raw_blocks,
+            estimate: opts.estimate,
+            blocks: opts.blocks,
+            offsets: opts.offsets.clone(),
+            stats: opts.stats.clone(),
+            annotate_record_number: opts.annotate_record_number,
+            trailing_newline: !opts.no_trailing_newline,
+            mmap: opts.mmap,
+            verify_crc: !opts.no_verify_crc,
+            sanity_check: !opts.no_sanity_check,
+            strict_bounds: opts.strict_bounds,
+            max_output_records: opts.max_output_records,
+            yes: opts.yes,
+            strict: opts.strict,
+            verify_source: opts.verify_source,
+            verbose: opts.verbose,
+            regions,
+            outputs: opts.output.clone(),
+            compress: opts.compress,
+            reindex: opts.reindex,
+            threads: opts.threads,
+            keep_going: opts.keep_going,
+            plan: opts.plan,
+            resume: opts.resume,
+        })
+    }
 }
 
 // Run extract
-#[allow(clippy::too_many_lines)]
 pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
-    let (start, end) = match (opts.start, opts.end) {
-        (Some(s), Some(e)) => (s, e),
-        (Some(s), None) => (s, s),
-        (None, Some(e)) => (e, e),
-        (None, None) => bail!("Either --start or --end must be given"),
-    };
-    ensure!(start <= end, "--start must be less than or equal to --end");
+    let config = ExtractConfig::try_from(opts)?;
+    ensure!(
+        !config.regions.is_empty() || config.outputs.len() <= 1,
+        "--output may only be given once without --region ({} outputs)",
+        config.outputs.len()
+    );
+    match (config.regions.is_empty(), config.outputs.as_slice()) {
+        // The output is written to a temporary file that is renamed over it once complete
+        (true, [output]) => {
+            let tmp_path = tmp_path(output);
+            let mut writer = OutputWriter::create(&tmp_path, gzip_output(&config, output))?;
+            let result = extract(&config, &mut writer).and_then(|()| Ok(writer.finish()?));
+            finish_output(output, &tmp_path, result)
+        }
+        _ => {
+            let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
+            extract(&config, &mut writer)?;
+            writer.flush()?;
+            Ok(())
+        }
+    }
+}
 
-    // Create the file names
-    let fqi_path = format!("{}.{}", opts.input.to_string_lossy(), "fqi");
-    let gzi_path = format!("{}.{}", opts.input.to_string_lossy(), "gzi");
+/// True if the given output is gzip-compressed, which it is if its extension is `.gz` or `.bgz`,
+/// unless it is BGZF compressed as it is extracted (`compress`), whatever its extension
+fn gzip_output(config: &ExtractConfig, output: &Path) -> bool {
+    let extension = output.extension().and_then(|extension| extension.to_str());
+    !config.compress && matches!(extension, Some("gz" | "bgz"))
+}
 
-    // Read the FASTQ index
-    let fastq_index = FastqIndex::read(Path::new(&fqi_path));
-    let fqi_range = match fastq_index.range(start, end) {
-        Some(range) => range,
-        None => return Ok(()),
-    };
-    // println!("{:?}", fqi_range);
-    // println!(
-    //     "The following command will output {} leading and {} trailing records:",
-    //     fqi_range.leading_records, fqi_range.trailing_records
-    // );
-    // println!("    bgzip -b {} -s {} {:?}", fqi_range.start_byte, fqi_range.num_bytes(), opts.input);
-
-    // Read the BGZF index and find the compressed offset
-    let gzi = BgzfIndex::from(gzi_path);
-    let mut start_entry: BgzfIndexOffset = gzi.entries[0];
-    let mut num_blocks: usize = 0;
-    for entry in gzi.entries {
-        if entry.uncompressed_offset < fqi_range.start_byte {
-            start_entry = entry;
-            num_blocks = 0;
-        }
-        num_blocks += 1;
-        if entry.uncompressed_offset >= fqi_range.end_byte {
-            break;
-        }
+/// A writer to an output file, which may be gzip-compressed.  Call `finish` once the records are
+/// written, so that an error writing the end of the output (e.g. the gzip trailer) is returned
+/// rather than lost when the writer is dropped.
+enum OutputWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl OutputWriter {
+    /// Creates the output file at the given path, gzip-compressing it if `gzip` is true
+    fn create(path: &Path, gzip: bool) -> Result<OutputWriter> {
+        let file =
+            File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+        let writer = BufWriter::with_capacity(BUFFERSIZE, file);
+        Ok(if gzip {
+            OutputWriter::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            OutputWriter::Plain(writer)
+        })
     }
 
-    // Build a BgzfReader starting at the next FASTQ record
-    let file = File::open(opts.input.clone()).unwrap();
-    let bgzf_reader: BgzfReader =
-        BgzfReader::new(file, fqi_range.start_byte, start_entry, num_blocks);
+    /// Writes any buffered bytes, and the gzip trailer if the output is gzip-compressed
+    fn finish(self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(mut writer) => writer.flush(),
+            OutputWriter::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
 
-    // Write the FASTQ entries
-    let reader = seq_io::fastq::Reader::new(bgzf_reader);
-    let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
-    let mut num_to_write: u64 = end - start + 1;
-    for (index, result) in reader.into_records().enumerate() {
-        let rec = result?;
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Plain(writer) => writer.write(buf),
+            OutputWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
 
-        if index as u64 >= fqi_range.leading_records {
-            rec.write(&mut writer)?;
-            num_to_write -= 1;
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(writer) => writer.flush(),
+            OutputWriter::Gzip(encoder) => encoder.flush(),
         }
-        if num_to_write == 0 {
-            break;
+    }
+}
+
+/// Renames the temporary file to which an output was written over the output if it was extracted
+/// (per `result`), and otherwise removes it, so that a partially written output is never left at
+/// its path.
+fn finish_output(output: &Path, tmp_path: &Path, result: Result<()>) -> Result<()> {
+    if result.is_err() {
+        let _ignored = fs::remove_file(tmp_path);
+    }
+    result?;
+    fs::rename(tmp_path, output)
+        .with_context(|| format!("Could not rename {} to {}", tmp_path.display(), output.display()))
+}
+
+/// Extracts the requested records, writing them to the given writer.  With `compress`, the output
+/// is BGZF compressed, and with `reindex`, its indexes are written alongside the (single) output.
+/// With regions, each region is written to its own output, which is compressed and indexed in the
+/// same way.
+pub fn extract<W: Write>(config: &ExtractConfig, writer: &mut W) -> Result<(), anyhow::Error> {
+    if config.reindex {
+        ensure!(
+            config.compress && (!config.regions.is_empty() || config.outputs.len() == 1),
+            "--reindex requires --compress and a single --output, or an --output per --region"
+        );
+        ensure!(
+            !(config.all || config.byte_range.is_some() || config.raw_blocks || config.estimate),
+            "--reindex cannot be used with --all, --byte-start, --raw-blocks, or --estimate"
+        );
+        ensure!(config.format == RecordFormat::Fastq, "--reindex requires FASTQ output");
+        ensure!(config.trailing_newline, "--reindex cannot be used with --no-trailing-newline");
+    }
+    if !config.verify_crc {
+        warn!("Not checking the CRC32 of the BGZF blocks (--no-verify-crc)");
+    }
+    // Each region's output is compressed separately
+    if !config.compress || !config.regions.is_empty() {
+        return extract_uncompressed(config, writer);
+    }
+
+    let mut bgzf_writer = BgzfWriter::new(writer);
+    let result = extract_uncompressed(config, &mut bgzf_writer);
+    if !config.reindex {
+        result?;
+        bgzf_writer.finish()?;
+        return Ok(());
+    }
+    // The FASTQ index was written as the records were extracted
+    let output = &config.outputs[0];
+    let result = result
+        .and_then(|()| Ok(bgzf_writer.finish()?))
+        .and_then(|(_, gzi)| write_gzi(output, &gzi));
+    finish_indexes(output, result)
+}
+
+/// Returns the paths of the FASTQ index and BGZF index written alongside an output, for
+/// `--reindex`
+fn index_paths(output: &Path) -> [PathBuf; 2] {
+    ["fqi", "gzi"]
+        .map(|extension| PathBuf::from(format!("{}.{}", output.to_string_lossy(), extension)))
+}
+
+/// Writes the BGZF index of a compressed output to a temporary file alongside it, for `--reindex`
+/// (see `finish_indexes`)
+fn write_gzi(output: &Path, gzi: &BgzfIndex) -> Result<()> {
+    let [_, gzi_path] = index_paths(output);
+    let tmp_path = tmp_path(&gzi_path);
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("Could not create {}", tmp_path.display()))?;
+    let mut gzi_writer = BufWriter::with_capacity(BUFFERSIZE, file);
+    gzi.write_to(&mut gzi_writer)?;
+    gzi_writer.flush()?;
+    Ok(())
+}
+
+/// Renames the indexes of an output, which are written to temporary files as it is extracted,
+/// over their final paths if the output was extracted (per `result`), and otherwise removes them,
+/// so that the indexes are never left without their output.
+fn finish_indexes(output: &Path, result: Result<()>) -> Result<()> {
+    for path in index_paths(output) {
+        let tmp_path = tmp_path(&path);
+        if result.is_err() {
+            let _ignored = fs::remove_file(&tmp_path);
+            continue;
         }
+        fs::rename(&tmp_path, &path).with_context(|| {
+            format!("Could not rename {} to {}", tmp_path.display(), path.display())
+        })?;
+    }
+    result
+}
+
+/// Extracts the requested records, writing them uncompressed to the given writer
+fn extract_uncompressed<W: Write>(
+    config: &ExtractConfig,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    check_bgzf(&config.input)?;
+    check_indexes(config)?;
+
+    // Read the BGZF index
+    let gzi_path = format!("{}.{}", config.input.to_string_lossy(), "gzi");
+    let gzi = BgzfIndex::from(gzi_path)?;
+
+    let file = File::open(&config.input)
+        .with_context(|| format!("Could not open {}", config.input.display()))?;
+    if config.mmap {
+        // SAFETY: the mapping is only read, and the file must not be modified while it is mapped
+        #[allow(unsafe_code)]
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Could not memory map {}", config.input.display()))?;
+        extract_from(config, || Ok(Cursor::new(&mmap[..])), &gzi, writer)
+    } else {
+        // Open the file for each reader, as cloned file handles share their offset
+        extract_from(config, || File::open(&config.input), &gzi, writer)
     }
+}
 
+/// Checks that the input is BGZF compressed, being gzip with the `BC` extra subfield that `bgzip`
+/// writes to each block, so that a plain or gzip-compressed FASTQ is reported with how to
+/// compress it, rather than failing when the first block is read.
+fn check_bgzf(input: &Path) -> Result<()> {
+    let mut file =
+        File::open(input).with_context(|| format!("Could not open {}", input.display()))?;
+    let mut header = [0u8; 12];
+    let num_read = read_fully(&mut file, &mut header)?;
+    ensure!(
+        2 <= num_read && header[0..2] == [0x1f, 0x8b],
+        "The input is not gzip-compressed: {}; compress it with `bgzip` (see `fqme index --help`)",
+        input.display()
+    );
+
+    // The extra field (FEXTRA) must be present and contain the BGZF block size subfield
+    let is_bgzf = num_read == header.len() && header[3] & 4 != 0 && {
+        let mut extra = vec![0u8; LittleEndian::read_u16(&header[10..12]) as usize];
+        read_fully(&mut file, &mut extra)? == extra.len() && has_bgzf_subfield(&extra)
+    };
+    ensure!(
+        is_bgzf,
+        "The input is gzip-compressed but not BGZF, so records cannot be found: {}; re-compress it \
+         with `bgzip` (e.g. `gzip -dc {} | bgzip`)",
+        input.display(),
+        input.display()
+    );
     Ok(())
 }
 
-pub struct BgzfReader {
-    reader: File,
-    bgzf: Bgzf,
-    header_buf: Vec<u8>,
-    compressed_buffer: BytesMut,
-    uncompressed_buffer: BytesMut,
-    decompressor: Decompressor,
-    uncompressed_data: Vec<u8>,
-    uncompressed_data_index: usize,
-    num_blocks_left: usize,
+/// Returns true if the gzip extra field contains the BGZF block size subfield (`BC`)
+fn has_bgzf_subfield(mut extra: &[u8]) -> bool {
+    while 4 <= extra.len() {
+        if extra[0..2] == *b"BC" {
+            return true;
+        }
+        let length = 4 + LittleEndian::read_u16(&extra[2..4]) as usize;
+        extra = &extra[length.min(extra.len())..];
+    }
+    false
 }
 
-impl BgzfReader {
-    fn new(mut reader: File, start_byte: u64, entry: BgzfIndexOffset, num_blocks: usize) -> Self {
-        let bgzf = Bgzf::new();
-        let header_buf = vec![0; Bgzf::HEADER_SIZE];
-        let compressed_buffer = BytesMut::with_capacity(BGZF_BLOCK_SIZE);
-        let uncompressed_buffer = BytesMut::with_capacity(BUFSIZE);
-        let decompressor = libdeflater::Decompressor::new();
-        let uncompressed_data: Vec<u8> = vec![];
+/// Checks that the BGZF index, and the FASTQ index if it will be used, exist alongside the input,
+/// so that a missing index is reported with how to create it.
+fn check_indexes(config: &ExtractConfig) -> Result<()> {
+    let gzi_path = PathBuf::from(format!("{}.{}", config.input.to_string_lossy(), "gzi"));
+    ensure!(
+        gzi_path.exists(),
+        "BGZF index not found: {}; create it with `bgzip -r {}`",
+        gzi_path.display(),
+        config.input.display()
+    );
 
-        reader.seek(SeekFrom::Start(entry.compressed_offset)).unwrap();
+    // Decompressing the whole FASTQ or a byte range does not use the FASTQ index
+    if config.all || config.byte_range.is_some() {
+        return Ok(());
+    }
+    let fqi_path = PathBuf::from(format!("{}.{}", config.input.to_string_lossy(), "fqi"));
+    ensure!(
+        fqi_path.exists(),
+        "FASTQ index not found: {}; create it with `fqme index --output {}` while compressing \
+         the FASTQ (see `fqme index --help`)",
+        fqi_path.display(),
+        fqi_path.display()
+    );
+    Ok(())
+}
 
-        let mut bgzf_reader = BgzfReader {
-            reader,
-            bgzf,
-            header_buf,
-            compressed_buffer,
-            uncompressed_buffer,
-            decompressor,
-            uncompressed_data,
-            uncompressed_data_index: 0,
-            num_blocks_left: num_blocks,
-        };
+/// Extracts the requested records from the bgzip'ed FASTQ source, writing them to the given
+/// writer.  Each call to `open` returns a new reader over the source, so that regions may be
+/// extracted in parallel.
+#[allow(clippy::too_many_lines)]
+fn extract_from<R, F, W>(
+    config: &ExtractConfig,
+    open: F,
+    gzi: &BgzfIndex,
+    writer: &mut W,
+) -> Result<(), anyhow::Error>
+where
+    R: Read + Seek,
+    F: Fn() -> io::Result<R> + Sync,
+    W: Write,
+{
+    ensure!(config.wrap != Some(0), "--wrap must be greater than zero");
+    ensure!(
+        config.wrap.is_none() || config.format == RecordFormat::Fasta,
+        "--wrap requires FASTA output (--fasta or --output-format fasta)"
+    );
+    ensure!(
+        config.format == RecordFormat::Fastq
+            || !(config.all || config.byte_range.is_some() || config.raw_blocks),
+        "--all, --byte-start/--byte-end, and --raw-blocks copy the FASTQ as is, so require FASTQ \
+         output"
+    );
+    ensure!(
+        !config.annotate_record_number || config.format == RecordFormat::Fastq,
+        "--annotate-record-number requires FASTQ output"
+    );
+    ensure!(
+        config.max_output_records.is_none() || !(config.all || config.byte_range.is_some()),
+        "--max-output-records cannot be used with --all or --byte-start/--byte-end, as they do not \
+         use the FASTQ index to count the records"
+    );
+    let mut source = open()?;
 
-        // move to the start uncompressed byte offset
-        let mut cur_uncompressed_offset = entry.uncompressed_offset;
-        while cur_uncompressed_offset < start_byte {
-            // fill the data, stop when we have no more data
-            if bgzf_reader.bytes_available() == 0 && bgzf_reader.fill().unwrap() == 0 {
-                break;
-            }
-            cur_uncompressed_offset += 1;
-            bgzf_reader.uncompressed_data_index += 1;
+    // Decompress every block, bypassing the FASTQ index
+    if config.all {
+        let mut bgzf_reader = BgzfReader::with_verify_crc(
+            source,
+            UncompressedOffset(0),
+            gzi.block_start(UncompressedOffset(0)),
+            UncompressedOffset(u64::MAX),
+            config.verify_crc,
+        )?;
+        io::copy(&mut bgzf_reader, writer)?;
+        return Ok(());
+    }
+
+    // Extract an uncompressed byte range, bypassing the FASTQ index
+    if let Some((byte_start, byte_end)) = config.byte_range {
+        ensure!(byte_start < byte_end, "--byte-start must be less than --byte-end");
+        return write_byte_range(source, gzi, byte_start, byte_end, config.verify_crc, writer);
+    }
+
+    // Open the FASTQ index, which is memory mapped so that only the entries needed to find the
+    // records are read, rather than every entry
+    let fqi_path = format!("{}.{}", config.input.to_string_lossy(), "fqi");
+    let fastq_index = FastqIndex::open(Path::new(&fqi_path))?;
+    let fastq_index = fastq_index.as_ref();
+    if fastq_index.estimated_bytes() {
+        warn!(
+            "This index has estimated byte offsets, which may be inaccurate for files with '+' \
+             comments; re-index with `fqme index --raw`: {}",
+            fqi_path
+        );
+    }
+
+    // Estimate the size of the range from the FASTQ index alone
+    if config.estimate {
+        let (start, end) = start_and_end(config, fastq_index.total_records())?;
+        return write_estimate(fastq_index, start, end, config.step, writer);
+    }
+
+    // List the BGZF blocks that span the range from the indexes alone
+    if config.blocks {
+        let (start, end) = start_and_end(config, fastq_index.total_records())?;
+        return write_blocks(source, gzi, fastq_index, start, end, writer);
+    }
+
+    // Plan the regions from the FASTQ index alone
+    if config.plan {
+        return write_plan(config, fastq_index, writer);
+    }
+
+    // Check the number of records to extract before reading the bgzip'ed FASTQ
+    check_max_output_records(config, fastq_index)?;
+
+    // Check that the bgzip'ed FASTQ has not changed since it was indexed, only when asked, as every
+    // block is read
+    let verify_source = config.verify_source || config.strict;
+    if let Some(expected_crc32) = fastq_index.source_crc32().filter(|_| verify_source) {
+        let (crc32, num_bytes) = bgzf_crc32(&mut source)?;
+        let total_bytes = fastq_index.total_bytes();
+        if crc32 != expected_crc32 || num_bytes != total_bytes {
+            let message = format!(
+                "The bgzip'ed FASTQ does not match its index (CRC32 {:08x} over {} bytes, but the \
+                 index has CRC32 {:08x} over {} bytes); the index may be stale: {}",
+                crc32,
+                num_bytes,
+                expected_crc32,
+                total_bytes,
+                config.input.display()
+            );
+            ensure!(!config.strict, message);
+            warn!("{}", message);
         }
+    }
 
-        bgzf_reader
+    // Extract each region to its own output file
+    if !config.regions.is_empty() {
+        return extract_regions(config, &open, gzi, fastq_index);
     }
 
-    fn bytes_available(&self) -> usize {
-        self.uncompressed_data.len() - self.uncompressed_data_index
+    // Extract a list of records, in file order, buffering them if they are output in the order given
+    if let Some(requested) = &config.records {
+        let requested: Vec<u64> = requested.iter().map(|record| record.0).collect();
+        let mut records = requested.clone();
+        records.sort_unstable();
+        records.dedup();
+        // Check every record before writing any, so that an output is not left partially written
+        for &record in &records {
+            check_bounds(config, fastq_index, record, record)?;
+        }
+        let mut buffered: HashMap<u64, OwnedRecord> = HashMap::new();
+        let mut record_writer = RecordWriter::new(writer, config, fastq_index.spacing())?;
+        // Reuse the reader across records, re-positioning it for each record
+        let mut bgzf_reader = BgzfReader::with_verify_crc(
+            source,
+            UncompressedOffset(0),
+            gzi.block_start(UncompressedOffset(0)),
+            UncompressedOffset(0),
+            config.verify_crc,
+        )?;
+        for record in records {
+            let fqi_range = match fastq_index.range(RecordNum(record), RecordNum(record)) {
+                Some(range) => range,
+                None => {
+                    warn!(
+                        "Skipping record {} as it is out of range (total records: {})",
+                        record,
+                        fastq_index.total_records()
+                    );
+                    continue;
+                }
+            };
+            let (start_entry, num_blocks) = find_blocks(gzi, &fqi_range);
+            if config.verbose {
+                log_range(&config.input, &fqi_range, start_entry, num_blocks);
+            }
+            bgzf_reader.seek_to(fqi_range.start_byte, start_entry, fqi_range.end_byte)?;
+            check_record_start(config, &mut bgzf_reader, fqi_range.start_byte)?;
+            if config.sort_output {
+                write_records(&mut bgzf_reader, &fqi_range, record, record, 1, &mut record_writer)?;
+            } else {
+                record_writer.add_source_range(&fqi_range);
+                let reader =
+                    seq_io::fastq::Reader::new((&mut bgzf_reader).take(fqi_range.num_bytes()));
+                let leading_records = fqi_range.leading_records as usize;
+                if let Some(result) = reader.into_records().nth(leading_records) {
+                    buffered.insert(record, result?);
+                }
+            }
+        }
+        for record in requested {
+            if let Some(rec) = buffered.get(&record) {
+                record_writer.write(record, rec)?;
+            }
+        }
+        record_writer.finish()?;
+        return Ok(());
     }
 
-    fn fill(&mut self) -> io::Result<usize> {
-        let available = self.bytes_available();
-        if 0 < available {
-            return Ok(available);
+    // Extract records until the target number of bases is reached
+    if let Some(target_bases) = config.target_bases {
+        ensure!(target_bases > 0, "--target-bases must be greater than zero");
+        let start = match config.start {
+            Some(start) => resolve_record(start, fastq_index.total_records())?,
+            None => 1,
+        };
+        check_bounds(config, fastq_index, start, start)?;
+        let fqi_range =
+            match fastq_index.range(RecordNum(start), RecordNum(fastq_index.total_records())) {
+                Some(range) => range,
+                // Nothing to extract, but the offsets and FASTQ index (if any) are still written
+                None => return RecordWriter::new(writer, config, fastq_index.spacing())?.finish(),
+            };
+        let (start_entry, num_blocks) = find_blocks(gzi, &fqi_range);
+        if config.verbose {
+            log_range(&config.input, &fqi_range, start_entry, num_blocks);
         }
+        let mut bgzf_reader = BgzfReader::with_verify_crc(
+            source,
+            fqi_range.start_byte,
+            start_entry,
+            fqi_range.end_byte,
+            config.verify_crc,
+        )?;
+        check_record_start(config, &mut bgzf_reader, fqi_range.start_byte)?;
+        let mut record_writer = RecordWriter::new(writer, config, fastq_index.spacing())?;
+        write_bases(&mut bgzf_reader, &fqi_range, start, target_bases, &mut record_writer)?;
+        record_writer.finish()?;
+        return Ok(());
+    }
 
-        // if no more blocks, we fill nothing
-        if self.num_blocks_left == 0 {
-            return Ok(0);
+    // Extract the records that contain a range of bases
+    if let Some((base_start, base_end)) = config.base_range {
+        ensure!(base_start < base_end, "--base-start must be less than --base-end");
+        let mut record_writer = RecordWriter::new(writer, config, fastq_index.spacing())?;
+        // Finding the bases reads every entry of the index into memory
+        let fastq_index = FastqIndex::read(Path::new(&fqi_path))?;
+        if let Some(base_range) = fastq_index.range_by_base(base_start, base_end)? {
+            let (start_entry, num_blocks) = find_blocks(gzi, &base_range.range);
+            if config.verbose {
+                log_range(&config.input, &base_range.range, start_entry, num_blocks);
+            }
+            let mut bgzf_reader = BgzfReader::with_verify_crc(
+                source,
+                base_range.range.start_byte,
+                start_entry,
+                base_range.range.end_byte,
+                config.verify_crc,
+            )?;
+            check_record_start(config, &mut bgzf_reader, base_range.range.start_byte)?;
+            write_base_range(
+                &mut bgzf_reader,
+                &base_range,
+                base_start,
+                base_end,
+                &mut record_writer,
+            )?;
         }
+        record_writer.finish()?;
+        return Ok(());
+    }
 
-        // Read in a block!
+    let (start, end) = start_and_end(config, fastq_index.total_records())?;
+    extract_range(config, source, gzi, fastq_index, start, end, writer)
+}
 
-        // Read the block header
-        match self.reader.read_exact(&mut self.header_buf) {
-            Ok(()) => (),
-            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
-            e => e.unwrap(),
+/// Checks that no more than --max-output-records records would be extracted, as found from the
+/// FASTQ index alone, failing if so unless --yes is given, in which case a warning is logged.
+fn check_max_output_records(config: &ExtractConfig, fastq_index: &dyn RecordIndex) -> Result<()> {
+    let Some(max_output_records) = config.max_output_records else {
+        return Ok(());
+    };
+    let Some(num_records) = num_output_records(config, fastq_index)? else {
+        return Ok(());
+    };
+    if num_records <= max_output_records {
+        return Ok(());
+    }
+    ensure!(
+        config.yes,
+        "{} records would be extracted, more than --max-output-records ({}); narrow the range, or \
+         add --yes to extract them anyway",
+        num_records,
+        max_output_records
+    );
+    warn!(
+        "Extracting {} records, more than --max-output-records ({})",
+        num_records, max_output_records
+    );
+    Ok(())
+}
+
+/// Returns the number of records that would be extracted by --start and --end (every --step
+/// record), or by every --region together, as found from the FASTQ index alone.  Returns `None`
+/// when the records are selected otherwise (e.g. by --records or --target-bases), as the number of
+/// records is then bounded by what is given.  A region that is invalid is not counted, as it is
+/// reported when extracting.
+fn num_output_records(
+    config: &ExtractConfig,
+    fastq_index: &dyn RecordIndex,
+) -> Result<Option<u64>> {
+    let selected_records = |start: u64, end: u64| {
+        fastq_index
+            .range(RecordNum(start), RecordNum(end))
+            .map_or(0, FastqIndexRange::selected_records)
+    };
+    if !config.regions.is_empty() {
+        let regions = config.regions.iter().filter(|&&region| check_region(region).is_ok());
+        return Ok(Some(regions.map(|&(start, end)| selected_records(start.0, end.0)).sum()));
+    }
+    if config.records.is_some() || config.target_bases.is_some() || config.base_range.is_some() {
+        return Ok(None);
+    }
+    let (start, end) = start_and_end(config, fastq_index.total_records())?;
+    Ok(Some(selected_records(start, end).div_ceil(config.step.max(1))))
+}
+
+/// Returns the first and last records (1-based inclusive) given by --start and --end, where a
+/// single record is extracted if only one is given.  Negative records are resolved against the
+/// total number of records (see `resolve_record`).
+fn start_and_end(config: &ExtractConfig, total_records: u64) -> Result<(u64, u64)> {
+    let (start, end) = match (config.start, config.end) {
+        (Some(s), Some(e)) => (s, e),
+        (Some(s), None) => (s, s),
+        (None, Some(e)) => (e, e),
+        (None, None) => bail!("Either a start record, an end record, or the records must be given"),
+    };
+    let (start, end) = (resolve_record(start, total_records)?, resolve_record(end, total_records)?);
+    ensure!(start <= end, "--start must be less than or equal to --end");
+    Ok((start, end))
+}
+
+/// Returns the 1-based record number for a record given on the command line, resolving a record
+/// that counts back from the last record.  Errors for record zero, or a record before the first
+/// record.
+fn resolve_record(record: RecordPosition, total_records: u64) -> Result<u64> {
+    let from_end = match record {
+        RecordPosition::Number(RecordNum(number)) => {
+            ensure!(
+                number != 0,
+                "Records are numbered from 1 (or from -1 for the last record), found 0"
+            );
+            return Ok(number);
         }
-        self.bgzf.check_header(&self.header_buf).unwrap();
+        RecordPosition::FromEnd(from_end) => from_end,
+    };
+    ensure!(
+        from_end <= total_records,
+        "Record {} is before the first record (total records: {})",
+        record,
+        total_records
+    );
+    Ok(total_records - from_end + 1)
+}
 
-        // Read the compressed block data
-        let size = self.bgzf.get_block_size(&self.header_buf).unwrap();
-        self.compressed_buffer.clear();
-        self.compressed_buffer.resize(size - Bgzf::HEADER_SIZE, 0);
-        self.reader.read_exact(&mut self.compressed_buffer)?;
-        let check = self.bgzf.get_footer_values(&self.compressed_buffer);
+/// Writes the number of uncompressed bytes that would be read, and the number of records that
+/// would be output, when extracting every `step`th record from `start` to `end` (1-based
+/// inclusive), along with the estimated size of the output given the mean record size.
+fn write_estimate<W: Write>(
+    fastq_index: &dyn RecordIndex,
+    start: u64,
+    end: u64,
+    step: u64,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    let (num_bytes, selected_records) = match fastq_index.range(RecordNum(start), RecordNum(end)) {
+        Some(range) => (range.num_bytes(), range.selected_records().div_ceil(step.max(1))),
+        None => (0, 0),
+    };
+    writeln!(writer, "num_bytes\t{}", num_bytes)?;
+    writeln!(writer, "selected_records\t{}", selected_records)?;
+    let mean_record_bytes = fastq_index.mean_record_bytes().unwrap_or(0.0);
+    let output_bytes = (selected_records as f64 * mean_record_bytes).round() as u64;
+    writeln!(writer, "output_bytes\t{}", output_bytes)?;
+    Ok(())
+}
 
-        // Decompress the block data
-        self.uncompressed_buffer.clear();
-        self.uncompressed_buffer.resize(check.amount as usize, 0);
-        decompress(
-            &self.compressed_buffer,
-            &mut self.decompressor,
-            &mut self.uncompressed_buffer,
-            check,
-        )
-        .unwrap();
+/// Writes the BGZF blocks that would be read when extracting the records from `start` to `end`
+/// (1-based inclusive), one per line after a header line.  The source is only read to find where
+/// the last block ends, which is before the end-of-file block, if present.
+fn write_blocks<R: Read + Seek, W: Write>(
+    mut source: R,
+    gzi: &BgzfIndex,
+    fastq_index: &dyn RecordIndex,
+    start: u64,
+    end: u64,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    writeln!(writer, "compressed_offset\tcompressed_size\tuncompressed_offset\tuncompressed_size")?;
+    let span = fastq_index
+        .range(RecordNum(start), RecordNum(end))
+        .and_then(|range| gzi.compressed_range(range.start_byte, range.end_byte));
+    let Some((first, last, _)) = span else {
+        return Ok(());
+    };
 
-        // Append
-        self.uncompressed_data.clear();
-        self.uncompressed_data.extend(&self.uncompressed_buffer);
-        self.uncompressed_data_index = 0;
+    let mut compressed_end = source.seek(SeekFrom::End(0))?;
+    if compressed_end >= BGZF_EOF.len() as u64 {
+        let mut tail = [0u8; BGZF_EOF.len()];
+        source.seek(SeekFrom::Start(compressed_end - BGZF_EOF.len() as u64))?;
+        source.read_exact(&mut tail)?;
+        if tail == BGZF_EOF {
+            compressed_end -= BGZF_EOF.len() as u64;
+        }
+    }
+    let eof = BgzfIndexOffset {
+        compressed_offset: CompressedOffset(compressed_end),
+        uncompressed_offset: UncompressedOffset(fastq_index.total_bytes()),
+    };
+    for block in gzi.blocks(first, CompressedOffset(last.0 + 1), eof) {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            block.compressed_offset,
+            block.compressed_size,
+            block.uncompressed_offset,
+            block.uncompressed_size
+        )?;
+    }
+    Ok(())
+}
 
-        Ok(self.bytes_available())
+/// Writes the records and uncompressed bytes of each region, and the output to which it would be
+/// extracted, one per line after a header line.
+fn write_plan<W: Write>(
+    config: &ExtractConfig,
+    fastq_index: &dyn RecordIndex,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    ensure!(
+        config.regions.len() == config.outputs.len(),
+        "Each region must have an output ({} regions, {} outputs)",
+        config.regions.len(),
+        config.outputs.len()
+    );
+    writeln!(writer, "start_record\tend_record\tstart_byte\tend_byte\toutput")?;
+    for (&region, output) in config.regions.iter().zip(config.outputs.iter()) {
+        check_region(region)?;
+        let (start, end) = (region.0 .0, region.1 .0);
+        let (end, start_byte, end_byte) = match fastq_index.range(RecordNum(start), RecordNum(end))
+        {
+            Some(range) => (
+                end.min(fastq_index.total_records()),
+                range.start_byte.to_string(),
+                range.end_byte.to_string(),
+            ),
+            None => (end, "NA".to_string(), "NA".to_string()),
+        };
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", start, end, start_byte, end_byte, output.display())?;
     }
+    Ok(())
 }
 
-impl Read for BgzfReader {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        #[allow(clippy::needless_range_loop)]
-        for buf_index in 0..buf.len() {
-            // no more data available, try to fill
-            if self.bytes_available() == 0 {
-                // no more data was filled, return how many bytes we've read
-                if self.fill().unwrap() == 0 {
-                    return Ok(buf_index);
-                }
-            }
-            buf[buf_index] = self.uncompressed_data[self.uncompressed_data_index];
-            self.uncompressed_data_index += 1;
-        }
-        Ok(buf.len())
+/// Checks that the records from `start` to `end` (1-based inclusive) are in the FASTQ.  With
+/// --strict-bounds, a record past the last record is an error, otherwise the range is clamped to
+/// the last record (see `FastqIndex::range`).
+fn check_bounds(
+    config: &ExtractConfig,
+    fastq_index: &dyn RecordIndex,
+    start: u64,
+    end: u64,
+) -> Result<()> {
+    let total_records = fastq_index.total_records();
+    if end <= total_records {
+        return Ok(());
     }
+    if total_records < start {
+        // No records are in range, which is logged when extracting
+        ensure!(
+            !config.strict_bounds,
+            "Record {} is past the last record (total records: {})",
+            start,
+            total_records
+        );
+        return Ok(());
+    }
+    ensure!(
+        !config.strict_bounds,
+        "The end record ({}) is past the last record (total records: {})",
+        end,
+        total_records
+    );
+    debug!(
+        "Clamping records {} to {} to the last record (total records: {})",
+        start, end, total_records
+    );
+    Ok(())
 }
 
-#[inline]
-pub fn decompress(
-    input: &[u8],
-    decoder: &mut libdeflater::Decompressor,
-    output: &mut [u8],
-    footer_vals: FooterValues,
-) -> Result<(), GzpError> {
-    if footer_vals.amount != 0 {
-        let _bytes_decompressed = decoder.deflate_decompress(&input[..input.len() - 8], output)?;
+/// Extracts the records from `start` to `end` (1-based inclusive), writing them to the given writer
+fn extract_range<R: Read + Seek, W: Write>(
+    config: &ExtractConfig,
+    source: R,
+    gzi: &BgzfIndex,
+    fastq_index: &dyn RecordIndex,
+    start: u64,
+    end: u64,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    ensure!(config.step > 0, "--step must be greater than zero");
+    check_bounds(config, fastq_index, start, end)?;
+    let fqi_range = match fastq_index.range(RecordNum(start), RecordNum(end)) {
+        Some(range) => range,
+        None => {
+            warn!(
+                "No records to extract, as records {} to {} are out of range (total records: {})",
+                start,
+                end,
+                fastq_index.total_records()
+            );
+            if config.raw_blocks {
+                return Ok(());
+            }
+            // Nothing to extract, but the offsets and FASTQ index (if any) are still written
+            return RecordWriter::new(writer, config, fastq_index.spacing())?.finish();
+        }
+    };
+
+    // Find the compressed offset
+    let (start_entry, num_blocks) = find_blocks(gzi, &fqi_range);
+    if config.verbose {
+        log_range(&config.input, &fqi_range, start_entry, num_blocks);
     }
-    let mut new_check = libdeflater::Crc::new();
-    new_check.update(output);
 
-    if footer_vals.sum != new_check.sum() {
-        return Err(GzpError::InvalidCheck { found: new_check.sum(), expected: footer_vals.sum });
+    if config.raw_blocks {
+        return write_raw_blocks(source, start_entry, num_blocks, writer);
     }
+
+    // Build a BgzfReader starting at the next FASTQ record
+    let mut bgzf_reader = BgzfReader::with_verify_crc(
+        source,
+        fqi_range.start_byte,
+        start_entry,
+        fqi_range.end_byte,
+        config.verify_crc,
+    )?;
+
+    check_record_start(config, &mut bgzf_reader, fqi_range.start_byte)?;
+
+    // Write the FASTQ entries
+    let mut record_writer = RecordWriter::new(writer, config, fastq_index.spacing())?;
+    write_records(&mut bgzf_reader, &fqi_range, start, end, config.step, &mut record_writer)?;
+    record_writer.finish()?;
     Ok(())
 }
 
-/// Parse args and set up logging / tracing
-pub fn setup() -> Opts {
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "info");
+/// Extracts records from a bgzip'ed FASTQ using its BGZF and FASTQ indexes, for use as a library
+/// rather than through the `extract` command.
+pub struct Extractor<R: Read + Seek = File> {
+    source: R,
+    gzi: BgzfIndex,
+    fastq_index: FastqIndex,
+}
+
+impl Extractor<File> {
+    /// Opens the bgzip'ed FASTQ along with its BGZF index (`.gzi`) and FASTQ index (`.fqi`)
+    pub fn open(input: &Path) -> Result<Extractor<File>> {
+        check_bgzf(input)?;
+        check_indexes(&ExtractConfig { input: input.to_path_buf(), ..ExtractConfig::default() })?;
+        let gzi = BgzfIndex::from(format!("{}.{}", input.to_string_lossy(), "gzi"))?;
+        let fqi_path = format!("{}.{}", input.to_string_lossy(), "fqi");
+        let fastq_index = FastqIndex::read(Path::new(&fqi_path))?;
+        let source =
+            File::open(input).with_context(|| format!("Could not open {}", input.display()))?;
+        Ok(Extractor::new(source, gzi, fastq_index))
     }
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+}
 
-    Opts::parse()
+impl<R: Read + Seek> Extractor<R> {
+    pub fn new(source: R, gzi: BgzfIndex, fastq_index: FastqIndex) -> Extractor<R> {
+        Extractor { source, gzi, fastq_index }
+    }
+
+    /// Returns the records in each of the given ranges of records (1-based inclusive).
+    ///
+    /// The ranges are ordered by where they start in the bgzip'ed FASTQ, and the records of each
+    /// range are returned in turn, in that order, regardless of the order in which the ranges were
+    /// given.  A record in more than one range is returned once for each range, while a range
+    /// past the end of the FASTQ returns no records.
+    ///
+    /// The records are read as they are iterated, in a forward pass over the FASTQ: a range that
+    /// starts within the last BGZF block read for the ranges before it is read along with them,
+    /// so that its block is not decompressed twice.  Only a range that overlaps the ranges before
+    /// it seeks back to re-read its records.  An error reading a record is returned by the
+    /// iterator, after which it returns no more records.
+    pub fn records_multi(
+        &mut self,
+        ranges: &[(u64, u64)],
+    ) -> Result<impl Iterator<Item = Result<OwnedRecord>> + '_> {
+        // The byte range of each range of records, in the order of the FASTQ
+        let mut fqi_ranges: Vec<(u64, u64, FastqIndexRange)> = ranges
+            .iter()
+            .filter_map(|&(start, end)| {
+                let fqi_range = self.fastq_index.range(RecordNum(start), RecordNum(end))?;
+                let (start, end) = (start.max(1), end.min(self.fastq_index.total_records));
+                Some((start, end, fqi_range))
+            })
+            .collect();
+        fqi_ranges.sort_by_key(|(start, end, fqi_range)| (fqi_range.start_byte, *start, *end));
+
+        // Group the ranges that start in the last block read for the ranges before them, and
+        // after the last record of those ranges, so that each group is read in a single pass
+        let mut queued: Vec<QueuedRange> = vec![];
+        let mut group: Option<(usize, u64)> = None; // the group's first range and last record
+        for (start, end, fqi_range) in fqi_ranges {
+            if let Some((first, last_record)) = group {
+                let group_start = queued[first].seek.as_mut().unwrap();
+                let last_block =
+                    self.gzi.block_start(UncompressedOffset(group_start.end_byte.0 - 1));
+                let next_block = self.gzi.block_start(fqi_range.start_byte);
+                if last_record < start
+                    && next_block.compressed_offset <= last_block.compressed_offset
+                {
+                    group_start.end_byte = group_start.end_byte.max(fqi_range.end_byte);
+                    group = Some((first, end));
+                    queued.push(QueuedRange { start, end, seek: None });
+                    continue;
+                }
+            }
+            group = Some((queued.len(), end));
+            let seek = GroupStart {
+                start_byte: fqi_range.start_byte,
+                end_byte: fqi_range.end_byte,
+                entry: self.gzi.block_start(fqi_range.start_byte),
+                first_record: start - fqi_range.leading_records,
+            };
+            queued.push(QueuedRange { start, end, seek: Some(seek) });
+        }
+
+        let mut records = MultiRecords {
+            source: Some(&mut self.source),
+            reader: None,
+            ranges: queued.into_iter(),
+            current: None,
+            record_number: 0,
+            record: RawRecord::default(),
+        };
+        // Position the reader at the first group, so that an error opening it is returned here
+        records.next_range()?;
+        Ok(records)
+    }
+
+    /// Calls `f` with each record from `start` to `end` (1-based inclusive), in turn.  Unlike
+    /// `records_multi`, the records are borrowed from the reader's buffer rather than copied into
+    /// owned records, so that they may be processed as they are read without allocating.
+    pub fn for_each_record<F: FnMut(&RefRecord)>(
+        &mut self,
+        start: u64,
+        end: u64,
+        mut f: F,
+    ) -> Result<()> {
+        let fqi_range = match self.fastq_index.range(RecordNum(start), RecordNum(end)) {
+            Some(fqi_range) => fqi_range,
+            None => return Ok(()),
+        };
+        let num_records = end.min(self.fastq_index.total_records) - start.max(1) + 1;
+        let start_entry = self.gzi.block_start(fqi_range.start_byte);
+        let bgzf_reader = BgzfReader::new(
+            &mut self.source,
+            fqi_range.start_byte,
+            start_entry,
+            fqi_range.end_byte,
+        )?;
+        let mut reader = seq_io::fastq::Reader::new(bgzf_reader.take(fqi_range.num_bytes()));
+        let first_record = start.max(1) - fqi_range.leading_records;
+        let mut index: u64 = 0;
+        while let Some(result) = reader.next() {
+            let rec = result.map_err(|err| parse_error(err, first_record + index))?;
+            if let Some(offset) = index.checked_sub(fqi_range.leading_records) {
+                if offset >= num_records {
+                    break;
+                }
+                f(&rec);
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Where a group of ranges read by `Extractor::records_multi` starts and ends
+struct GroupStart {
+    start_byte: UncompressedOffset,
+    end_byte: UncompressedOffset,
+    // the BGZF index entry at or before the start byte
+    entry: BgzfIndexOffset,
+    // the number of the record at the start byte
+    first_record: u64,
+}
+
+/// A range of records (1-based inclusive) to be read by `Extractor::records_multi`, which starts a
+/// new group if `seek` is set, and otherwise continues reading the group of the range before it.
+struct QueuedRange {
+    start: u64,
+    end: u64,
+    seek: Option<GroupStart>,
+}
+
+/// The records of the ranges given to `Extractor::records_multi`, read as they are iterated
+struct MultiRecords<'a, R: Read + Seek> {
+    // the source, until the reader is built for the first group
+    source: Option<&'a mut R>,
+    reader: Option<BgzfReader<&'a mut R>>,
+    ranges: std::vec::IntoIter<QueuedRange>,
+    // the range whose records are being returned
+    current: Option<(u64, u64)>,
+    // the number of the next record to be read
+    record_number: u64,
+    record: RawRecord,
+}
+
+impl<R: Read + Seek> MultiRecords<'_, R> {
+    /// Moves to the next range, seeking to the start of its group if it starts one.  Returns
+    /// false if there are no more ranges.
+    fn next_range(&mut self) -> Result<bool> {
+        let QueuedRange { start, end, seek } = match self.ranges.next() {
+            Some(range) => range,
+            None => {
+                self.current = None;
+                return Ok(false);
+            }
+        };
+        if let Some(GroupStart { start_byte, end_byte, entry, first_record }) = seek {
+            match self.reader.as_mut() {
+                Some(reader) => reader.seek_to(start_byte, entry, end_byte)?,
+                None => {
+                    let source = self.source.take().expect("the source is only taken once");
+                    self.reader = Some(BgzfReader::new(source, start_byte, entry, end_byte)?);
+                }
+            }
+            self.record_number = first_record;
+        }
+        self.current = Some((start, end));
+        Ok(true)
+    }
+
+    /// Returns the next record of the current range, moving on to the next range once the
+    /// current range has been read, or `None` once every range has been read.
+    fn next_record(&mut self) -> Result<Option<OwnedRecord>> {
+        loop {
+            let (start, _) = match self.current {
+                Some((_, end)) if end < self.record_number => {
+                    self.next_range()?;
+                    continue;
+                }
+                Some(range) => range,
+                None => return Ok(None),
+            };
+            let reader = self.reader.as_mut().expect("the reader is built for the first range");
+            let record_number = RecordNum(self.record_number);
+            self.record.read(reader)?;
+            self.record.check(record_number)?;
+            self.record_number += 1;
+            if start <= record_number.0 {
+                return Ok(Some(self.record.to_owned_record()));
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for MultiRecords<'_, R> {
+    type Item = Result<OwnedRecord>;
+
+    fn next(&mut self) -> Option<Result<OwnedRecord>> {
+        let result = self.next_record();
+        if result.is_err() {
+            // Stop after an error, rather than reading on from where it occurred
+            self.ranges = Vec::new().into_iter();
+            self.current = None;
+        }
+        result.transpose()
+    }
+}
+
+/// Calls `f` with each record from `start` to `end` (1-based inclusive) of the bgzip'ed FASTQ,
+/// using its BGZF and FASTQ indexes.  See `Extractor::for_each_record`.
+pub fn for_each_record<F: FnMut(&RefRecord)>(
+    input: &Path,
+    start: u64,
+    end: u64,
+    f: F,
+) -> Result<()> {
+    Extractor::open(input)?.for_each_record(start, end, f)
+}
+
+/// Parses a region, either `<start>-<end>` (1-based inclusive) or a single record.  The records
+/// are checked when extracting (see `check_region`).
+fn parse_region(region: &str) -> Result<(RecordNum, RecordNum)> {
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u64>()
+            .map(RecordNum)
+            .with_context(|| format!("Invalid region: {}", region))
+    };
+    match region.split_once('-') {
+        Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+        None => Ok((parse(region)?, parse(region)?)),
+    }
+}
+
+/// Checks that a region (1-based inclusive) starts at or after the first record, and at or before
+/// its end.
+fn check_region((start, end): (RecordNum, RecordNum)) -> Result<()> {
+    ensure!(start.0 > 0, "Regions are 1-based: {}-{}", start, end);
+    ensure!(
+        start <= end,
+        "The region start must be less than or equal to its end: {}-{}",
+        start,
+        end
+    );
+    Ok(())
+}
+
+/// Extracts each --region to its corresponding --output file, using up to --threads threads.  The
+/// indexes are shared across threads, while each region is read with its own reader.  With
+/// --keep-going, a region that fails is logged and the others are still extracted, and an error
+/// is returned at the end if any region failed.
+fn extract_regions<R, F>(
+    config: &ExtractConfig,
+    open: &F,
+    gzi: &BgzfIndex,
+    fastq_index: &dyn RecordIndex,
+) -> Result<(), anyhow::Error>
+where
+    R: Read + Seek,
+    F: Fn() -> io::Result<R> + Sync,
+{
+    ensure!(
+        config.regions.len() == config.outputs.len(),
+        "Each region must have an output ({} regions, {} outputs)",
+        config.regions.len(),
+        config.outputs.len()
+    );
+    ensure!(config.threads > 0, "--threads must be greater than zero");
+    ensure!(config.offsets.is_none(), "The record offsets cannot be written for regions");
+    ensure!(config.stats.is_none(), "The stats cannot be written for regions");
+    // Check every region before extracting any, unless continuing past the regions that fail
+    if !config.keep_going {
+        config.regions.iter().try_for_each(|&region| check_region(region))?;
+    }
+
+    // Each region is written to a temporary file that is renamed over the output once complete,
+    // so that a partially written output is never mistaken for a complete one (see --resume)
+    let extract_region = |index: usize| -> Result<(), anyhow::Error> {
+        let region = config.regions[index];
+        check_region(region)?;
+        let (start, end) = (region.0 .0, region.1 .0);
+        let output = &config.outputs[index];
+        if config.resume && output.exists() {
+            info!(
+                "Skipping region {}-{} as its output already exists: {}",
+                region.0,
+                region.1,
+                output.display()
+            );
+            return Ok(());
+        }
+        // The region is extracted as if to a single output, so that with --reindex its indexes are
+        // written alongside its output
+        let region_config =
+            ExtractConfig { regions: vec![], outputs: vec![output.clone()], ..config.clone() };
+        let tmp_path = tmp_path(output);
+        let writer = OutputWriter::create(&tmp_path, gzip_output(config, output))?;
+        let result = write_region(&region_config, open()?, gzi, fastq_index, start, end, writer);
+        // The indexes are renamed before the output, whose existence marks the region as complete
+        let result = if config.reindex { finish_indexes(output, result) } else { result };
+        finish_output(output, &tmp_path, result)
+    };
+
+    // Each thread extracts the next region that has not yet been started
+    let next_region = AtomicUsize::new(0);
+    let num_failed = AtomicUsize::new(0);
+    let extract_next = || -> Result<(), anyhow::Error> {
+        loop {
+            let index = next_region.fetch_add(1, Ordering::Relaxed);
+            if config.regions.len() <= index {
+                return Ok(());
+            }
+            match extract_region(index) {
+                Ok(()) => (),
+                Err(err) if config.keep_going => {
+                    let (start, end) = config.regions[index];
+                    error!(
+                        "Could not extract region {}-{} to {}: {:#}",
+                        start,
+                        end,
+                        config.outputs[index].display(),
+                        err
+                    );
+                    num_failed.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    };
+
+    let num_threads = std::cmp::min(config.threads, config.regions.len());
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_threads).map(|_| scope.spawn(extract_next)).collect();
+        handles.into_iter().try_for_each(|handle| handle.join().unwrap())
+    })?;
+    let num_failed = num_failed.into_inner();
+    ensure!(num_failed == 0, "{} of {} regions failed", num_failed, config.regions.len());
+    Ok(())
+}
+
+/// Extracts the records from `start` to `end` (1-based inclusive) to the given writer for a single
+/// region, BGZF compressing them and writing the BGZF index alongside the output per the config.
+fn write_region<R: Read + Seek>(
+    config: &ExtractConfig,
+    source: R,
+    gzi: &BgzfIndex,
+    fastq_index: &dyn RecordIndex,
+    start: u64,
+    end: u64,
+    mut writer: OutputWriter,
+) -> Result<()> {
+    if !config.compress {
+        extract_range(config, source, gzi, fastq_index, start, end, &mut writer)?;
+        writer.finish()?;
+        return Ok(());
+    }
+    let mut bgzf_writer = BgzfWriter::new(writer);
+    extract_range(config, source, gzi, fastq_index, start, end, &mut bgzf_writer)?;
+    let (writer, region_gzi) = bgzf_writer.finish()?;
+    writer.finish()?;
+    if config.reindex {
+        write_gzi(&config.outputs[0], &region_gzi)?;
+    }
+    Ok(())
+}
+
+/// Logs the resolved uncompressed byte range and the BGZF blocks that will be read
+fn log_range(
+    input: &Path,
+    fqi_range: &FastqIndexRange,
+    start_entry: BgzfIndexOffset,
+    num_blocks: usize,
+) {
+    info!(
+        "Uncompressed bytes [{}, {}) contain {} selected records, with {} leading and {} trailing \
+         records",
+        fqi_range.start_byte,
+        fqi_range.end_byte,
+        fqi_range.clone().selected_records(),
+        fqi_range.leading_records,
+        fqi_range.trailing_records
+    );
+    info!(
+        "Reading {} BGZF block(s) starting at compressed offset {} (uncompressed offset {})",
+        num_blocks, start_entry.compressed_offset, start_entry.uncompressed_offset
+    );
+    info!(
+        "The following command will output the same records, including the leading and trailing \
+         records: bgzip -b {} -s {} {}",
+        fqi_range.start_byte,
+        fqi_range.num_bytes(),
+        input.display()
+    );
+}
+
+/// Parses a list of 1-based record numbers, either given inline as a comma-separated list, or as
+/// `@<path>` to a file with one record number per line.  The records are returned in the order
+/// given.
+fn parse_records(value: &str) -> Result<Vec<RecordNum>> {
+    let contents = match value.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read records from: {}", path))?,
+        None => value.replace(',', "\n"),
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse::<u64>()
+                .map(RecordNum)
+                .with_context(|| format!("Invalid record number: {}", line))
+        })
+        .collect()
+}
+
+/// Finds the BGZF index entry from which to start reading, and the number of blocks to read, to
+/// cover the given range.
+///
+/// The start entry is the last entry at or before the range's start byte, which is the start of the
+/// file when the range starts in the first block, whether or not the BGZF index has the synthetic
+/// first entry (see [`BgzfIndex::from_with_options`]).  The blocks counted are
+/// those that overlap the range's uncompressed bytes, so a block that starts at or after the end
+/// byte is not counted.
+pub(crate) fn find_blocks(
+    gzi: &BgzfIndex,
+    fqi_range: &FastqIndexRange,
+) -> (BgzfIndexOffset, usize) {
+    let start_entry = gzi.block_start(fqi_range.start_byte);
+    let num_blocks = gzi
+        .compressed_range(fqi_range.start_byte, fqi_range.end_byte)
+        .map_or(0, |(_, _, num_blocks)| num_blocks);
+    (start_entry, num_blocks)
+}
+
+/// Writes the complete records that lie within the uncompressed bytes `[byte_start, byte_end)`.
+///
+/// Since the byte range need not start on a record boundary, the first record is found by
+/// skipping to the start of the next line, then to the first line that starts with `@` whose
+/// third line starts with `+`.  A partial record at either end of the range is dropped.
+fn write_byte_range<R: Read + Seek, W: Write>(
+    source: R,
+    gzi: &BgzfIndex,
+    byte_start: UncompressedOffset,
+    byte_end: UncompressedOffset,
+    verify_crc: bool,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    // Read from one byte before the range to know if the range starts at the start of a line
+    let read_start = UncompressedOffset(byte_start.0.saturating_sub(1));
+    if byte_end <= read_start {
+        return Ok(());
+    }
+    let start_entry = gzi.block_start(read_start);
+    let bgzf_reader =
+        BgzfReader::with_verify_crc(source, read_start, start_entry, byte_end, verify_crc)?;
+    let mut reader = BufReader::with_capacity(BUFFERSIZE, bgzf_reader.take(byte_end - read_start));
+
+    // Skip the remainder of a line that starts before the range
+    if byte_start.0 > 0 {
+        let mut line: Vec<u8> = vec![];
+        reader.read_until(b'\n', &mut line)?;
+    }
+
+    let mut lines: VecDeque<Vec<u8>> = VecDeque::with_capacity(4);
+    loop {
+        let mut line: Vec<u8> = vec![];
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // A line cut short by the end of the range is part of a partial record
+        if line.last() != Some(&b'\n') && reader.get_ref().limit() == 0 {
+            break;
+        }
+        lines.push_back(line);
+        if lines.len() == 4 {
+            if lines[0].starts_with(b"@") && lines[2].starts_with(b"+") {
+                for line in lines.drain(..) {
+                    writer.write_all(&line)?;
+                }
+            } else {
+                lines.pop_front();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The output formats that may be given with `--output-format`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Fastq,
+    Fasta,
+    Tsv,
+}
+
+/// The format in which extracted records are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Fastq,
+    /// FASTA, optionally wrapping the sequence to a number of bases per line (see `wrap`)
+    Fasta,
+    /// The name, sequence, and qualities, separated by tabs
+    Tsv,
+}
+
+impl RecordFormat {
+    /// Writes the record in this format, wrapping FASTA sequences to the given number of bases per
+    /// line
+    fn write<B: Record, W: Write>(
+        self,
+        rec: &B,
+        wrap: Option<usize>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        match self {
+            RecordFormat::Fastq => rec.write(writer),
+            RecordFormat::Fasta => {
+                writer.write_all(b">")?;
+                writer.write_all(rec.head())?;
+                writer.write_all(b"\n")?;
+                let seq = rec.seq();
+                let line_length = wrap.unwrap_or(seq.len()).max(1);
+                if seq.is_empty() {
+                    writer.write_all(b"\n")?;
+                }
+                for line in seq.chunks(line_length) {
+                    writer.write_all(line)?;
+                    writer.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+            RecordFormat::Tsv => {
+                writer.write_all(rec.head())?;
+                writer.write_all(b"\t")?;
+                writer.write_all(rec.seq())?;
+                writer.write_all(b"\t")?;
+                writer.write_all(rec.qual())?;
+                writer.write_all(b"\n")
+            }
+        }
+    }
+}
+
+/// Writes records in the configured format, and optionally the offset in the output at which each
+/// record starts (see `--offsets`).  The newline at the end of the last record is withheld with
+/// `--no-trailing-newline`.  The records written, and the source bytes read, are counted for
+/// `--stats`.
+struct RecordWriter<W: Write> {
+    writer: ByteCountingWriter<TrailingNewlineWriter<W>>,
+    format: RecordFormat,
+    wrap: Option<usize>,
+    annotate_record_number: bool,
+    trailing_newline: bool,
+    offsets: Option<BufWriter<File>>,
+    stats: Option<PathBuf>,
+    records_written: u64,
+    // the uncompressed bytes of the source that were read
+    source_range: Option<(UncompressedOffset, UncompressedOffset)>,
+    // indexes the records written, for `--reindex`
+    index_writer: Option<IndexWriter<BufWriter<File>>>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Creates the writer, creating the offsets file and writing its header if one is configured,
+    /// and the FASTQ index for `reindex`, whose entries are spaced as given.
+    fn new(
+        writer: W,
+        config: &ExtractConfig,
+        spacing: CheckpointSpacing,
+    ) -> Result<RecordWriter<W>> {
+        let offsets = match &config.offsets {
+            Some(path) => {
+                let file = File::create(path)
+                    .with_context(|| format!("Could not create {}", path.display()))?;
+                let mut offsets = BufWriter::with_capacity(BUFFERSIZE, file);
+                writeln!(offsets, "record_number\toffset")?;
+                Some(offsets)
+            }
+            None => None,
+        };
+        // The FASTQ index is written to a temporary file (see `finish_indexes`)
+        let index_writer = match config.outputs.first() {
+            Some(output) if config.reindex => {
+                let [fqi_path, _] = index_paths(output);
+                let tmp_path = tmp_path(&fqi_path);
+                let file = File::create(&tmp_path)
+                    .with_context(|| format!("Could not create {}", tmp_path.display()))?;
+                let writer = BufWriter::with_capacity(BUFFERSIZE, file);
+                Some(IndexWriter::with_spacing(writer, spacing, 0)?)
+            }
+            _ => None,
+        };
+        Ok(RecordWriter {
+            writer: ByteCountingWriter::new(TrailingNewlineWriter::new(
+                writer,
+                !config.trailing_newline,
+            )),
+            format: config.format,
+            wrap: config.wrap,
+            annotate_record_number: config.annotate_record_number,
+            trailing_newline: config.trailing_newline,
+            offsets,
+            stats: config.stats.clone(),
+            records_written: 0,
+            source_range: None,
+            index_writer,
+        })
+    }
+
+    /// Adds the uncompressed bytes of the given range to the bytes of the source that were read
+    fn add_source_range(&mut self, range: &FastqIndexRange) {
+        self.source_range = Some(match self.source_range {
+            Some((start, end)) => (start.min(range.start_byte), end.max(range.end_byte)),
+            None => (range.start_byte, range.end_byte),
+        });
+    }
+
+    /// Writes the record with the given 1-based record number
+    fn write<B: Record>(&mut self, record_number: u64, rec: &B) -> Result<()> {
+        let offset = self.writer.num_bytes();
+        if let Some(offsets) = &mut self.offsets {
+            writeln!(offsets, "{}\t{}", record_number, offset)?;
+        }
+        if self.annotate_record_number {
+            self.writer.write_all(b"@")?;
+            self.writer.write_all(rec.head())?;
+            self.writer.write_all(b"\n")?;
+            self.writer.write_all(rec.seq())?;
+            write!(self.writer, "\n+record={}\n", record_number)?;
+            self.writer.write_all(rec.qual())?;
+            self.writer.write_all(b"\n")?;
+        } else {
+            self.format.write(rec, self.wrap, &mut self.writer)?;
+        }
+        if let Some(index_writer) = &mut self.index_writer {
+            index_writer.add_record(self.writer.num_bytes() - offset, rec.seq().len() as u64)?;
+        }
+        self.records_written += 1;
+        Ok(())
+    }
+
+    /// Flushes the offsets file, writes the stats file, and finishes the FASTQ index, if any
+    fn finish(self) -> Result<()> {
+        if let Some(mut offsets) = self.offsets {
+            offsets.flush()?;
+        }
+        if let Some(path) = &self.stats {
+            // Every record ends with a newline, so the last byte counted is withheld when stripping
+            // the trailing newline
+            let num_bytes = self.writer.num_bytes();
+            let bytes_written =
+                if self.trailing_newline { num_bytes } else { num_bytes.saturating_sub(1) };
+            let (source_start_byte, source_end_byte) = match self.source_range {
+                Some((start, end)) => (start.to_string(), end.to_string()),
+                None => ("NA".to_string(), "NA".to_string()),
+            };
+            let file = File::create(path)
+                .with_context(|| format!("Could not create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "records_written\t{}", self.records_written)?;
+            writeln!(writer, "bytes_written\t{}", bytes_written)?;
+            writeln!(writer, "source_start_byte\t{}", source_start_byte)?;
+            writeln!(writer, "source_end_byte\t{}", source_end_byte)?;
+            writer.flush()?;
+        }
+        if let Some(index_writer) = self.index_writer {
+            index_writer.finish(None)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error for a BGZF block (or its header, given `part`) at the given compressed offset that
+/// ends early, e.g. for a file whose download was cut off.
+fn truncated_block(offset: u64, part: &str, num_bytes: usize, expected: usize) -> io::Error {
+    let message = format!(
+        "BGZF block {}at compressed offset {} is truncated: found {} of {} bytes",
+        part, offset, num_bytes, expected
+    );
+    io::Error::new(ErrorKind::UnexpectedEof, message)
+}
+
+/// Copies the given number of compressed BGZF blocks, starting at the given entry, followed by an
+/// end-of-file block.
+fn write_raw_blocks<R: Read + Seek, W: Write>(
+    mut source: R,
+    start_entry: BgzfIndexOffset,
+    num_blocks: usize,
+    writer: &mut W,
+) -> Result<(), anyhow::Error> {
+    let bgzf = Bgzf::new();
+    let mut block: Vec<u8> = vec![0; Bgzf::HEADER_SIZE];
+    let mut offset = source.seek(SeekFrom::Start(start_entry.compressed_offset.into()))?;
+    for _ in 0..num_blocks {
+        block.resize(Bgzf::HEADER_SIZE, 0);
+        let num_read = read_fully(&mut source, &mut block)?;
+        if num_read < Bgzf::HEADER_SIZE {
+            return Err(truncated_block(offset, "header ", num_read, Bgzf::HEADER_SIZE).into());
+        }
+        bgzf.check_header(&block).map_err(invalid_data)?;
+        let size = bgzf.get_block_size(&block).map_err(invalid_data)?;
+        ensure!(size >= Bgzf::HEADER_SIZE + 8, "Invalid BGZF block size: {}", size);
+        block.resize(size, 0);
+        let num_read = read_fully(&mut source, &mut block[Bgzf::HEADER_SIZE..])?;
+        if num_read < size - Bgzf::HEADER_SIZE {
+            return Err(truncated_block(offset, "", Bgzf::HEADER_SIZE + num_read, size).into());
+        }
+        writer.write_all(&block)?;
+        offset += size as u64;
+    }
+    writer.write_all(&BGZF_EOF)?;
+    Ok(())
+}
+
+/// Checks that the reader, positioned at the given uncompressed byte from the FASTQ index, is at
+/// the start of a record, unless the check is disabled (see `--no-sanity-check`).  A record that
+/// does not start with '@' means the index does not match the FASTQ, e.g. it is stale.
+fn check_record_start<R: Read + Seek>(
+    config: &ExtractConfig,
+    bgzf_reader: &mut BgzfReader<R>,
+    start_byte: UncompressedOffset,
+) -> Result<()> {
+    if !config.sanity_check {
+        return Ok(());
+    }
+    match bgzf_reader.peek()? {
+        Some(byte) if byte == UTF8_BOM[0] && start_byte.0 == 0 => bail!(
+            "The FASTQ starts with a UTF-8 byte order mark (BOM), which is not supported; remove \
+             it and re-index the FASTQ: {}",
+            config.input.display()
+        ),
+        Some(byte) if byte != b'@' => bail!(
+            "The FASTQ index appears misaligned; the stream did not start at a record boundary \
+             (found {:?} rather than '@' at uncompressed byte {}): {}",
+            byte as char,
+            start_byte,
+            config.input.display()
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Writes the selected records in the given range, skipping over the leading records, and writing
+/// only every `step`th record from `start` to `end` (1-based inclusive).
+fn write_records<R: Read + Seek, W: Write>(
+    bgzf_reader: &mut BgzfReader<R>,
+    fqi_range: &FastqIndexRange,
+    start: u64,
+    end: u64,
+    step: u64,
+    record_writer: &mut RecordWriter<W>,
+) -> Result<(), anyhow::Error> {
+    let num_records = end - start + 1;
+    record_writer.add_source_range(fqi_range);
+    // Only read the range's bytes, so any data after the range (e.g. blank lines) is not parsed
+    let reader = seq_io::fastq::Reader::new(bgzf_reader.take(fqi_range.num_bytes()));
+    for (index, result) in reader.into_records().enumerate() {
+        let rec = result
+            .map_err(|err| parse_error(err, start - fqi_range.leading_records + index as u64))?;
+
+        if let Some(offset) = (index as u64).checked_sub(fqi_range.leading_records) {
+            if offset >= num_records {
+                break;
+            }
+            if offset.is_multiple_of(step) {
+                record_writer.write(start + offset, &rec)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the selected records in the given range, starting at record `start` (1-based) and
+/// skipping over the leading records, until the total number of bases written reaches or exceeds
+/// the target number of bases.
+fn write_bases<R: Read + Seek, W: Write>(
+    bgzf_reader: &mut BgzfReader<R>,
+    fqi_range: &FastqIndexRange,
+    start: u64,
+    target_bases: u64,
+    record_writer: &mut RecordWriter<W>,
+) -> Result<(), anyhow::Error> {
+    record_writer.add_source_range(fqi_range);
+    // Only read the range's bytes, so any data after the range (e.g. blank lines) is not parsed
+    let reader = seq_io::fastq::Reader::new(bgzf_reader.take(fqi_range.num_bytes()));
+    let mut num_bases: u64 = 0;
+    for (index, result) in reader.into_records().enumerate() {
+        let rec = result
+            .map_err(|err| parse_error(err, start - fqi_range.leading_records + index as u64))?;
+
+        if let Some(offset) = (index as u64).checked_sub(fqi_range.leading_records) {
+            record_writer.write(start + offset, &rec)?;
+            num_bases += rec.seq().len() as u64;
+        }
+        if num_bases >= target_bases {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the records that contain any of the bases `[base_start, base_end)`, reading the records
+/// of the given base range, which span whole indexed chunks.
+fn write_base_range<R: Read + Seek, W: Write>(
+    bgzf_reader: &mut BgzfReader<R>,
+    base_range: &FastqBaseRange,
+    base_start: u64,
+    base_end: u64,
+    record_writer: &mut RecordWriter<W>,
+) -> Result<(), anyhow::Error> {
+    record_writer.add_source_range(&base_range.range);
+    let reader = seq_io::fastq::Reader::new(bgzf_reader.take(base_range.range.num_bytes()));
+    // The number of bases before the current record
+    let mut num_bases = base_range.start_base;
+    for (record_number, result) in (base_range.start_record.0..).zip(reader.into_records()) {
+        if base_end <= num_bases {
+            break;
+        }
+        let rec = result.map_err(|err| parse_error(err, record_number))?;
+        let rec_bases = rec.seq().len() as u64;
+        if base_start < num_bases + rec_bases {
+            record_writer.write(record_number, &rec)?;
+        }
+        num_bases += rec_bases;
+    }
+    Ok(())
+}
+
+/// Reads the uncompressed bytes of a range of BGZF blocks from a bgzip'ed source, typically a
+/// `File` or a memory-mapped file.
+///
+/// Reading starts at the given BGZF index entry, decompressing forward through every block until
+/// the start byte is reached, and stops once the next block starts at or after the end byte.  The
+/// blocks are found by reading their headers rather than from the index, so the index entries may
+/// be sparser than the blocks (e.g. a `.gzi` with an entry for every other block).
+pub struct BgzfReader<R: Read + Seek = File> {
+    reader: R,
+    bgzf: Bgzf,
+    header_buf: Vec<u8>,
+    compressed_buffer: BytesMut,
+    decoder: BlockDecoder,
+    uncompressed_data: Vec<u8>,
+    uncompressed_data_index: usize,
+    // the uncompressed offset of the next block
+    uncompressed_offset: UncompressedOffset,
+    // no blocks are read that start at or after this uncompressed offset
+    end_byte: UncompressedOffset,
+    // the compressed offset of the next block, for reporting errors
+    compressed_offset: u64,
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Builds a reader of the uncompressed bytes from `start_byte`, starting at the BGZF index
+    /// entry at or before it, and reading the blocks up to `end_byte` (exclusive).  Bytes after
+    /// `end_byte` may be read from the last block.
+    pub(crate) fn new(
+        reader: R,
+        start_byte: UncompressedOffset,
+        entry: BgzfIndexOffset,
+        end_byte: UncompressedOffset,
+    ) -> io::Result<Self> {
+        BgzfReader::with_verify_crc(reader, start_byte, entry, end_byte, true)
+    }
+
+    /// Builds a reader as with `new`, checking the CRC32 of each block only if `verify_crc` is
+    /// true.
+    pub(crate) fn with_verify_crc(
+        reader: R,
+        start_byte: UncompressedOffset,
+        entry: BgzfIndexOffset,
+        end_byte: UncompressedOffset,
+        verify_crc: bool,
+    ) -> io::Result<Self> {
+        let bgzf = Bgzf::new();
+        let header_buf = vec![0; Bgzf::HEADER_SIZE];
+        let compressed_buffer = BytesMut::with_capacity(BGZF_BLOCK_SIZE);
+        let decoder = BlockDecoder::with_verify_crc(verify_crc);
+        let uncompressed_data: Vec<u8> = vec![];
+
+        let mut bgzf_reader = BgzfReader {
+            reader,
+            bgzf,
+            header_buf,
+            compressed_buffer,
+            decoder,
+            uncompressed_data,
+            uncompressed_data_index: 0,
+            uncompressed_offset: entry.uncompressed_offset,
+            end_byte,
+            compressed_offset: 0,
+        };
+
+        bgzf_reader.seek_to(start_byte, entry, end_byte)?;
+
+        Ok(bgzf_reader)
+    }
+
+    /// Re-positions the reader at the given uncompressed start byte, starting from the given
+    /// BGZF index entry and reading the blocks up to the given end byte, discarding any buffered
+    /// data.
+    fn seek_to(
+        &mut self,
+        start_byte: UncompressedOffset,
+        entry: BgzfIndexOffset,
+        end_byte: UncompressedOffset,
+    ) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(entry.compressed_offset.into()))?;
+        self.compressed_offset = entry.compressed_offset.into();
+        self.uncompressed_data.clear();
+        self.uncompressed_data_index = 0;
+        self.uncompressed_offset = entry.uncompressed_offset;
+        self.end_byte = end_byte;
+
+        // move to the start uncompressed byte offset
+        let mut cur_uncompressed_offset = entry.uncompressed_offset;
+        while cur_uncompressed_offset < start_byte {
+            // fill the data, stop when we have no more data
+            if self.bytes_available() == 0 && self.fill()? == 0 {
+                break;
+            }
+            cur_uncompressed_offset += 1;
+            self.uncompressed_data_index += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the next uncompressed byte without consuming it, or `None` if there are no more
+    /// bytes to read.
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        if self.fill()? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.uncompressed_data[self.uncompressed_data_index]))
+    }
+
+    fn bytes_available(&self) -> usize {
+        self.uncompressed_data.len() - self.uncompressed_data_index
+    }
+
+    /// Decompresses the next block if no data is buffered, returning the number of bytes
+    /// available, or zero if there are no more blocks to read.
+    fn fill(&mut self) -> io::Result<usize> {
+        let available = self.bytes_available();
+        if 0 < available {
+            return Ok(available);
+        }
+
+        // if the next block is past the end, we fill nothing
+        if self.end_byte <= self.uncompressed_offset {
+            return Ok(0);
+        }
+
+        // Read in a block!
+
+        // Read the block header
+        // Read the block header, where no bytes means the end of the data
+        let offset = self.compressed_offset;
+        match read_fully(&mut self.reader, &mut self.header_buf)? {
+            0 => return Ok(0),
+            n if n < Bgzf::HEADER_SIZE => {
+                return Err(truncated_block(offset, "header ", n, Bgzf::HEADER_SIZE));
+            }
+            _ => (),
+        }
+        self.bgzf.check_header(&self.header_buf).map_err(invalid_data)?;
+
+        // Read the compressed block data
+        let size = self.bgzf.get_block_size(&self.header_buf).map_err(invalid_data)?;
+        if size < Bgzf::HEADER_SIZE + 8 {
+            return Err(invalid_data(format!("Invalid BGZF block size: {}", size)));
+        }
+        self.compressed_buffer.clear();
+        self.compressed_buffer.resize(size - Bgzf::HEADER_SIZE, 0);
+        let num_read = read_fully(&mut self.reader, &mut self.compressed_buffer)?;
+        if num_read < self.compressed_buffer.len() {
+            return Err(truncated_block(offset, "", Bgzf::HEADER_SIZE + num_read, size));
+        }
+        self.compressed_offset += size as u64;
+        let check = self.bgzf.get_footer_values(&self.compressed_buffer);
+
+        // Decompress the block data
+        self.decoder
+            .decode(&self.compressed_buffer, check, &mut self.uncompressed_data)
+            .map_err(invalid_data)?;
+        self.uncompressed_data_index = 0;
+        self.uncompressed_offset += self.uncompressed_data.len() as u64;
+
+        // Skip empty blocks, so that zero bytes are only returned at the end of the data
+        if self.uncompressed_data.is_empty() {
+            return self.fill();
+        }
+        Ok(self.bytes_available())
+    }
+}
+
+/// Reads the uncompressed data, decompressing at most one block per call.  A read may return fewer
+/// bytes than requested (at most the remainder of the current block), and `Ok(0)` is returned only
+/// once the last block has been read (or `buf` is empty), and on every read thereafter.  An I/O or
+/// decompression error is returned as an `Err`, in which case no bytes were read.
+impl<R: Read + Seek> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.fill()? == 0 {
+            return Ok(0);
+        }
+        let start = self.uncompressed_data_index;
+        let n = std::cmp::min(buf.len(), self.bytes_available());
+        buf[..n].copy_from_slice(&self.uncompressed_data[start..start + n]);
+        self.uncompressed_data_index += n;
+        Ok(n)
+    }
+}
+
+/// Buffers the uncompressed data of the current block, so that lines may be read without another
+/// buffer.  The buffer is empty only once the last block has been read.
+impl<R: Read + Seek> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.fill()?;
+        Ok(&self.uncompressed_data[self.uncompressed_data_index..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.uncompressed_data_index =
+            (self.uncompressed_data_index + amount).min(self.uncompressed_data.len());
+    }
+}
+
+/// Converts a BGZF format or decompression error into an I/O error
+fn invalid_data<E: std::fmt::Display>(error: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error.to_string())
+}
+
+/// Computes the CRC32 and number of bytes of the uncompressed data in the BGZF source, by
+/// combining the CRC32 and uncompressed size stored in each block's footer, so that no data is
+/// decompressed.
+pub fn bgzf_crc32<R: Read + Seek>(reader: &mut R) -> Result<(u32, u64)> {
+    let bgzf = Bgzf::new();
+    let mut header = vec![0; Bgzf::HEADER_SIZE];
+    let mut footer = [0u8; 8];
+    let mut crc32: u32 = 0;
+    let mut num_bytes: u64 = 0;
+    let mut offset = reader.seek(SeekFrom::Start(0))?;
+    loop {
+        let num_read = read_fully(reader, &mut header)?;
+        if num_read == 0 {
+            break;
+        }
+        ensure!(
+            num_read == header.len(),
+            "BGZF block header at offset {} is truncated: found {} of {} bytes",
+            offset,
+            num_read,
+            header.len()
+        );
+        bgzf.check_header(&header)?;
+        let block_size = bgzf.get_block_size(&header)? as u64;
+        reader.seek(SeekFrom::Start(offset + block_size - footer.len() as u64))?;
+        reader
+            .read_exact(&mut footer)
+            .with_context(|| format!("BGZF block at offset {} is truncated", offset))?;
+        let block_bytes = u64::from(LittleEndian::read_u32(&footer[4..8]));
+        crc32 = crc32_combine(crc32, LittleEndian::read_u32(&footer[0..4]), block_bytes);
+        num_bytes += block_bytes;
+        offset += block_size;
+    }
+    Ok((crc32, num_bytes))
+}
+
+/// Decompresses a BGZF block into `output`, which must be the block's uncompressed size, and
+/// verifies its CRC32.
+#[deprecated(note = "use BlockDecoder, which also sizes the output and may skip the CRC32")]
+#[inline]
+pub fn decompress(
+    input: &[u8],
+    decoder: &mut Decompressor,
+    output: &mut [u8],
+    footer_vals: FooterValues,
+) -> Result<(), GzpError> {
+    if footer_vals.amount != 0 {
+        let _bytes_decompressed = decoder.deflate_decompress(&input[..input.len() - 8], output)?;
+    }
+    let mut new_check = Crc::new();
+    new_check.update(output);
+
+    if footer_vals.sum != new_check.sum() {
+        return Err(GzpError::InvalidCheck { found: new_check.sum(), expected: footer_vals.sum });
+    }
+    Ok(())
+}
+
+/// Decompresses BGZF blocks and verifies their CRC32s, reusing the decompressor across blocks.
+pub struct BlockDecoder {
+    decompressor: Decompressor,
+    verify_crc: bool,
+}
+
+impl BlockDecoder {
+    pub fn new() -> BlockDecoder {
+        BlockDecoder::with_verify_crc(true)
+    }
+
+    /// Builds a decoder that skips computing and checking the CRC32s if `verify_crc` is false
+    pub fn with_verify_crc(verify_crc: bool) -> BlockDecoder {
+        BlockDecoder { decompressor: Decompressor::new(), verify_crc }
+    }
+
+    /// Decompresses a block into `out`, which is resized to the block's uncompressed size.  The
+    /// compressed data is the block's bytes following its header, including the footer, whose
+    /// values are given.  Errors if the data cannot be decompressed, or if its CRC32 does not
+    /// match the footer (unless the CRC32 is not verified).
+    pub fn decode(
+        &mut self,
+        compressed: &[u8],
+        footer: FooterValues,
+        out: &mut Vec<u8>,
+    ) -> Result<(), GzpError> {
+        out.clear();
+        out.resize(footer.amount as usize, 0);
+        if footer.amount != 0 {
+            let data = &compressed[..compressed.len().saturating_sub(8)];
+            let _bytes_decompressed = self.decompressor.deflate_decompress(data, out)?;
+        }
+        if !self.verify_crc {
+            return Ok(());
+        }
+        let mut crc = Crc::new();
+        crc.update(out);
+
+        if footer.sum != crc.sum() {
+            return Err(GzpError::InvalidCheck { found: crc.sum(), expected: footer.sum });
+        }
+        Ok(())
+    }
+}
+
+impl Default for BlockDecoder {
+    fn default() -> Self {
+        BlockDecoder::new()
+    }
+}
+
+/// Compresses the data written to it into BGZF blocks, like `bgzip`, recording the offsets of each
+/// block for the BGZF index.  Call `finish` to write the last block and the end-of-file block.
+pub struct BgzfWriter<W: Write> {
+    writer: W,
+    compressor: Compressor,
+    // the uncompressed data for the current block
+    buffer: Vec<u8>,
+    compressed_buffer: Vec<u8>,
+    // the offsets of the start of the current block
+    offset: BgzfIndexOffset,
+    entries: Vec<BgzfIndexOffset>,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(writer: W) -> BgzfWriter<W> {
+        BgzfWriter {
+            writer,
+            compressor: Compressor::new(CompressionLvl::default()),
+            buffer: Vec::with_capacity(BGZF_BLOCK_SIZE),
+            compressed_buffer: vec![],
+            offset: BgzfIndexOffset::default(),
+            entries: vec![BgzfIndexOffset::default()],
+        }
+    }
+
+    /// Compresses and writes the buffered data as a block, if there is any
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.offset.uncompressed_offset.0 > 0 {
+            self.entries.push(self.offset);
+        }
+        self.compressed_buffer.resize(self.compressor.deflate_compress_bound(self.buffer.len()), 0);
+        let num_compressed = self
+            .compressor
+            .deflate_compress(&self.buffer, &mut self.compressed_buffer)
+            .map_err(invalid_data)?;
+        let block_size = Bgzf::HEADER_SIZE + num_compressed + 8;
+        let bsize = u16::try_from(block_size - 1)
+            .map_err(|_| invalid_data(format!("BGZF block is too large: {}", block_size)))?;
+        let mut crc = Crc::new();
+        crc.update(&self.buffer);
+
+        // The header is that of the end-of-file block, followed by this block's size
+        self.writer.write_all(&BGZF_EOF[..Bgzf::HEADER_SIZE - 2])?;
+        self.writer.write_all(&bsize.to_le_bytes())?;
+        self.writer.write_all(&self.compressed_buffer[..num_compressed])?;
+        self.writer.write_all(&crc.sum().to_le_bytes())?;
+        self.writer.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+
+        self.offset.compressed_offset += block_size as u64;
+        self.offset.uncompressed_offset += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the end-of-file block, returning the inner writer and the BGZF
+    /// index of the blocks written, including the synthetic first entry.
+    pub fn finish(mut self) -> io::Result<(W, BgzfIndex)> {
+        self.write_block()?;
+        self.writer.write_all(&BGZF_EOF)?;
+        self.writer.flush()?;
+        let index = BgzfIndex::new(self.entries);
+        Ok((self.writer, index))
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num_written = std::cmp::min(buf.len(), BGZF_BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..num_written]);
+        if self.buffer.len() == BGZF_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(num_written)
+    }
+
+    /// Writes the buffered data as a (possibly short) block, then flushes the inner writer
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.writer.flush()
+    }
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        fs::File,
+        io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    };
+
+    use byteorder::{ByteOrder, LittleEndian};
+    use clap::Parser;
+    use fgoxide::io::Io;
+    use gzp::{
+        bgzf::BgzfSyncWriter, deflate::Bgzf, BlockFormatSpec, Compression, FooterValues, FormatSpec,
+    };
+    use libdeflater::{CompressionLvl, Compressor, Crc};
+    use seq_io::{fastq::OwnedRecord, BaseRecord};
+    use tempfile::TempDir;
+
+    use crate::{
+        testutil::{
+            bgzip, capture_logs, captured_logs, record, records, splitmix64, to_fastq, write_fastq,
+            write_varied_synthetic_fastq,
+        },
+        tools::{
+            bgzf_index::{BgzfIndex, BgzfIndexOffset},
+            fastq_index::{CheckpointSpacing, FastqIndex, FastqIndexRange, RecordIndex},
+            locate::locate,
+            verify::{self, verify},
+        },
+        types::{CompressedOffset, RecordNum, RecordPosition, UncompressedOffset},
+    };
+
+    use super::{
+        bgzf_crc32, check_bgzf, check_region, extract, extract_range, find_blocks, for_each_record,
+        parse_region, run, start_and_end, write_records, BgzfReader, BlockDecoder, ExtractConfig,
+        Extractor, Opts, OutputFormat, RecordFormat, RecordWriter, BGZF_EOF,
+    };
+
+    fn default_opts(input: &Path) -> Opts {
+        Opts {
+            input: input.to_path_buf(),
+            start: None,
+            end: None,
+            step: 1,
+            records: None,
+            sort_output: true,
+            target_bases: None,
+            byte_start: None,
+            byte_end: None,
+            base_start: None,
+            base_end: None,
+            all: false,
+            fasta: false,
+            output_format: OutputFormat::Fastq,
+            wrap: None,
+            raw_blocks: false,
+            estimate: false,
+            blocks: false,
+            offsets: None,
+            stats: None,
+            annotate_record_number: false,
+            no_trailing_newline: false,
+            mmap: false,
+            no_verify_crc: false,
+            no_sanity_check: false,
+            strict_bounds: false,
+            max_output_records: None,
+            yes: false,
+            strict: false,
+            verify_source: false,
+            verbose: false,
+            region: vec![],
+            output: vec![],
+            compress: false,
+            reindex: false,
+            threads: 4,
+            keep_going: false,
+            plan: false,
+            resume: false,
+        }
+    }
+
+    fn to_config(opts: &Opts) -> ExtractConfig {
+        ExtractConfig::try_from(opts).unwrap()
+    }
+
+    fn run_extract(opts: &Opts) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+        extract(&to_config(opts), &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_extract_range() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        let opts = Opts { start: Some(3), end: Some(7), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[2..7]));
+    }
+
+    #[test]
+    fn test_extract_range_lazy_index() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let fqi_path = dir.path().join("test.fastq.gz.fqi");
+        let eager = FastqIndex::read(&fqi_path).unwrap();
+        let lazy = FastqIndex::open_mmap(&fqi_path).unwrap();
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        let config = ExtractConfig { input: input.clone(), ..ExtractConfig::default() };
+
+        // the same records are extracted whether the index is read into memory or memory mapped
+        for (start, end) in [(1, 1), (1, 10), (2, 3), (4, 6), (5, 9), (10, 10), (8, 20), (11, 12)] {
+            let extract_with = |fastq_index: &dyn RecordIndex| {
+                let mut output: Vec<u8> = vec![];
+                let source = fs::File::open(&input).unwrap();
+                extract_range(&config, source, &gzi, fastq_index, start, end, &mut output).unwrap();
+                output
+            };
+            let output = extract_with(&eager);
+            assert_eq!(output, extract_with(&lazy), "start: {} end: {}", start, end);
+            let (first, last) = ((start - 1) as usize, end.min(10) as usize);
+            assert_eq!(output, to_fastq(&records[first.min(last)..last]));
+        }
+    }
+
+    #[test]
+    fn test_extract_sparse_gzi() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        // use small blocks so that records span blocks without an entry
+        let input = write_fastq(dir.path(), &records, 1, 50);
+        let gzi_path = format!("{}.gzi", input.display());
+        let gzi = BgzfIndex::from(gzi_path.clone()).unwrap();
+        assert!(gzi.entries.len() > 4);
+
+        // keep an entry for every other block
+        let sparse = BgzfIndex::new(gzi.entries.iter().step_by(2).copied().collect());
+        sparse.write_to(&mut fs::File::create(&gzi_path).unwrap()).unwrap();
+        for start in 1..=10 {
+            for end in start..=10 {
+                let opts = Opts { start: Some(start), end: Some(end), ..default_opts(&input) };
+                let expected = to_fastq(&records[start as usize - 1..end as usize]);
+                assert_eq!(run_extract(&opts), expected, "start: {} end: {}", start, end);
+            }
+        }
+        let opts = Opts { records: Some("9,2,5".to_string()), ..default_opts(&input) };
+        assert_eq!(
+            run_extract(&opts),
+            to_fastq(&[records[1].clone(), records[4].clone(), records[8].clone()])
+        );
+        let opts = Opts { byte_start: Some(20), byte_end: Some(140), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..5]));
+    }
+
+    #[test]
+    fn test_extract_config() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        let config = ExtractConfig {
+            input: input.clone(),
+            start: Some(RecordPosition::from(2)),
+            end: Some(RecordPosition::from(8)),
+            step: 3,
+            format: RecordFormat::Fasta,
+            ..ExtractConfig::default()
+        };
+        let mut output: Vec<u8> = vec![];
+        extract(&config, &mut output).unwrap();
+        assert_eq!(output, b">read-2\nGATTACA\n>read-5\nGATTACA\n>read-8\nGATTACA\n");
+
+        // the same as the command line options
+        let opts =
+            Opts { start: Some(2), end: Some(8), step: 3, fasta: true, ..default_opts(&input) };
+        assert_eq!(to_config(&opts), config);
+    }
+
+    #[test]
+    fn test_extract_offsets() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+        let offsets = dir.path().join("offsets.tsv");
+
+        // the offsets of the records within the output, computed from their sizes
+        let expected = |selected: &[usize]| {
+            let mut expected = String::from("record_number\toffset\n");
+            let mut offset = 0;
+            for record_number in selected {
+                expected.push_str(&format!("{}\t{}\n", record_number, offset));
+                offset += to_fastq(&records[record_number - 1..*record_number]).len();
+            }
+            expected
+        };
+
+        let opts = Opts {
+            start: Some(8),
+            end: Some(10),
+            offsets: Some(offsets.clone()),
+            ..default_opts(&input)
+        };
+        let output = run_extract(&opts);
+        assert_eq!(output, to_fastq(&records[7..10]));
+        assert_eq!(fs::read_to_string(&offsets).unwrap(), expected(&[8, 9, 10]));
+        assert_eq!(
+            fs::read_to_string(&offsets).unwrap(),
+            "record_number\toffset\n8\t0\n9\t26\n10\t52\n"
+        );
+
+        let opts = Opts { start: Some(1), end: Some(7), step: 3, ..opts };
+        run_extract(&opts);
+        assert_eq!(fs::read_to_string(&offsets).unwrap(), expected(&[1, 4, 7]));
+
+        let opts =
+            Opts { records: Some("9,2,5".to_string()), start: None, end: None, step: 1, ..opts };
+        run_extract(&opts);
+        assert_eq!(fs::read_to_string(&offsets).unwrap(), expected(&[2, 5, 9]));
+    }
+
+    #[test]
+    fn test_extract_gzip_output() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // an output ending in .gz is gzip-compressed, whatever the output format
+        let output = dir.path().join("slice.fq.gz");
+        let opts = Opts {
+            start: Some(3),
+            end: Some(6),
+            output: vec![output.clone()],
+            ..default_opts(&input)
+        };
+        run(&opts).unwrap();
+        assert_eq!(&fs::read(&output).unwrap()[0..2], &[0x1f, 0x8b]);
+        let mut actual = vec![];
+        Io::default().new_reader(&output).unwrap().read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, to_fastq(&records[2..6]));
+
+        let output = dir.path().join("slice.tsv.gz");
+        let opts = Opts {
+            start: Some(3),
+            end: Some(3),
+            output: vec![output.clone()],
+            output_format: OutputFormat::Tsv,
+            ..default_opts(&input)
+        };
+        run(&opts).unwrap();
+        let mut actual = String::new();
+        Io::default().new_reader(&output).unwrap().read_to_string(&mut actual).unwrap();
+        assert_eq!(actual, "read-3\tGATTACA\tIIIIIII\n");
+
+        // as are the outputs of regions
+        let outputs = vec![dir.path().join("a.fq.gz"), dir.path().join("b.fq")];
+        let opts = Opts {
+            region: vec!["2-4".to_string(), "6".to_string()],
+            output: outputs.clone(),
+            ..default_opts(&input)
+        };
+        run(&opts).unwrap();
+        let mut actual = vec![];
+        Io::default().new_reader(&outputs[0]).unwrap().read_to_end(&mut actual).unwrap();
+        assert_eq!(&fs::read(&outputs[0]).unwrap()[0..2], &[0x1f, 0x8b]);
+        assert_eq!(actual, to_fastq(&records[1..4]));
+        assert_eq!(fs::read(&outputs[1]).unwrap(), to_fastq(&records[5..6]));
+        assert!(!dir.path().join("a.fq.gz.tmp").exists());
+
+        // other outputs are not compressed
+        let output = dir.path().join("slice.fq");
+        run(&Opts {
+            start: Some(3),
+            end: Some(6),
+            output: vec![output.clone()],
+            ..default_opts(&input)
+        })
+        .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), to_fastq(&records[2..6]));
+        assert!(!dir.path().join("slice.fq.tmp").exists());
+
+        // an output is not left half-written when extracting fails
+        let output = dir.path().join("failed.fq.gz");
+        let opts = Opts {
+            start: Some(8),
+            end: Some(20),
+            strict_bounds: true,
+            output: vec![output.clone()],
+            ..default_opts(&input)
+        };
+        assert!(run(&opts).is_err());
+        assert!(!output.exists());
+        assert!(!dir.path().join("failed.fq.gz.tmp").exists());
+    }
+
+    #[test]
+    fn test_extract_reindex() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+        let slice = dir.path().join("slice.fastq.gz");
+
+        let opts = Opts {
+            start: Some(3),
+            end: Some(9),
+            compress: true,
+            reindex: true,
+            output: vec![slice.clone()],
+            ..default_opts(&input)
+        };
+        fs::write(&slice, run_extract(&opts)).unwrap();
+
+        // the FASTQ index is the same as if the slice had been indexed with `fqme index`
+        let fqi_path = dir.path().join("slice.fastq.gz.fqi");
+        let expected = FastqIndex::from_raw_reader(&to_fastq(&records[2..9])[..], 3, &mut None);
+        assert_eq!(FastqIndex::read(&fqi_path).unwrap(), expected.unwrap());
+        assert!(dir.path().join("slice.fastq.gz.gzi").exists());
+
+        // records can be extracted from the slice using its indexes
+        for (start, end) in [(1, 7), (2, 5), (4, 4), (7, 7)] {
+            let opts = Opts { start: Some(start), end: Some(end), ..default_opts(&slice) };
+            assert_eq!(
+                run_extract(&opts),
+                to_fastq(&records[start as usize + 1..end as usize + 2])
+            );
+        }
+
+        // an input index spaced by bytes is reindexed alike
+        let fastq = to_fastq(&records);
+        let index = FastqIndex::from_raw_reader_by_bytes(&fastq[..], 60, &mut None).unwrap();
+        index.write(&dir.path().join("test.fastq.gz.fqi"));
+        fs::write(&slice, run_extract(&opts)).unwrap();
+        let expected =
+            FastqIndex::from_raw_reader_by_bytes(&to_fastq(&records[2..9])[..], 60, &mut None);
+        let reindexed = FastqIndex::read(&fqi_path).unwrap();
+        assert_eq!(reindexed.nth_bytes, Some(60));
+        assert_eq!(reindexed, expected.unwrap());
+    }
+
+    #[test]
+    fn test_extract_compress_eof() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+        let opts = Opts { start: Some(3), end: Some(9), compress: true, ..default_opts(&input) };
+        let output = run_extract(&opts);
+        assert!(output.ends_with(&BGZF_EOF));
+
+        // build the BGZF index from the block headers and footers of the output
+        let mut entries = vec![BgzfIndexOffset::default()];
+        let mut offset = BgzfIndexOffset::default();
+        while (offset.compressed_offset.0 as usize) < output.len() {
+            let block = &output[offset.compressed_offset.0 as usize..];
+            let block_size = usize::from(LittleEndian::read_u16(&block[16..18])) + 1;
+            offset.compressed_offset += block_size as u64;
+            offset.uncompressed_offset +=
+                u64::from(LittleEndian::read_u32(&block[block_size - 4..block_size]));
+            entries.push(offset);
+        }
+        // the last entry is the end of the file, after the empty end-of-file block
+        let eof = entries.pop().unwrap();
+        assert_eq!(eof.compressed_offset.0, output.len() as u64);
+        assert_eq!(entries.last().unwrap().uncompressed_offset, eof.uncompressed_offset);
+        let gzi = BgzfIndex::new(entries);
+
+        // the reader reaches the end of the file cleanly, reading the end-of-file block
+        let mut reader = BgzfReader::new(
+            Cursor::new(&output[..]),
+            UncompressedOffset(0),
+            gzi.entries[0],
+            UncompressedOffset(u64::MAX),
+        )
+        .unwrap();
+        let mut fastq: Vec<u8> = vec![];
+        reader.read_to_end(&mut fastq).unwrap();
+        assert_eq!(fastq, to_fastq(&records[2..9]));
+        assert_eq!(reader.read(&mut [0u8; 16]).unwrap(), 0);
+        assert_eq!(reader.compressed_offset, output.len() as u64);
+    }
+
+    #[test]
+    fn test_plus_line_round_trip() {
+        let records = records(10);
+        let separators: [fn(&OwnedRecord) -> Vec<u8>; 4] = [
+            |_| b"+".to_vec(),
+            |_| b"+comment".to_vec(),
+            // the header repeated, with and without the '@'
+            |rec| [b"+", &rec.head[..]].concat(),
+            |rec| [b"+@", &rec.head[..]].concat(),
+        ];
+        for (separator, raw) in separators.iter().flat_map(|s| [(s, false), (s, true)]) {
+            let mut fastq: Vec<u8> = vec![];
+            for rec in &records {
+                for line in [[b"@", &rec.head[..]].concat(), rec.seq.clone(), separator(rec)] {
+                    fastq.extend_from_slice(&line);
+                    fastq.push(b'\n');
+                }
+                fastq.extend_from_slice(&rec.qual);
+                fastq.push(b'\n');
+            }
+
+            // `fqme index` writes the FASTQ unchanged with --raw, otherwise as parsed
+            let (index, bgzipped) = if raw {
+                (FastqIndex::from_raw_reader(&fastq[..], 3, &mut None).unwrap(), fastq.clone())
+            } else {
+                let parsed: Vec<OwnedRecord> = seq_io::fastq::Reader::new(&fastq[..])
+                    .into_records()
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                let index = FastqIndex::from(parsed.iter().cloned().map(Ok), 3, &mut None);
+                (index.unwrap(), to_fastq(&parsed))
+            };
+            let dir = TempDir::new().unwrap();
+            let input = dir.path().join("test.fastq.gz");
+            let (bgzf, gzi) = bgzip(&bgzipped, 50);
+            fs::write(&input, bgzf).unwrap();
+            fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+            index.clone().write(&dir.path().join("test.fastq.gz.fqi"));
+            let message = format!("separator: {:?} raw: {}", separator(&records[0]), raw);
+
+            // every record, and a range spanning checkpoints, is extracted exactly
+            for i in 1..=10 {
+                let opts = Opts { start: Some(i), end: Some(i), ..default_opts(&input) };
+                let expected = to_fastq(&records[i as usize - 1..i as usize]);
+                assert_eq!(run_extract(&opts), expected, "record: {} {}", i, message);
+            }
+            let opts = Opts { start: Some(2), end: Some(8), ..default_opts(&input) };
+            assert_eq!(run_extract(&opts), to_fastq(&records[1..8]), "{}", message);
+
+            // the records start at the byte offsets in the index
+            let starts: Vec<u64> = index.entries.iter().map(|entry| entry.total_bytes).collect();
+            for (i, start_byte) in starts[..starts.len() - 1].iter().enumerate() {
+                let located = locate(&input, UncompressedOffset(*start_byte)).unwrap();
+                assert_eq!(located.start_byte.0, *start_byte, "{}", message);
+                assert_eq!(located.record, records[i * 3], "{}", message);
+            }
+            verify(&verify::Opts { input: input.clone(), full: true }).unwrap();
+        }
+    }
+
+    /// BGZF compresses the data with gzp into blocks of `block_size` uncompressed bytes, returning
+    /// the compressed bytes and the GZI index bytes, found by walking the blocks written
+    fn gzp_bgzip(data: &[u8], block_size: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut bgzf: Vec<u8> = vec![];
+        {
+            let mut writer = BgzfSyncWriter::new(&mut bgzf, Compression::new(6));
+            for chunk in data.chunks(block_size) {
+                // flushing ends the block
+                writer.write_all(chunk).unwrap();
+                writer.flush().unwrap();
+            }
+        }
+        let format = Bgzf::new();
+        let mut entries: Vec<BgzfIndexOffset> = vec![];
+        let (mut compressed_offset, mut uncompressed_offset) = (0, 0);
+        while compressed_offset < bgzf.len() {
+            let block_size = format.get_block_size(&bgzf[compressed_offset..]).unwrap();
+            let block = &bgzf[compressed_offset..compressed_offset + block_size];
+            entries.push(BgzfIndexOffset {
+                compressed_offset: CompressedOffset(compressed_offset as u64),
+                uncompressed_offset: UncompressedOffset(uncompressed_offset as u64),
+            });
+            compressed_offset += block_size;
+            uncompressed_offset += format.get_footer_values(block).amount as usize;
+        }
+        assert_eq!(uncompressed_offset, data.len());
+        assert!(bgzf.ends_with(&BGZF_EOF));
+        // the end-of-file block is not indexed
+        entries.pop();
+        let mut gzi: Vec<u8> = vec![];
+        BgzfIndex::new(entries).write_to(&mut gzi).unwrap();
+        (bgzf, gzi)
+    }
+
+    /// Drops the comment on the '+' line of each four-line record in the FASTQ
+    fn drop_plus_comments(fastq: &[u8]) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+        for (index, line) in fastq.split_inclusive(|&byte| byte == b'\n').enumerate() {
+            output.extend_from_slice(if index % 4 == 2 { b"+\n" } else { line });
+        }
+        output
+    }
+
+    #[test]
+    fn test_extract_random_round_trip() {
+        // Random FASTQs with varied read lengths and '+' line comments, indexed with random nth and
+        // bgzip'ed with random block sizes, from a fixed seed so that any failure is reproducible
+        let mut state: u64 = 1167;
+        let mut random = |low: u64, high: u64| low + splitmix64(&mut state) % (high - low + 1);
+        for case in 0..25 {
+            let (num_records, max_read_len) = (random(1, 200), random(1, 150));
+            // every fifth FASTQ is indexed without checkpoints (nth = 0)
+            let nth = if case % 5 == 0 { 0 } else { random(1, 20) };
+            let block_size = random(16, 1000);
+            let mut fastq: Vec<u8> = vec![];
+            let seed = random(0, u64::MAX - 1);
+            write_varied_synthetic_fastq(
+                &mut fastq,
+                num_records as usize,
+                max_read_len as usize,
+                seed,
+            )
+            .unwrap();
+            let message = format!(
+                "case: {} records: {} max_read_len: {} nth: {} block_size: {}",
+                case, num_records, max_read_len, nth, block_size
+            );
+
+            // the offset at which each record starts, and the end of the FASTQ
+            let line_starts = fastq.iter().enumerate().filter(|(_, &byte)| byte == b'\n');
+            let mut offsets: Vec<usize> =
+                line_starts.map(|(i, _)| i + 1).skip(3).step_by(4).collect();
+            offsets.insert(0, 0);
+            assert_eq!(offsets.len() as u64, num_records + 1, "{}", message);
+
+            let dir = TempDir::new().unwrap();
+            let input = dir.path().join("test.fastq.gz");
+            let (bgzf, gzi) = gzp_bgzip(&fastq, block_size as usize);
+            fs::write(&input, bgzf).unwrap();
+            fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+            let index = FastqIndex::from_raw_reader(&fastq[..], nth, &mut None).unwrap();
+            index.clone().write(&dir.path().join("test.fastq.gz.fqi"));
+
+            // single records, then ranges of records
+            for query in 0..40 {
+                let start = random(1, num_records);
+                let end = if query < 20 { start } else { random(start, num_records) };
+
+                // the index's range starts and ends at record boundaries around the records
+                let range = index.range(RecordNum(start), RecordNum(end)).unwrap();
+                let first = (start - 1 - range.leading_records) as usize;
+                let last = (end + range.trailing_records) as usize;
+                assert_eq!(
+                    range.start_byte.0, offsets[first] as u64,
+                    "{} start: {}",
+                    message, start
+                );
+                assert_eq!(range.end_byte.0, offsets[last] as u64, "{} end: {}", message, end);
+
+                // the records are extracted as in the FASTQ, without the '+' line comments
+                let slice = &fastq[offsets[start as usize - 1]..offsets[end as usize]];
+                let opts = Opts {
+                    start: Some(start as i64),
+                    end: Some(end as i64),
+                    ..default_opts(&input)
+                };
+                assert_eq!(
+                    run_extract(&opts),
+                    drop_plus_comments(slice),
+                    "{} start: {} end: {}",
+                    message,
+                    start,
+                    end
+                );
+            }
+        }
+    }
+
+    /// Records the byte ranges read from the inner reader
+    struct CountingReader<R: Read + Seek> {
+        inner: R,
+        position: u64,
+        reads: Vec<(u64, u64)>,
+    }
+
+    impl<R: Read + Seek> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let num_bytes = self.inner.read(buf)?;
+            self.reads.push((self.position, self.position + num_bytes as u64));
+            self.position += num_bytes as u64;
+            Ok(num_bytes)
+        }
+    }
+
+    impl<R: Read + Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.position = self.inner.seek(pos)?;
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn test_records_multi() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 1, 50);
+        let bgzf = fs::read(&input).unwrap();
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        let fastq_index = FastqIndex::read(&dir.path().join("test.fastq.gz.fqi")).unwrap();
+        let source = CountingReader { inner: Cursor::new(&bgzf[..]), position: 0, reads: vec![] };
+        let mut extractor = Extractor::new(source, gzi, fastq_index);
+
+        // the ranges are returned in the order of the FASTQ, and the first two share a block
+        let actual: Vec<OwnedRecord> = extractor
+            .records_multi(&[(8, 9), (1, 2), (4, 4), (20, 30)])
+            .unwrap()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        let expected: Vec<OwnedRecord> =
+            [1, 2, 4, 8, 9].iter().map(|i| records[i - 1].clone()).collect();
+        assert_eq!(actual, expected);
+
+        // each compressed byte was read at most once
+        let mut reads = extractor.source.reads.clone();
+        reads.retain(|(start, end)| start < end);
+        reads.sort_unstable();
+        for pair in reads.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "read twice: {:?}", pair);
+        }
+
+        // a record in overlapping ranges is returned for each range
+        let actual: Vec<OwnedRecord> = extractor
+            .records_multi(&[(3, 4), (2, 3)])
+            .unwrap()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        let expected: Vec<OwnedRecord> =
+            [2, 3, 3, 4].iter().map(|i| records[i - 1].clone()).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(Extractor::open(&input).unwrap().records_multi(&[]).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_for_each_record() {
+        let dir = TempDir::new().unwrap();
+        let records: Vec<OwnedRecord> = (1..=10)
+            .map(|i| OwnedRecord { seq: vec![b'A'; i], qual: vec![b'I'; i], ..record(i) })
+            .collect();
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // records 4 to 8 have 4 + 5 + 6 + 7 + 8 bases
+        let mut num_bases = 0;
+        for_each_record(&input, 4, 8, |rec| num_bases += rec.seq().len()).unwrap();
+        assert_eq!(num_bases, 30);
+
+        // the records are streamed in order, and a range past the end is clamped
+        let mut names = vec![];
+        for_each_record(&input, 9, 20, |rec| names.push(rec.head().to_vec())).unwrap();
+        assert_eq!(names, vec![b"read-9".to_vec(), b"read-10".to_vec()]);
+        for_each_record(&input, 11, 20, |_| panic!("no records")).unwrap();
+    }
+
+    #[test]
+    fn test_extract_annotate_record_number() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        let opts = Opts {
+            start: Some(5),
+            end: Some(8),
+            annotate_record_number: true,
+            ..default_opts(&input)
+        };
+        let output = String::from_utf8(run_extract(&opts)).unwrap();
+        let expected: String =
+            (5..=8).map(|i| format!("@read-{}\nGATTACA\n+record={}\nIIIIIII\n", i, i)).collect();
+        assert_eq!(output, expected);
+
+        // with --step, the numbers are those of the records in the input
+        let opts = Opts { step: 2, ..opts };
+        let output = String::from_utf8(run_extract(&opts)).unwrap();
+        let plus_lines: Vec<&str> = output.lines().filter(|line| line.starts_with('+')).collect();
+        assert_eq!(plus_lines, vec!["+record=5", "+record=7"]);
+    }
+
+    #[test]
+    fn test_empty_fastq() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let input = write_fastq(dir.path(), &[], 3, 50);
+
+        // the index has only the final entry, and the BGZF file only the end-of-file block
+        let index = FastqIndex::read(&dir.path().join("test.fastq.gz.fqi")).unwrap();
+        assert_eq!(index.total_records, 0);
+        assert_eq!(index.entries.len(), 1);
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        assert_eq!(gzi.entries, vec![BgzfIndexOffset::default()]);
+        assert_eq!(bgzf_crc32(&mut File::open(&input).unwrap()).unwrap(), (0, 0));
+
+        // any range is out of range
+        captured_logs();
+        for (start, end) in [(1, 1), (1, 10), (5, 7)] {
+            let opts = Opts { start: Some(start), end: Some(end), ..default_opts(&input) };
+            assert!(run_extract(&opts).is_empty());
+            assert_eq!(
+                captured_logs(),
+                vec![format!(
+                    "No records to extract, as records {} to {} are out of range (total \
+                     records: 0)",
+                    start, end
+                )]
+            );
+        }
+        let opts = Opts { records: Some("1,2".to_string()), ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        assert_eq!(captured_logs().len(), 2);
+
+        // other modes write nothing (or nothing but their header)
+        let opts = Opts { target_bases: Some(10), ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        let opts = Opts { all: true, ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        let opts = Opts { byte_start: Some(0), byte_end: Some(10), ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        let opts = Opts { start: Some(1), estimate: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b"num_bytes\t0\nselected_records\t0\noutput_bytes\t0\n");
+        let opts = Opts { start: Some(1), raw_blocks: true, ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        let region_output = dir.path().join("region.fastq");
+        let opts = Opts {
+            region: vec!["1-2".to_string()],
+            output: vec![region_output.clone()],
+            ..default_opts(&input)
+        };
+        assert!(run_extract(&opts).is_empty());
+        assert!(fs::read(&region_output).unwrap().is_empty());
+
+        // an empty slice, and its indexes, are written
+        let slice = dir.path().join("slice.fastq.gz");
+        let opts = Opts {
+            start: Some(1),
+            compress: true,
+            reindex: true,
+            output: vec![slice.clone()],
+            ..default_opts(&input)
+        };
+        fs::write(&slice, run_extract(&opts)).unwrap();
+        assert_eq!(
+            FastqIndex::read(&dir.path().join("slice.fastq.gz.fqi")).unwrap(),
+            FastqIndex::new(3)
+        );
+        for full in [false, true] {
+            verify(&verify::Opts { input: slice.clone(), full }).unwrap();
+            verify(&verify::Opts { input: input.clone(), full }).unwrap();
+        }
+        let err = locate(&input, UncompressedOffset(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Byte 0 is past the end of the FASTQ (0 bytes)");
+        captured_logs();
+    }
+
+    #[test]
+    fn test_extract_truncated_block() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // cut the last data block mid-way, dropping the end-of-file block
+        let bgzf = fs::read(&input).unwrap();
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        let last_block = gzi.entries.last().unwrap().compressed_offset.0 as usize;
+        let block_size = bgzf.len() - BGZF_EOF.len() - last_block;
+        fs::write(&input, &bgzf[..last_block + block_size / 2]).unwrap();
+        let expected = format!(
+            "BGZF block at compressed offset {} is truncated: found {} of {} bytes",
+            last_block,
+            block_size / 2,
+            block_size
+        );
+
+        // records before the last block are extracted as before
+        let opts = Opts { start: Some(1), end: Some(3), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..3]));
+
+        for opts in [
+            Opts { start: Some(8), end: Some(10), ..default_opts(&input) },
+            Opts { all: true, ..default_opts(&input) },
+            Opts { start: Some(10), raw_blocks: true, ..default_opts(&input) },
+        ] {
+            // the error may be wrapped by the FASTQ parser
+            let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+            assert!(format!("{:#}", err).contains(&expected), "{:#}", err);
+        }
+
+        // a truncated header
+        fs::write(&input, &bgzf[..last_block + 5]).unwrap();
+        let opts = Opts { start: Some(10), end: Some(10), ..default_opts(&input) };
+        let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+        let expected = format!(
+            "BGZF block header at compressed offset {} is truncated: found 5 of 18 bytes",
+            last_block
+        );
+        assert!(format!("{:#}", err).contains(&expected), "{:#}", err);
+    }
+
+    #[test]
+    fn test_extract_first_record() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        let opts = Opts { start: Some(1), end: Some(1), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..1]));
+    }
+
+    #[test]
+    fn test_extract_all_ranges_multi_block() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        // records span block boundaries
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        for start in 1..=records.len() {
+            for end in start..=records.len() {
+                let opts = Opts {
+                    start: Some(start as i64),
+                    end: Some(end as i64),
+                    ..default_opts(&input)
+                };
+                assert_eq!(run_extract(&opts), to_fastq(&records[start - 1..end]));
+            }
+        }
+    }
+
+    fn range(start_byte: u64, end_byte: u64) -> FastqIndexRange {
+        FastqIndexRange {
+            start_byte: UncompressedOffset(start_byte),
+            end_byte: UncompressedOffset(end_byte),
+            leading_records: 0,
+            trailing_records: 0,
+            total_records: 0,
+        }
+    }
+
+    #[test]
+    fn test_find_blocks() {
+        let entries: Vec<BgzfIndexOffset> = (0..4)
+            .map(|i| BgzfIndexOffset {
+                compressed_offset: CompressedOffset(i * 40),
+                uncompressed_offset: UncompressedOffset(i * 100),
+            })
+            .collect();
+        let gzi = BgzfIndex { num_entries: entries.len() as u64, entries: entries.clone() };
+
+        // the first block only, starting at the synthetic first entry
+        assert_eq!(find_blocks(&gzi, &range(0, 100)), (entries[0], 1));
+        assert_eq!(find_blocks(&gzi, &range(0, 50)), (entries[0], 1));
+        // the first two blocks
+        assert_eq!(find_blocks(&gzi, &range(0, 150)), (entries[0], 2));
+        // starting exactly at a block boundary does not read the previous block
+        assert_eq!(find_blocks(&gzi, &range(100, 200)), (entries[1], 1));
+        // mid-block to mid-block
+        assert_eq!(find_blocks(&gzi, &range(120, 250)), (entries[1], 2));
+        // through the last block
+        assert_eq!(find_blocks(&gzi, &range(120, 350)), (entries[1], 3));
+    }
+
+    #[test]
+    fn test_extract_end_on_block_boundary() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        // each block holds exactly two records (26 bytes each), so ranges end on block boundaries
+        let input = write_fastq(dir.path(), &records, 1, 52);
+        let fastq_index = FastqIndex::read(&dir.path().join("test.fastq.gz.fqi")).unwrap();
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+
+        for (start, end, expected_blocks) in [(1, 2, 1), (1, 4, 2), (2, 4, 2), (3, 4, 1), (5, 8, 2)]
+        {
+            let fqi_range = fastq_index.range(RecordNum(start), RecordNum(end)).unwrap();
+            assert!(gzi
+                .entries
+                .iter()
+                .any(|entry| entry.uncompressed_offset == fqi_range.end_byte));
+
+            // the block containing the last record is read, but not the block that follows
+            let (_, num_blocks) = find_blocks(&gzi, &fqi_range);
+            assert_eq!(num_blocks, expected_blocks, "start: {} end: {}", start, end);
+
+            let expected = to_fastq(&records[start as usize - 1..end as usize]);
+            let (start, end) = (Some(start as i64), Some(end as i64));
+            assert_eq!(run_extract(&Opts { start, end, ..default_opts(&input) }), expected);
+        }
+    }
+
+    #[test]
+    fn test_find_blocks_prepend_zero() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 1, 50);
+        let gzi_path = format!("{}.gzi", input.to_string_lossy());
+        let fastq_index = FastqIndex::read(&dir.path().join("test.fastq.gz.fqi")).unwrap();
+        let bgzf = fs::read(&input).unwrap();
+
+        for prepend_zero in [true, false] {
+            let gzi = BgzfIndex::from_with_options(gzi_path.clone(), prepend_zero).unwrap();
+            assert_eq!(gzi.entries.len() as u64, gzi.num_entries);
+            assert_eq!(gzi.entries[0] == BgzfIndexOffset::default(), prepend_zero);
+
+            // the first record (26 bytes) lies in the first block, at the start of the file
+            let fqi_range = fastq_index.range(RecordNum(1), RecordNum(1)).unwrap();
+            let (start_entry, num_blocks) = find_blocks(&gzi, &fqi_range);
+            assert_eq!((start_entry, num_blocks), (BgzfIndexOffset::default(), 1));
+
+            // the first two records (52 bytes) span the first two blocks
+            let fqi_range = fastq_index.range(RecordNum(1), RecordNum(2)).unwrap();
+            assert_eq!(find_blocks(&gzi, &fqi_range), (BgzfIndexOffset::default(), 2));
+            let mut reader = BgzfReader::new(
+                Cursor::new(&bgzf[..]),
+                fqi_range.start_byte,
+                start_entry,
+                fqi_range.end_byte,
+            )
+            .unwrap();
+            let mut output: Vec<u8> = vec![];
+            let mut record_writer = RecordWriter::new(
+                &mut output,
+                &ExtractConfig::default(),
+                CheckpointSpacing::Records(1),
+            )
+            .unwrap();
+            write_records(&mut reader, &fqi_range, 1, 2, 1, &mut record_writer).unwrap();
+            assert_eq!(output, to_fastq(&records[0..2]));
+        }
+    }
+
+    #[test]
+    fn test_extract_verbose() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        // not verbose
+        let opts = Opts { start: Some(5), end: Some(5), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[4..5]));
+        assert!(captured_logs().is_empty());
+
+        // verbose, with the same output
+        let opts = Opts { verbose: true, ..opts };
+        assert_eq!(run_extract(&opts), to_fastq(&records[4..5]));
+        let logs = captured_logs();
+        assert_eq!(logs.len(), 3);
+        assert_eq!(
+            logs[0],
+            "Uncompressed bytes [78, 156) contain 1 selected records, with 1 leading and 1 \
+             trailing records"
+        );
+        assert_eq!(
+            logs[1],
+            "Reading 2 BGZF block(s) starting at compressed offset 0 (uncompressed offset 0)"
+        );
+        assert!(logs[2].ends_with(&format!("bgzip -b 78 -s 78 {}", input.display())));
+    }
+
+    #[test]
+    fn test_extract_byte_range() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        // each record is 26 bytes, and use small blocks so ranges span many blocks
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let byte_range = |byte_start: u64, byte_end: u64| Opts {
+            byte_start: Some(byte_start),
+            byte_end: Some(byte_end),
+            ..default_opts(&input)
+        };
+        let record_range = |start: i64, end: i64| Opts {
+            start: Some(start),
+            end: Some(end),
+            ..default_opts(&input)
+        };
+
+        // exactly records 4-6
+        assert_eq!(run_extract(&byte_range(78, 156)), run_extract(&record_range(4, 6)));
+        // partial records at either end are dropped
+        assert_eq!(run_extract(&byte_range(70, 170)), run_extract(&record_range(4, 6)));
+        assert_eq!(run_extract(&byte_range(79, 155)), run_extract(&record_range(5, 5)));
+        // all records, including past the end of the file
+        assert_eq!(run_extract(&byte_range(0, 261)), to_fastq(&records));
+        assert_eq!(run_extract(&byte_range(0, 10_000)), to_fastq(&records));
+        // no complete records
+        assert!(run_extract(&byte_range(79, 120)).is_empty());
+        assert!(run_extract(&byte_range(1_000, 10_000)).is_empty());
+
+        // the range must not be empty
+        let result = extract(&to_config(&byte_range(100, 100)), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().contains("--byte-start must be less than"));
+    }
+
+    #[test]
+    fn test_byte_range_args() {
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        let opts = parse(&["--byte-start", "1", "--byte-end", "2"]).unwrap();
+        assert_eq!((opts.byte_start, opts.byte_end), (Some(1), Some(2)));
+        // both must be given
+        assert!(parse(&["--byte-start", "1"]).is_err());
+        assert!(parse(&["--byte-end", "2"]).is_err());
+        // and not with a record-based option
+        assert!(parse(&["--byte-start", "1", "--byte-end", "2", "--start", "1"]).is_err());
+        assert!(parse(&["--byte-start", "1", "--byte-end", "2", "--target-bases", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_extract_trailing_blank_lines() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        // add trailing blank lines to the compressed FASTQ, but not the index
+        let mut fastq = to_fastq(&records);
+        fastq.extend_from_slice(b"\n\n");
+        let (bgzf, gzi) = bgzip(&fastq, 50);
+        fs::write(&input, bgzf).unwrap();
+        fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+
+        let opts = Opts { start: Some(10), end: Some(10), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[9..]));
+        let opts = Opts { start: Some(9), target_bases: Some(1_000), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[8..]));
+    }
+
+    #[test]
+    fn test_extract_mmap() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        for (start, end) in [(1, 1), (2, 7), (4, 10)] {
+            let opts = Opts { start: Some(start), end: Some(end), ..default_opts(&input) };
+            let expected = run_extract(&opts);
+            assert_eq!(run_extract(&Opts { mmap: true, ..opts }), expected);
+        }
+        let opts = Opts { records: Some("9,2,5".to_string()), ..default_opts(&input) };
+        let expected = run_extract(&opts);
+        assert_eq!(run_extract(&Opts { mmap: true, ..opts }), expected);
+    }
+
+    #[test]
+    fn test_extract_header_only_index() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 0, 50);
+        let opts = Opts { start: Some(4), end: Some(6), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[3..6]));
+        let opts = Opts { records: Some("10,1".to_string()), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&[records[0].clone(), records[9].clone()]));
+    }
+
+    #[test]
+    fn test_extract_stats() {
+        let dir = TempDir::new().unwrap();
+        // records 1-9 are 26 bytes, and record 10 is 27 bytes
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let stats = dir.path().join("stats.tsv");
+        let expected = |records_written: usize, bytes_written: usize, start: &str, end: &str| {
+            format!(
+                "records_written\t{}\nbytes_written\t{}\nsource_start_byte\t{}\n\
+                 source_end_byte\t{}\n",
+                records_written, bytes_written, start, end
+            )
+        };
+
+        // the records read span the indexed chunks of records 4-6 and 7-9
+        let opts = Opts {
+            start: Some(5),
+            end: Some(8),
+            stats: Some(stats.clone()),
+            ..default_opts(&input)
+        };
+        assert_eq!(run_extract(&opts), to_fastq(&records[4..8]));
+        assert_eq!(fs::read_to_string(&stats).unwrap(), expected(4, 104, "78", "234"));
+
+        // the bytes written are those output, so exclude the trailing newline when it is stripped
+        let opts = Opts { no_trailing_newline: true, ..opts };
+        assert_eq!(run_extract(&opts).len(), 103);
+        assert_eq!(fs::read_to_string(&stats).unwrap(), expected(4, 103, "78", "234"));
+
+        // the source bytes span every record read
+        let opts = Opts {
+            records: Some("10,2".to_string()),
+            stats: Some(stats.clone()),
+            ..default_opts(&input)
+        };
+        run_extract(&opts);
+        assert_eq!(fs::read_to_string(&stats).unwrap(), expected(2, 53, "0", "261"));
+        let opts = Opts { sort_output: false, ..opts };
+        run_extract(&opts);
+        assert_eq!(fs::read_to_string(&stats).unwrap(), expected(2, 53, "0", "261"));
+
+        // no records
+        let opts = Opts {
+            start: Some(11),
+            end: Some(12),
+            stats: Some(stats.clone()),
+            ..default_opts(&input)
+        };
+        assert!(run_extract(&opts).is_empty());
+        assert_eq!(fs::read_to_string(&stats).unwrap(), expected(0, 0, "NA", "NA"));
+    }
+
+    #[test]
+    fn test_extract_no_trailing_newline() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // a single record ends with a newline by default
+        let opts = Opts { start: Some(5), end: Some(5), ..default_opts(&input) };
+        let expected = to_fastq(&records[4..5]);
+        let output = run_extract(&opts);
+        assert_eq!(output, expected);
+        assert_eq!(output.last(), Some(&b'\n'));
+        let output = run_extract(&Opts { no_trailing_newline: true, ..opts });
+        assert_eq!(output, expected[..expected.len() - 1]);
+        assert_eq!(output.last(), Some(&b'I'));
+
+        // only the last of several records, and the last line of a FASTA record, lose the newline
+        for opts in [
+            Opts { start: Some(2), end: Some(9), ..default_opts(&input) },
+            Opts { records: Some("10,1,5".to_string()), ..default_opts(&input) },
+            Opts {
+                start: Some(1),
+                end: Some(3),
+                fasta: true,
+                wrap: Some(3),
+                ..default_opts(&input)
+            },
+        ] {
+            let expected = run_extract(&opts);
+            let output = run_extract(&Opts { no_trailing_newline: true, ..opts });
+            assert_eq!(output, expected[..expected.len() - 1]);
+        }
+
+        // no records
+        let opts = Opts {
+            start: Some(20),
+            end: Some(30),
+            no_trailing_newline: true,
+            ..default_opts(&input)
+        };
+        assert!(run_extract(&opts).is_empty());
+    }
+
+    #[test]
+    fn test_extract_strict_bounds() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // by default, the end is clamped to the last record, and records past it are skipped
+        let opts = Opts { start: Some(8), end: Some(20), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[7..10]));
+        let opts = Opts { start: Some(11), end: Some(12), ..default_opts(&input) };
+        assert!(run_extract(&opts).is_empty());
+        let opts = Opts { records: Some("2,11".to_string()), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..2]));
+
+        // with --strict-bounds, records past the last record are an error
+        let strict_error = |opts: Opts| {
+            let opts = Opts { strict_bounds: true, ..opts };
+            extract(&to_config(&opts), &mut vec![]).unwrap_err().to_string()
+        };
+        assert_eq!(
+            strict_error(Opts { start: Some(8), end: Some(20), ..default_opts(&input) }),
+            "The end record (20) is past the last record (total records: 10)"
+        );
+        assert_eq!(
+            strict_error(Opts { start: Some(11), end: Some(12), ..default_opts(&input) }),
+            "Record 11 is past the last record (total records: 10)"
+        );
+        assert_eq!(
+            strict_error(Opts { records: Some("2,11".to_string()), ..default_opts(&input) }),
+            "Record 11 is past the last record (total records: 10)"
+        );
+        // and no records are written before the record past the last record is found
+        let opts =
+            Opts { records: Some("2,11".to_string()), strict_bounds: true, ..default_opts(&input) };
+        let mut output: Vec<u8> = vec![];
+        assert!(extract(&to_config(&opts), &mut output).is_err());
+        assert!(output.is_empty());
+        let opts =
+            Opts { start: Some(8), end: Some(10), strict_bounds: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[7..10]));
+    }
+
+    #[test]
+    fn test_extract_sanity_check() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // shift the checkpoint before record 4 by one byte, into the record's name
+        let fqi_path = dir.path().join("test.fastq.gz.fqi");
+        let index = FastqIndex::read(&fqi_path).unwrap();
+        let mut entries = index.entries.clone();
+        entries[1].total_bytes += 1;
+        FastqIndex { entries, ..index }.write(&fqi_path);
+
+        let message = "The FASTQ index appears misaligned; the stream did not start at a record \
+                       boundary (found 'r' rather than '@' at uncompressed byte 79)";
+        for opts in [
+            Opts { start: Some(4), end: Some(5), ..default_opts(&input) },
+            Opts { records: Some("5".to_string()), ..default_opts(&input) },
+            Opts { start: Some(4), target_bases: Some(7), ..default_opts(&input) },
+        ] {
+            let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{}", err);
+
+            // the records are still parsed without the check
+            let opts = Opts { no_sanity_check: true, ..opts };
+            let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+            assert!(!err.to_string().contains("misaligned"), "{}", err);
+        }
+
+        // records read from the following checkpoint are unaffected
+        let opts = Opts { start: Some(7), end: Some(8), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[6..8]));
+
+        // a FASTQ that starts with a UTF-8 byte order mark is reported as such
+        let (bgzf, gzi) = bgzip(&[&b"\xef\xbb\xbf"[..], &to_fastq(&records)].concat(), 50);
+        fs::write(&input, bgzf).unwrap();
+        fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+        let opts = Opts { start: Some(1), end: Some(1), ..default_opts(&input) };
+        let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("byte order mark (BOM)"), "{}", err);
+    }
+
+    #[test]
+    fn test_extract_malformed_record() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // replace the '@' of record 5, so that its offsets are unchanged
+        let mut fastq = to_fastq(&records);
+        fastq[4 * 26] = b'X';
+        let (bgzf, gzi) = bgzip(&fastq, 50);
+        fs::write(&input, bgzf).unwrap();
+        fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+        for opts in [
+            Opts { start: Some(4), end: Some(6), ..default_opts(&input) },
+            Opts { start: Some(4), target_bases: Some(14), ..default_opts(&input) },
+        ] {
+            let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Could not parse FASTQ record #5; its header does not start with '@', so the \
+                 FASTQ may be corrupt"
+            );
+        }
+        let err = for_each_record(&input, 5, 5, |_| ()).unwrap_err();
+        assert!(err.to_string().starts_with("Could not parse FASTQ record #5"), "{}", err);
+
+        // the records in other chunks are extracted
+        let opts = Opts { start: Some(1), end: Some(3), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..3]));
+
+        // a missing '+' line is reported as for `index`, with the record number
+        fastq[4 * 26] = b'@';
+        fastq[4 * 26 + 16] = b'X';
+        let (bgzf, gzi) = bgzip(&fastq, 50);
+        fs::write(&input, bgzf).unwrap();
+        fs::write(dir.path().join("test.fastq.gz.gzi"), gzi).unwrap();
+        let opts = Opts { start: Some(4), end: Some(6), ..default_opts(&input) };
+        let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not parse FASTQ record #5; records must be four lines, as multi-line (wrapped) \
+             FASTQ is not supported"
+        );
+        let err = err.downcast_ref::<seq_io::fastq::Error>().unwrap();
+        assert!(matches!(err, seq_io::fastq::Error::InvalidSep { .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_extract_max_output_records() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let opts = || Opts {
+            start: Some(1),
+            end: Some(10),
+            max_output_records: Some(5),
+            ..default_opts(&input)
+        };
+
+        // the requests within the cap are extracted, counting every --step record
+        assert_eq!(run_extract(&Opts { end: Some(5), ..opts() }), to_fastq(&records[0..5]));
+        let stepped: Vec<OwnedRecord> = records.iter().step_by(3).cloned().collect();
+        assert_eq!(run_extract(&Opts { step: 3, ..opts() }), to_fastq(&stepped));
+
+        // keep only the header of the first BGZF block, so that the request fails if the blocks
+        // are read before the number of records is checked
+        let bgzf = fs::read(&input).unwrap();
+        fs::write(&input, &bgzf[..18]).unwrap();
+        let err = extract(&to_config(&opts()), &mut vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "10 records would be extracted, more than --max-output-records (5); narrow the range, \
+             or add --yes to extract them anyway"
+        );
+        let output = dir.path().join("region.fastq");
+        let regions = Opts {
+            start: None,
+            end: None,
+            region: vec!["1-3".to_string(), "8-20".to_string()],
+            output: vec![output.clone(), output],
+            ..opts()
+        };
+        let err = extract(&to_config(&regions), &mut vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("6 records would be extracted"), "{}", err);
+
+        // the records extracted by --all or a byte range are not counted, so cannot be capped
+        let all = Opts { start: None, end: None, all: true, ..opts() };
+        let byte_range =
+            Opts { start: None, end: None, byte_start: Some(0), byte_end: Some(10), ..opts() };
+        for opts in [all, byte_range] {
+            let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+            assert!(err.to_string().starts_with("--max-output-records cannot be used"), "{}", err);
+        }
+
+        // with --yes, the records are extracted after a warning
+        fs::write(&input, &bgzf).unwrap();
+        captured_logs();
+        assert_eq!(run_extract(&Opts { yes: true, ..opts() }), to_fastq(&records));
+        assert_eq!(
+            captured_logs(),
+            vec!["Extracting 10 records, more than --max-output-records (5)".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_extract_no_verify_crc() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // the same output with and without checking the CRC32s
+        for opts in [
+            Opts { start: Some(2), end: Some(9), ..default_opts(&input) },
+            Opts { records: Some("10,1,5".to_string()), ..default_opts(&input) },
+            Opts { start: Some(4), target_bases: Some(10), ..default_opts(&input) },
+            Opts { byte_start: Some(30), byte_end: Some(200), ..default_opts(&input) },
+            Opts { all: true, ..default_opts(&input) },
+        ] {
+            let expected = run_extract(&opts);
+            assert!(captured_logs().is_empty());
+            let opts = Opts { no_verify_crc: true, ..opts };
+            assert_eq!(run_extract(&opts), expected);
+            let logs = captured_logs();
+            assert_eq!(logs.len(), 1);
+            assert!(logs[0].contains("--no-verify-crc"));
+        }
+
+        // corrupt the CRC32 in the footer of the first block
+        let mut bgzf = fs::read(&input).unwrap();
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        let footer = gzi.entries[1].compressed_offset.0 as usize - 8;
+        bgzf[footer] ^= 0xff;
+        fs::write(&input, bgzf).unwrap();
+        let opts = Opts { start: Some(1), end: Some(1), ..default_opts(&input) };
+        assert!(extract(&to_config(&opts), &mut vec![]).is_err());
+        let opts = Opts { no_verify_crc: true, ..opts };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..1]));
+    }
+
+    #[test]
+    fn test_extract_source_crc32() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let mut records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let fqi_path = dir.path().join("test.fastq.gz.fqi");
+        let mut crc = Crc::new();
+        crc.update(&to_fastq(&records));
+        let index = FastqIndex::read(&fqi_path).unwrap();
+        FastqIndex { source_crc32: Some(crc.sum()), ..index }.write(&fqi_path);
+
+        // the BGZF file matches the index
+        let mut file = File::open(&input).unwrap();
+        let (crc32, num_bytes) = bgzf_crc32(&mut file).unwrap();
+        assert_eq!((crc32, num_bytes), (crc.sum(), 261));
+        let opts = Opts { start: Some(2), end: Some(3), strict: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..3]));
+        assert!(captured_logs().is_empty());
+
+        // modify a record without changing its length
+        records[7].seq = b"TTTTTTT".to_vec();
+        let (bgzf, _) = bgzip(&to_fastq(&records), 50);
+        fs::write(&input, bgzf).unwrap();
+
+        // not checked by default
+        let opts = Opts { strict: false, ..opts };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..3]));
+        assert!(captured_logs().is_empty());
+
+        // warn when verifying the source
+        let opts = Opts { verify_source: true, ..opts };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..3]));
+        let logs = captured_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("does not match its index"));
+
+        // fail when strict
+        let opts = Opts { strict: true, verify_source: false, ..opts };
+        let result = extract(&to_config(&opts), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().contains("the index may be stale"));
+    }
+
+    #[test]
+    fn test_extract_estimated_bytes() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let opts = Opts { start: Some(2), end: Some(3), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..3]));
+        assert!(captured_logs().is_empty());
+
+        // an index built from the parsed records is flagged, and extracting warns
+        let fqi_path = dir.path().join("test.fastq.gz.fqi");
+        let index = FastqIndex::from(records.iter().cloned().map(Ok), 3, &mut None).unwrap();
+        assert!(index.estimated_bytes);
+        index.write(&fqi_path);
+        assert_eq!(run_extract(&opts), to_fastq(&records[1..3]));
+        let logs = captured_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].starts_with("This index has estimated byte offsets"));
+    }
+
+    #[test]
+    fn test_extract_records_inline() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        // out of order, duplicated, and out of range records
+        let opts = Opts { records: Some("9,2,4,2,11,0".to_string()), ..default_opts(&input) };
+        let expected = to_fastq(&[record(2), record(4), record(9)]);
+        assert_eq!(run_extract(&opts), expected);
+    }
+
+    #[test]
+    fn test_extract_records_sort_output() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        // in file order, by default
+        let opts = Opts { records: Some("5,1,3".to_string()), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&[record(1), record(3), record(5)]));
+
+        // in the order given
+        let opts = Opts { sort_output: false, ..opts };
+        assert_eq!(run_extract(&opts), to_fastq(&[record(5), record(1), record(3)]));
+
+        // repeats are kept, and out of range records skipped
+        let opts = Opts { records: Some("9,2,11,9,2".to_string()), ..opts };
+        let expected = to_fastq(&[record(9), record(2), record(9), record(2)]);
+        assert_eq!(run_extract(&opts), expected);
+
+        let opts = Opts::parse_from(["extract", "-f", "x.fastq.gz", "--records", "5,1,3"]);
+        assert!(opts.sort_output);
+        let args = ["extract", "-f", "x.fastq.gz", "--records", "5,1,3", "--sort-output", "false"];
+        assert!(!Opts::parse_from(args).sort_output);
+    }
+
+    #[test]
+    fn test_extract_records_from_file() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        let path = dir.path().join("records.txt");
+        fs::write(&path, "10\n1\n\n5\n").unwrap();
+        let opts = Opts { records: Some(format!("@{}", path.display())), ..default_opts(&input) };
+        let expected = to_fastq(&[record(1), record(5), record(10)]);
+        assert_eq!(run_extract(&opts), expected);
+    }
+
+    #[test]
+    fn test_extract_target_bases() {
+        let dir = TempDir::new().unwrap();
+        // records of length 5, 10, 15, ...
+        let records: Vec<OwnedRecord> = (1..=10)
+            .map(|i| OwnedRecord {
+                head: format!("read-{}", i).into_bytes(),
+                seq: vec![b'A'; 5 * i],
+                qual: vec![b'I'; 5 * i],
+            })
+            .collect();
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        // 5 + 10 = 15 bases is below the target, so the third record crosses the threshold
+        let opts = Opts { target_bases: Some(20), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..3]));
+
+        // exactly reaching the target stops
+        let opts = Opts { target_bases: Some(15), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[0..2]));
+
+        // start part way through the file
+        let opts = Opts { start: Some(4), target_bases: Some(20), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[3..4]));
+
+        // more bases than in the file
+        let opts = Opts { start: Some(9), target_bases: Some(1000), ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records[8..10]));
+    }
+
+    #[test]
+    fn test_extract_base_range() {
+        let dir = TempDir::new().unwrap();
+        // the records span bases [0, 3), [3, 8), [8, 9), [9, 13), [13, 19), [19, 21), and [21, 28)
+        let records: Vec<OwnedRecord> = [3, 5, 1, 4, 6, 2, 7]
+            .iter()
+            .enumerate()
+            .map(|(i, length)| OwnedRecord {
+                head: format!("read-{}", i + 1).into_bytes(),
+                seq: vec![b'A'; *length],
+                qual: vec![b'I'; *length],
+            })
+            .collect();
+        let input = write_fastq(dir.path(), &records, 2, 50);
+        let base_range = |base_start: u64, base_end: u64| Opts {
+            base_start: Some(base_start),
+            base_end: Some(base_end),
+            ..default_opts(&input)
+        };
+
+        assert_eq!(run_extract(&base_range(0, 1)), to_fastq(&records[0..1]));
+        assert_eq!(run_extract(&base_range(3, 9)), to_fastq(&records[1..3]));
+        // partially overlapping records are output whole
+        assert_eq!(run_extract(&base_range(7, 14)), to_fastq(&records[1..5]));
+        assert_eq!(run_extract(&base_range(20, 100)), to_fastq(&records[5..7]));
+        assert_eq!(run_extract(&base_range(0, 28)), to_fastq(&records));
+        assert!(run_extract(&base_range(28, 30)).is_empty());
+
+        let result = extract(&to_config(&base_range(5, 5)), &mut Vec::new());
+        assert!(result.is_err());
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        assert!(parse(&["--base-start", "1"]).is_err());
+        assert!(parse(&["--base-start", "1", "--base-end", "2", "-s", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_extract_records_invalid() {
+        let dir = TempDir::new().unwrap();
+        let input = write_fastq(dir.path(), &records(10), 3, 100);
+
+        // the records are parsed when building the configuration
+        let opts = Opts { records: Some("1,two".to_string()), ..default_opts(&input) };
+        assert!(ExtractConfig::try_from(&opts).is_err());
+        assert!(run(&opts).is_err());
+    }
+
+    #[test]
+    fn test_extract_regions() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let outputs = vec![dir.path().join("a.fastq"), dir.path().join("b.fastq")];
+
+        for mmap in [false, true] {
+            let opts = Opts {
+                region: vec!["2-7".to_string(), "9".to_string()],
+                output: outputs.clone(),
+                threads: 2,
+                mmap,
+                ..default_opts(&input)
+            };
+            assert!(run_extract(&opts).is_empty());
+
+            // compare to extracting each region sequentially
+            for (output, (start, end)) in outputs.iter().zip([(2, 7), (9, 9)]) {
+                let opts = Opts { start: Some(start), end: Some(end), ..default_opts(&input) };
+                assert_eq!(fs::read(output).unwrap(), run_extract(&opts));
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_regions_keep_going() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let outputs: Vec<PathBuf> =
+            ["a", "b", "c"].iter().map(|name| dir.path().join(format!("{}.fastq", name))).collect();
+        // records are 1-based, so the second region is out of range
+        let opts = Opts {
+            region: vec!["2-7".to_string(), "0-2".to_string(), "9".to_string()],
+            output: outputs.clone(),
+            threads: 2,
+            ..default_opts(&input)
+        };
+
+        // without --keep-going, nothing is extracted
+        let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Regions are 1-based: 0-2");
+        assert!(outputs.iter().all(|output| !output.exists()));
+
+        // the other regions are extracted, but the failure is still reported (and logged by the
+        // thread that extracted the region)
+        let opts = Opts { keep_going: true, ..opts };
+        let err = extract(&to_config(&opts), &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 regions failed");
+        assert_eq!(fs::read(&outputs[0]).unwrap(), to_fastq(&records[1..7]));
+        assert!(!outputs[1].exists());
+        assert_eq!(fs::read(&outputs[2]).unwrap(), to_fastq(&records[8..9]));
+
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        assert!(parse(&["--keep-going", "-s", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_extract_regions_resume() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let outputs: Vec<PathBuf> =
+            ["a", "b", "c"].iter().map(|name| dir.path().join(format!("{}.fastq", name))).collect();
+        let opts = || Opts {
+            region: vec!["1-3".to_string(), "4-6".to_string(), "7-10".to_string()],
+            output: outputs.clone(),
+            threads: 2,
+            ..default_opts(&input)
+        };
+        extract(&to_config(&opts()), &mut vec![]).unwrap();
+        let expected = [&records[0..3], &records[3..6], &records[6..10]].map(to_fastq);
+        for (output, expected) in outputs.iter().zip(&expected) {
+            assert_eq!(&fs::read(output).unwrap(), expected);
+            assert!(!output.with_extension("fastq.tmp").exists());
+        }
+
+        // only the missing output is extracted, so an existing output is left as is
+        fs::write(&outputs[0], b"unchanged").unwrap();
+        fs::remove_file(&outputs[2]).unwrap();
+        let opts = Opts { resume: true, ..opts() };
+        extract(&to_config(&opts), &mut vec![]).unwrap();
+        assert_eq!(fs::read(&outputs[0]).unwrap(), b"unchanged");
+        assert_eq!(fs::read(&outputs[1]).unwrap(), expected[1]);
+        assert_eq!(fs::read(&outputs[2]).unwrap(), expected[2]);
+
+        // without --resume, every output is overwritten
+        extract(&to_config(&Opts { resume: false, ..opts }), &mut vec![]).unwrap();
+        assert_eq!(fs::read(&outputs[0]).unwrap(), expected[0]);
+    }
+
+    #[test]
+    fn test_extract_regions_compress() {
+        let dir = TempDir::new().unwrap();
+        let records = records(8);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let outputs: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|name| dir.path().join(format!("{}.fastq.gz", name)))
+            .collect();
+        let opts = Opts {
+            region: vec!["1-3".to_string(), "4-6".to_string(), "7-8".to_string()],
+            output: outputs.clone(),
+            compress: true,
+            reindex: true,
+            ..default_opts(&input)
+        };
+        let mut writer = vec![];
+        extract(&to_config(&opts), &mut writer).unwrap();
+        assert!(writer.is_empty());
+
+        // each shard is compressed and indexed, so it can itself be extracted from
+        for (output, expected) in
+            outputs.iter().zip([&records[0..3], &records[3..6], &records[6..8]])
+        {
+            let fqi_path = PathBuf::from(format!("{}.fqi", output.display()));
+            let expected_index = FastqIndex::from_raw_reader(&to_fastq(expected)[..], 3, &mut None);
+            assert_eq!(FastqIndex::read(&fqi_path).unwrap(), expected_index.unwrap());
+            assert!(PathBuf::from(format!("{}.gzi", output.display())).exists());
+            let opts = Opts { start: Some(1), end: Some(1), ..default_opts(output) };
+            assert_eq!(run_extract(&opts), to_fastq(&expected[0..1]));
+            assert_eq!(
+                run_extract(&Opts { all: true, ..default_opts(output) }),
+                to_fastq(expected)
+            );
+        }
+        // the outputs and their indexes were written to temporary files, which were renamed
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            assert!(!path.to_string_lossy().ends_with(".tmp"), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_extract_plan() {
+        let dir = TempDir::new().unwrap();
+        // 8 records of 26 bytes each
+        let records = records(8);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let outputs: Vec<PathBuf> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| dir.path().join(format!("{}.fastq", name)))
+            .collect();
+        let opts = Opts {
+            region: vec![
+                "1-3".to_string(),
+                "4-6".to_string(),
+                "7-9".to_string(),
+                "10-12".to_string(),
+            ],
+            output: outputs.clone(),
+            plan: true,
+            ..default_opts(&input)
+        };
+        let expected = format!(
+            "start_record\tend_record\tstart_byte\tend_byte\toutput\n\
+             1\t3\t0\t78\t{}\n\
+             4\t6\t78\t156\t{}\n\
+             7\t8\t156\t208\t{}\n\
+             10\t12\tNA\tNA\t{}\n",
+            outputs[0].display(),
+            outputs[1].display(),
+            outputs[2].display(),
+            outputs[3].display()
+        );
+        assert_eq!(String::from_utf8(run_extract(&opts)).unwrap(), expected);
+        // nothing is extracted
+        assert!(outputs.iter().all(|output| !output.exists()));
+
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        assert!(parse(&["--plan", "-s", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_record_range_args() {
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        // resolved against an index of 8 records
+        let range = |args: &[&str]| start_and_end(&to_config(&parse(args).unwrap()), 8);
+        let range_ok = |args: &[&str]| range(args).unwrap();
+        assert_eq!(range_ok(&["-s", "3", "-e", "5"]), (3, 5));
+        assert_eq!(range_ok(&["--start", "3", "--end", "5"]), (3, 5));
+        assert_eq!(range_ok(&["--start-record", "3", "--end-record", "5"]), (3, 5));
+        assert_eq!(range_ok(&["--start-record", "3", "-e", "5"]), (3, 5));
+        assert_eq!(range_ok(&["--end-record", "4"]), (4, 4));
+
+        // negative records count back from the last record
+        assert_eq!(range_ok(&["--start", "-2"]), (7, 7));
+        assert_eq!(range_ok(&["-s", "-2", "-e", "-1"]), (7, 8));
+        assert_eq!(range_ok(&["-s", "-8", "-e", "3"]), (1, 3));
+        // a missing value is not parsed as the start record
+        assert!(parse(&["-s", "-e", "5"]).is_err());
+        assert!(parse(&["--start", "--end", "5"]).is_err());
+        for (args, message) in [
+            (
+                &["-s", "0"][..],
+                "Records are numbered from 1 (or from -1 for the last record), found 0",
+            ),
+            (&["-s", "-9"], "Record -9 is before the first record (total records: 8)"),
+            (&["-s", "-1", "-e", "-2"], "--start must be less than or equal to --end"),
+        ] {
+            assert_eq!(range(args).unwrap_err().to_string(), message);
+        }
+
+        // a record range cannot be combined with other ways of selecting records
+        for other in
+            [&["--byte-start", "0", "--byte-end", "10"][..], &["--records", "1"], &["--all"]]
+        {
+            assert!(parse(&[&["--start-record", "3"], other].concat()).is_err());
+            assert!(parse(&[&["--end-record", "3"], other].concat()).is_err());
+        }
+        assert!(parse(&["--end-record", "3", "--region", "1-2", "--output", "a"]).is_err());
+        assert!(parse(&["--byte-end", "3", "--all"]).is_err());
+    }
+
+    #[test]
+    fn test_region_args() {
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        let opts =
+            parse(&["--region", "1-2", "--output", "a", "--region", "3", "--output", "b"]).unwrap();
+        assert_eq!(opts.region, vec!["1-2", "3"]);
+        assert_eq!(opts.output, vec![PathBuf::from("a"), PathBuf::from("b")]);
+        assert!(parse(&["--region", "1-2"]).is_err());
+        assert!(parse(&["--region", "1-2", "--output", "a", "--start", "1"]).is_err());
+        // without --region, there is at most one output, rather than falling back to stdout
+        let outputs = parse(&["-s", "1", "--output", "a", "--output", "b"]).unwrap();
+        assert_eq!(
+            run(&outputs).unwrap_err().to_string(),
+            "--output may only be given once without --region (2 outputs)"
+        );
+
+        assert_eq!(parse_region("3-5").unwrap(), (RecordNum(3), RecordNum(5)));
+        assert_eq!(parse_region("4").unwrap(), (RecordNum(4), RecordNum(4)));
+        assert!(parse_region("a-b").is_err());
+        assert!(check_region((RecordNum(3), RecordNum(5))).is_ok());
+        assert!(check_region((RecordNum(0), RecordNum(2))).is_err());
+        assert!(check_region((RecordNum(5), RecordNum(3))).is_err());
+
+        // the values are parsed when building the configuration
+        let config = to_config(&opts);
+        assert_eq!(
+            config.regions,
+            vec![(RecordNum(1), RecordNum(2)), (RecordNum(3), RecordNum(3))]
+        );
+        let opts = parse(&["--region", "2-x", "--output", "a"]).unwrap();
+        assert_eq!(ExtractConfig::try_from(&opts).unwrap_err().to_string(), "Invalid region: 2-x");
+        let config = to_config(&parse(&["--records", "3,1"]).unwrap());
+        assert_eq!(config.records, Some(vec![RecordNum(3), RecordNum(1)]));
+        let opts = parse(&["--records", "3,x"]).unwrap();
+        assert_eq!(
+            ExtractConfig::try_from(&opts).unwrap_err().to_string(),
+            "Invalid record number: x"
+        );
+    }
+
+    /// Returns a reader over all the blocks of the BGZF data, along with the BGZF index
+    fn bgzf_reader(bgzf: Vec<u8>, gzi: Vec<u8>) -> (BgzfReader<Cursor<Vec<u8>>>, BgzfIndex) {
+        let dir = TempDir::new().unwrap();
+        let gzi_path = dir.path().join("test.fastq.gz.gzi");
+        fs::write(&gzi_path, gzi).unwrap();
+        let gzi = BgzfIndex::from(gzi_path.to_string_lossy().to_string()).unwrap();
+        let reader = BgzfReader::new(
+            Cursor::new(bgzf),
+            UncompressedOffset(0),
+            gzi.entries[0],
+            UncompressedOffset(u64::MAX),
+        )
+        .unwrap();
+        (reader, gzi)
+    }
+
+    #[test]
+    fn test_bgzf_reader_eof() {
+        let fastq = to_fastq(&records(10));
+        let (bgzf, gzi) = bgzip(&fastq, 50);
+        let (mut reader, _) = bgzf_reader(bgzf, gzi);
+
+        // reads are short, returning at most the rest of the current block
+        let mut buf = vec![0u8; 1024];
+        assert_eq!(reader.read(&mut buf).unwrap(), 50);
+        assert_eq!(&buf[..50], &fastq[..50]);
+        assert_eq!(reader.read(&mut buf[..10]).unwrap(), 10);
+        assert_eq!(reader.read(&mut buf).unwrap(), 40);
+        assert_eq!(reader.read(&mut []).unwrap(), 0);
+
+        // the rest of the data, then zero bytes at EOF and thereafter
+        let mut rest: Vec<u8> = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &fastq[100..]);
+        for _ in 0..3 {
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_bgzf_reader_error() {
+        let fastq = to_fastq(&records(10));
+        let (mut bgzf, gzi) = bgzip(&fastq, 50);
+        let (_, index) = bgzf_reader(bgzf.clone(), gzi.clone());
+        // corrupt the CRC32 in the footer of the second block
+        let crc_offset = index.entries[2].compressed_offset.0 as usize - 8;
+        bgzf[crc_offset] ^= 0xff;
+        let (mut reader, _) = bgzf_reader(bgzf, gzi);
+
+        let mut buf = vec![0u8; 1024];
+        assert_eq!(reader.read(&mut buf).unwrap(), 50);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // a truncated block is also an error, as is a truncated block header
+        let (bgzf, gzi) = bgzip(&fastq, 50);
+        let (mut reader, _) = bgzf_reader(bgzf[..crc_offset].to_vec(), gzi.clone());
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+        let (mut reader, _) = bgzf_reader(bgzf[..crc_offset + 12].to_vec(), gzi);
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_extract_all() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        // the FASTQ index is not used
+        fs::remove_file(dir.path().join("test.fastq.gz.fqi")).unwrap();
+
+        let opts = Opts { all: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&records));
+        assert_eq!(run_extract(&Opts { mmap: true, ..opts }), to_fastq(&records));
+    }
+
+    #[test]
+    fn test_extract_fasta() {
+        let dir = TempDir::new().unwrap();
+        let mut records = records(10);
+        records[4].seq = b"ACGTACGTAC".to_vec();
+        records[4].qual = b"IIIIIIIIII".to_vec();
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        let opts = Opts { start: Some(4), end: Some(5), fasta: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b">read-4\nGATTACA\n>read-5\nACGTACGTAC\n");
+
+        let opts = Opts { wrap: Some(4), ..opts };
+        assert_eq!(run_extract(&opts), b">read-4\nGATT\nACA\n>read-5\nACGT\nACGT\nAC\n");
+
+        let opts = Opts { records: Some("9".to_string()), fasta: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b">read-9\nGATTACA\n");
+
+        let opts = Opts { start: Some(1), fasta: true, wrap: Some(0), ..default_opts(&input) };
+        assert!(extract(&to_config(&opts), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_extract_output_format() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let opts = || Opts { start: Some(4), end: Some(5), ..default_opts(&input) };
+
+        let expected: [(OutputFormat, &[u8]); 3] = [
+            (OutputFormat::Fastq, &to_fastq(&records[3..5])),
+            (OutputFormat::Fasta, b">read-4\nGATTACA\n>read-5\nGATTACA\n"),
+            (OutputFormat::Tsv, b"read-4\tGATTACA\tIIIIIII\nread-5\tGATTACA\tIIIIIII\n"),
+        ];
+        for (output_format, expected) in expected {
+            let opts = Opts { output_format, ..opts() };
+            assert_eq!(run_extract(&opts), expected, "format: {:?}", output_format);
+        }
+
+        // --fasta is the same as the FASTA output format, which may be wrapped
+        let fasta = Opts { fasta: true, ..opts() };
+        let output_format = Opts { output_format: OutputFormat::Fasta, ..opts() };
+        assert_eq!(to_config(&fasta).format, to_config(&output_format).format);
+        let wrapped = Opts { wrap: Some(4), ..output_format };
+        assert_eq!(run_extract(&wrapped), b">read-4\nGATT\nACA\n>read-5\nGATT\nACA\n");
+        let tsv = Opts { output_format: OutputFormat::Tsv, ..wrapped };
+        let err = extract(&to_config(&tsv), &mut vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("--wrap requires FASTA output"));
+
+        // only an explicit TSV output format disagrees with --fasta
+        let fasta_tsv = Opts { fasta: true, output_format: OutputFormat::Tsv, ..opts() };
+        assert!(ExtractConfig::try_from(&fasta_tsv).is_err());
+
+        // the default output format does not conflict with other options, whereas a non-FASTQ
+        // format is an error where the records are not parsed
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        for args in [
+            &["--output-format", "fastq", "--annotate-record-number", "-s", "1"][..],
+            &["--output-format", "fastq", "--raw-blocks", "-s", "1"],
+            &["--output-format", "fasta", "--fasta", "-s", "1"],
+            &["--output-format", "tsv", "--all"],
+        ] {
+            assert!(parse(args).is_ok(), "args: {:?}", args);
+        }
+        for opts in [
+            Opts { output_format: OutputFormat::Tsv, all: true, ..default_opts(&input) },
+            Opts { output_format: OutputFormat::Fasta, raw_blocks: true, ..opts() },
+            Opts { output_format: OutputFormat::Tsv, annotate_record_number: true, ..opts() },
+        ] {
+            assert!(extract(&to_config(&opts), &mut vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_extract_step() {
+        let dir = TempDir::new().unwrap();
+        let records = records(8);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        let opts = Opts { start: Some(1), end: Some(8), step: 3, ..default_opts(&input) };
+        let expected = [records[0].clone(), records[3].clone(), records[6].clone()];
+        assert_eq!(run_extract(&opts), to_fastq(&expected));
+
+        let opts = Opts { start: Some(2), end: Some(5), step: 2, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), to_fastq(&[records[1].clone(), records[3].clone()]));
+
+        let opts = Opts { start: Some(1), end: Some(8), step: 0, ..default_opts(&input) };
+        assert!(extract(&to_config(&opts), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_bgzf_reader_large_blocks() {
+        // incompressible data, so that each compressed block is larger than bgzip writes
+        let mut state: u32 = 1;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let (bgzf, gzi) = bgzip(&data, 65_400);
+        let (mut reader, index) = bgzf_reader(bgzf, gzi);
+        let block_size = index.entries[1].compressed_offset - index.entries[0].compressed_offset;
+        assert!(block_size > 65_280, "block size: {}", block_size);
+
+        let mut output: Vec<u8> = vec![];
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+
+        // the largest uncompressed block allowed by the format
+        let data = b"@r\nACGT\n+\nIIII\n".repeat(10_000);
+        let (bgzf, gzi) = bgzip(&data, 65_536);
+        let (mut reader, _) = bgzf_reader(bgzf, gzi);
+        let mut output: Vec<u8> = vec![];
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_extract_raw_blocks() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        for (start, end) in [(1, 1), (4, 6), (10, 10), (1, 10)] {
+            let opts = Opts {
+                start: Some(start),
+                end: Some(end),
+                raw_blocks: true,
+                ..default_opts(&input)
+            };
+            let output = run_extract(&opts);
+            assert!(output.ends_with(&BGZF_EOF));
+
+            // the blocks decompress to the requested records, and those around them
+            let (mut reader, _) = bgzf_reader(output, vec![0; 8]);
+            let mut fastq: Vec<u8> = vec![];
+            reader.read_to_end(&mut fastq).unwrap();
+            let expected = to_fastq(&records[start as usize - 1..end as usize]);
+            assert!(fastq.windows(expected.len()).any(|window| window == expected));
+            assert!(to_fastq(&records).windows(fastq.len()).any(|window| window == fastq));
+        }
+
+        let parse =
+            |args: &[&str]| Opts::try_parse_from([&["extract", "-f", "in.fq.gz"], args].concat());
+        assert!(parse(&["--raw-blocks", "-s", "1"]).is_ok());
+        assert!(parse(&["--raw-blocks", "--fasta"]).is_err());
+    }
+
+    #[test]
+    fn test_extract_estimate() {
+        let dir = TempDir::new().unwrap();
+        let input = write_fastq(dir.path(), &records(10), 3, 50);
+
+        // records 4-5 are in the chunk of records 4-6, where records 1-9 are 26 bytes each
+        let opts = Opts { start: Some(4), end: Some(5), estimate: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b"num_bytes\t78\nselected_records\t2\noutput_bytes\t52\n");
+
+        // records 3-10 span all four chunks
+        let opts = Opts { start: Some(3), end: Some(10), estimate: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b"num_bytes\t261\nselected_records\t8\noutput_bytes\t209\n");
+
+        let opts = Opts { start: Some(11), estimate: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b"num_bytes\t0\nselected_records\t0\noutput_bytes\t0\n");
+
+        // with --step, only the records that would be output are counted: 3, 6, and 9
+        let opts =
+            Opts { start: Some(3), end: Some(10), step: 3, estimate: true, ..default_opts(&input) };
+        assert_eq!(run_extract(&opts), b"num_bytes\t261\nselected_records\t3\noutput_bytes\t78\n");
+    }
+
+    #[test]
+    fn test_extract_blocks() {
+        let dir = TempDir::new().unwrap();
+        let input = write_fastq(dir.path(), &records(10), 3, 50);
+        let gzi = BgzfIndex::from(format!("{}.gzi", input.display())).unwrap();
+        let offsets: Vec<(u64, u64)> = gzi
+            .entries
+            .iter()
+            .map(|entry| (entry.compressed_offset.0, entry.uncompressed_offset.0))
+            .collect();
+        let data_end = fs::metadata(&input).unwrap().len() - BGZF_EOF.len() as u64;
+        let header = "compressed_offset\tcompressed_size\tuncompressed_offset\tuncompressed_size\n";
+        let line = |i: usize| {
+            let (next_compressed, next_uncompressed) =
+                offsets.get(i + 1).copied().unwrap_or((data_end, 261));
+            format!(
+                "{}\t{}\t{}\t{}\n",
+                offsets[i].0,
+                next_compressed - offsets[i].0,
+                offsets[i].1,
+                next_uncompressed - offsets[i].1
+            )
+        };
+
+        // records 4-6 span bytes [78, 156), so the second through fourth blocks of 50 bytes
+        let opts = Opts { start: Some(4), end: Some(6), blocks: true, ..default_opts(&input) };
+        let expected = format!("{}{}{}{}", header, line(1), line(2), line(3));
+        assert_eq!(String::from_utf8(run_extract(&opts)).unwrap(), expected);
+
+        // the last record, bytes [234, 261), ends in the last block
+        let opts = Opts { start: Some(10), blocks: true, ..default_opts(&input) };
+        assert_eq!(offsets.len(), 6);
+        let expected = format!("{}{}{}", header, line(4), line(5));
+        assert_eq!(String::from_utf8(run_extract(&opts)).unwrap(), expected);
+
+        let opts = Opts { start: Some(11), blocks: true, ..default_opts(&input) };
+        assert_eq!(String::from_utf8(run_extract(&opts)).unwrap(), header);
+    }
+
+    #[test]
+    fn test_block_decoder() {
+        let data = b"@r1\nACGT\n+\nIIII\n".repeat(3);
+        let (bgzf, _) = bgzip(&data, data.len());
+        let block = &bgzf[Bgzf::HEADER_SIZE..bgzf.len() - BGZF_EOF.len()];
+        let footer = Bgzf::new().get_footer_values(block);
+        let mut crc = Crc::new();
+        crc.update(&data);
+        assert_eq!((footer.sum, footer.amount as usize), (crc.sum(), data.len()));
+
+        let mut decoder = BlockDecoder::new();
+        let mut out: Vec<u8> = vec![1, 2, 3];
+        decoder.decode(block, footer, &mut out).unwrap();
+        assert_eq!(out, data);
+
+        // the EOF block is empty
+        let eof = &BGZF_EOF[Bgzf::HEADER_SIZE..];
+        decoder.decode(eof, Bgzf::new().get_footer_values(eof), &mut out).unwrap();
+        assert!(out.is_empty());
+
+        // a CRC32 mismatch
+        let footer = FooterValues { sum: footer.sum ^ 1, amount: footer.amount };
+        assert!(decoder.decode(block, footer, &mut out).is_err());
+    }
+
+    #[test]
+    fn test_extract_not_bgzf() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let opts = Opts { start: Some(1), ..default_opts(&input) };
+        let extract_error =
+            || format!("{:#}", extract(&to_config(&opts), &mut vec![]).unwrap_err());
+
+        // a plain FASTQ
+        let fastq = to_fastq(&records);
+        fs::write(&input, &fastq).unwrap();
+        let err = extract_error();
+        assert!(err.starts_with("The input is not gzip-compressed"), "{}", err);
+        assert!(err.contains("compress it with `bgzip`"), "{}", err);
+        fs::write(&input, b"").unwrap();
+        assert!(extract_error().starts_with("The input is not gzip-compressed"));
+
+        // a gzip-compressed FASTQ, without the BGZF extra subfield
+        let mut compressor = Compressor::new(CompressionLvl::default());
+        let mut gzip = vec![0; compressor.gzip_compress_bound(fastq.len())];
+        let size = compressor.gzip_compress(&fastq, &mut gzip).unwrap();
+        gzip.truncate(size);
+        fs::write(&input, &gzip).unwrap();
+        let err = extract_error();
+        assert!(err.starts_with("The input is gzip-compressed but not BGZF"), "{}", err);
+        assert!(err.contains(&format!("gzip -dc {} | bgzip", input.display())), "{}", err);
+        assert!(Extractor::open(&input).is_err());
+
+        // an extra field with another subfield before the BGZF subfield
+        let (bgzf, _) = bgzip(&fastq, 50);
+        let mut with_subfield = bgzf[..10].to_vec();
+        with_subfield.extend_from_slice(&[12, 0, b'X', b'Y', 2, 0, 0, 0]);
+        with_subfield.extend_from_slice(&bgzf[12..]);
+        fs::write(&input, &with_subfield).unwrap();
+        check_bgzf(&input).unwrap();
+    }
+
+    #[test]
+    fn test_extract_missing_indexes() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        let opts = Opts { start: Some(1), ..default_opts(&input) };
+
+        // the FASTQ index is missing
+        fs::remove_file(dir.path().join("test.fastq.gz.fqi")).unwrap();
+        let err = format!("{:#}", extract(&to_config(&opts), &mut vec![]).unwrap_err());
+        assert!(err.starts_with("FASTQ index not found"), "{}", err);
+        assert!(err.contains("fqme index --output"), "{}", err);
+        // but is not needed to decompress the whole FASTQ
+        assert_eq!(run_extract(&Opts { all: true, ..default_opts(&input) }), to_fastq(&records));
+
+        // the BGZF index is missing
+        fs::remove_file(dir.path().join("test.fastq.gz.gzi")).unwrap();
+        let err = format!("{:#}", extract(&to_config(&opts), &mut vec![]).unwrap_err());
+        assert!(err.starts_with("BGZF index not found"), "{}", err);
+        assert!(err.contains(&format!("bgzip -r {}", input.display())), "{}", err);
+    }
 }
@@ -1,17 +1,77 @@
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Stdout},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Stdout, Write},
     path::Path,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use anyhow::{bail, ensure, Context, Result};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use fgoxide::io::Io;
+use flate2::bufread::MultiGzDecoder;
+use log::{info, warn};
+use memmap2::Mmap;
 use seq_io::{
     fastq::{Error, OwnedRecord},
     BaseRecord,
 };
 
-use crate::utils::BUFFERSIZE;
+use crate::{
+    types::{RecordNum, UncompressedOffset},
+    utils::{read_fully, tmp_path, BUFFERSIZE},
+};
+
+/// The magic bytes at the start of a versioned FASTQ index.  Indexes without these bytes are
+/// legacy indexes, whose header is only the total number of records and nth.
+const MAGIC: [u8; 4] = *b"FQIX";
+
+/// The magic bytes at the start of a gzip-compressed file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The UTF-8 byte order mark (BOM), which some tools write at the start of a text file
+pub const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+/// The version of the FASTQ index format that is written.  Version 2 may store the number of
+/// bases in each entry (see `FLAG_TOTAL_BASES`), so version 1 indexes are also read.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The version of the FASTQ index format that is written when the entries are aligned (see
+/// `IndexWriter::with_align`).  The header is followed by the offset of the first entry, then zero
+/// padding up to that offset.  Unaligned indexes are written as version 2, so that they can still
+/// be read by older versions.
+pub const ALIGNED_FORMAT_VERSION: u32 = 3;
+
+/// The number of bytes in the index header: the magic bytes, version, total number of records,
+/// nth, flags, and source CRC32
+const HEADER_BYTES: u64 = 32;
+
+/// The number of bytes in the header of a legacy index: the total number of records and nth
+const LEGACY_HEADER_BYTES: u64 = 16;
+
+/// The number of bytes in the header of an aligned index, before the padding: the header and the
+/// offset of the first entry
+const ALIGNED_HEADER_BYTES: u64 = HEADER_BYTES + 8;
+
+/// The header flag set when the index stores the CRC32 of the source FASTQ
+const FLAG_SOURCE_CRC32: u32 = 1;
+
+/// The header flag set when each index entry stores the total number of bases
+const FLAG_TOTAL_BASES: u32 = 2;
+
+/// The header flag set when the byte offsets were estimated from the parsed records (see
+/// `FastqIndex::record_to_num_bytes`), as by the legacy `FastqIndex::from`, rather than counted
+/// from the FASTQ bytes.  The estimate is incorrect when a '+' line contains a comment, unless the
+/// parsed records were written out (e.g. by `fqme index`) and it is that FASTQ that is indexed.
+const FLAG_ESTIMATED_BYTES: u32 = 4;
+
+/// The header flag set when the entries are spaced by uncompressed bytes rather than by records
+/// (see `CheckpointSpacing::Bytes`), in which case the header's nth is the number of bytes
+const FLAG_BYTE_SPACED: u32 = 8;
+
+/// The number of bytes per index entry, without the total number of bases
+const ENTRY_BYTES: u64 = 16;
+
+/// The number of bytes per index entry with the total number of bases
+const ENTRY_BYTES_WITH_BASES: u64 = 24;
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct FastqIndexEntry {
@@ -19,63 +79,554 @@ pub struct FastqIndexEntry {
     pub total_records: u64,
     // total # of bytes seen
     pub total_bytes: u64,
+    // total # of bases seen, or `None` for an index that does not store them (e.g. one written by
+    // an older version)
+    pub total_bases: Option<u64>,
+}
+
+impl FastqIndexEntry {
+    /// The entry before the first record, counting bases
+    fn empty() -> FastqIndexEntry {
+        FastqIndexEntry { total_records: 0, total_bytes: 0, total_bases: Some(0) }
+    }
+}
+
+/// The records that span a range of bases, found by `FastqIndex::range_by_base`
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+pub struct FastqBaseRange {
+    // the first record (1-based) of the indexed chunk containing the first base
+    pub start_record: RecordNum,
+    // the last record (1-based, inclusive) of the indexed chunk containing the last base
+    pub end_record: RecordNum,
+    // the number of bases before the first record
+    pub start_base: u64,
+    // the uncompressed bytes of the records
+    pub range: FastqIndexRange,
+}
+
+/// How the checkpoint entries of an index are spaced
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub enum CheckpointSpacing {
+    /// Every nth record, or only the first and final entries if zero
+    Records(u64),
+    /// The first record that starts at or after each multiple of this many uncompressed bytes, so
+    /// that the entries are evenly spaced in the FASTQ when the records' lengths vary
+    Bytes(u64),
+}
+
+impl CheckpointSpacing {
+    /// True if a checkpoint entry is added for the given totals, before the record that follows
+    /// them, given the number of bytes at the last checkpoint entry
+    fn is_checkpoint(self, totals: &FastqIndexEntry, checkpoint_bytes: u64) -> bool {
+        match self {
+            CheckpointSpacing::Records(nth) => totals.total_records.is_multiple_of(nth),
+            CheckpointSpacing::Bytes(nth_bytes) => {
+                totals.total_records == 0
+                    || checkpoint_bytes / nth_bytes < totals.total_bytes / nth_bytes
+            }
+        }
+    }
+
+    /// Errors if the entries are spaced by zero bytes, as there would be no checkpoints to find
+    pub fn check(self) -> Result<()> {
+        ensure!(
+            self != CheckpointSpacing::Bytes(0),
+            "The number of bytes between entries (--nth-bytes) must be greater than zero"
+        );
+        Ok(())
+    }
+
+    /// The number of records between entries, or zero if the entries are spaced by bytes
+    pub fn nth(self) -> u64 {
+        match self {
+            CheckpointSpacing::Records(nth) => nth,
+            CheckpointSpacing::Bytes(_) => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct FastqIndex {
     pub total_records: u64,
+    // index every Nth record, or zero to store only the first and final entries, or when the
+    // entries are spaced by bytes
     pub nth: u64,
+    // the number of uncompressed bytes between entries, if spaced by bytes rather than records
+    pub nth_bytes: Option<u64>,
+    // the CRC32 of the uncompressed FASTQ bytes that were indexed, if computed
+    pub source_crc32: Option<u32>,
+    // true if the byte offsets were estimated from the parsed records (see `FLAG_ESTIMATED_BYTES`)
+    pub estimated_bytes: bool,
     pub entries: Vec<FastqIndexEntry>,
 }
 
+/// The values stored in the index header
+struct FastqIndexHeader {
+    total_records: u64,
+    nth: u64,
+    nth_bytes: Option<u64>,
+    source_crc32: Option<u32>,
+    // true if each entry stores the total number of bases
+    has_bases: bool,
+    // true if the byte offsets were estimated from the parsed records
+    estimated_bytes: bool,
+    // the number of bytes in the header, including any padding, where the entries start
+    num_bytes: u64,
+}
+
+impl FastqIndexHeader {
+    /// The number of bytes per entry
+    fn entry_bytes(&self) -> u64 {
+        if self.has_bases {
+            ENTRY_BYTES_WITH_BASES
+        } else {
+            ENTRY_BYTES
+        }
+    }
+}
+
 impl FastqIndex {
-    pub fn read(path: &Path) -> FastqIndex {
-        let mut reader = BufReader::with_capacity(BUFFERSIZE, File::open(path).unwrap());
-        let mut entries: Vec<FastqIndexEntry> = vec![];
-        let total_records = reader.read_u64::<LittleEndian>().unwrap();
-        let nth = reader.read_u64::<LittleEndian>().unwrap();
-        while let Ok(num_records) = reader.read_u64::<LittleEndian>() {
-            let total_bytes = reader.read_u64::<LittleEndian>().unwrap();
-            let entry = FastqIndexEntry { total_records: num_records, total_bytes };
+    /// Reads the index at the given path, which may be gzip-compressed (see `write`).
+    pub fn read(path: &Path) -> Result<FastqIndex> {
+        if FastqIndex::is_gzip(path)? {
+            let reader = FastqIndex::gzip_reader(path)?;
+            return FastqIndex::read_from(reader)
+                .with_context(|| format!("Invalid FASTQ index: {}", path.display()));
+        }
+        let file = File::open(path)
+            .with_context(|| format!("Could not open FASTQ index: {}", path.display()))?;
+
+        // Validate the file size before allocating the entries
+        let file_bytes = file.metadata()?.len();
+        ensure!(
+            LEGACY_HEADER_BYTES <= file_bytes,
+            "FASTQ index is too short ({} bytes) to contain a header: {}",
+            file_bytes,
+            path.display()
+        );
+        let mut reader = BufReader::with_capacity(BUFFERSIZE, file);
+        let header = FastqIndex::parse_header(&mut reader)
+            .with_context(|| format!("Invalid FASTQ index: {}", path.display()))?;
+        let entries_bytes = file_bytes - header.num_bytes;
+        let entry_bytes = header.entry_bytes();
+        ensure!(
+            entries_bytes.is_multiple_of(entry_bytes),
+            "FASTQ index has a truncated entry ({} trailing bytes): {}",
+            entries_bytes % entry_bytes,
+            path.display()
+        );
+        let num_entries = (entries_bytes / entry_bytes) as usize;
+
+        FastqIndex::read_entries(reader, header, num_entries)
+            .with_context(|| format!("Invalid FASTQ index: {}", path.display()))
+    }
+
+    /// Reads the index from the given reader, which is read in full.  This allows reading the
+    /// index from a non-seekable source such as stdin.
+    pub fn read_from<R: Read>(reader: R) -> Result<FastqIndex> {
+        let mut reader = BufReader::with_capacity(BUFFERSIZE, reader);
+        let header = FastqIndex::parse_header(&mut reader)?;
+        FastqIndex::read_entries(reader, header, 0)
+    }
+
+    /// Memory maps the index at the given path, so that ranges are found by reading the entries
+    /// directly from the mapped bytes rather than reading every entry into memory (see
+    /// `MmapIndex`).  The header and entries are validated as in `read_from`, scanning the entries
+    /// once without holding them in memory.  Errors if the index is gzip-compressed.
+    pub fn open_mmap(path: &Path) -> Result<MmapIndex> {
+        ensure!(
+            !FastqIndex::is_gzip(path)?,
+            "FASTQ index is gzip-compressed, so cannot be memory mapped: {}",
+            path.display()
+        );
+        let file = File::open(path)
+            .with_context(|| format!("Could not open FASTQ index: {}", path.display()))?;
+        // SAFETY: the mapping is only read, and the file must not be modified while it is mapped
+        #[allow(unsafe_code)]
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Could not memory map FASTQ index: {}", path.display()))?;
+        MmapIndex::new(mmap).with_context(|| format!("Invalid FASTQ index: {}", path.display()))
+    }
+
+    /// Opens the index at the given path for finding the ranges of records.  The index is memory
+    /// mapped (see `open_mmap`), so only the entries needed to find each range are read from disk,
+    /// unless it is gzip-compressed, in which case it is read into memory.  Memory use then does
+    /// not grow with the number of entries, which for an index of every record (`nth == 1`) is 24
+    /// bytes per record when read into memory.
+    pub fn open(path: &Path) -> Result<Box<dyn RecordIndex>> {
+        if FastqIndex::is_gzip(path)? {
+            Ok(Box::new(FastqIndex::read(path)?))
+        } else {
+            Ok(Box::new(FastqIndex::open_mmap(path)?))
+        }
+    }
+
+    /// Reads the format version of the index at the given path, or `None` for a legacy index.  Only
+    /// the start of the header is read.
+    pub fn read_version(path: &Path) -> Result<Option<u32>> {
+        let mut reader = FastqIndex::header_reader(path)?;
+        let mut prefix = [0u8; 8];
+        reader
+            .read_exact(&mut prefix)
+            .with_context(|| format!("FASTQ index is missing its header: {}", path.display()))?;
+        if prefix[0..4] == MAGIC {
+            Ok(Some(LittleEndian::read_u32(&prefix[4..8])))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the total number of records and nth from the header of the index at the given path,
+    /// without reading the entries, so that it takes the same time regardless of the size of the
+    /// index.
+    pub fn read_header(path: &Path) -> Result<(u64, u64)> {
+        let mut reader = FastqIndex::header_reader(path)?;
+        let header = FastqIndex::parse_header(&mut reader)
+            .with_context(|| format!("Invalid FASTQ index: {}", path.display()))?;
+        Ok((header.total_records, header.nth))
+    }
+
+    /// Opens the index at the given path for reading its header, decompressing it if it is
+    /// gzip-compressed.
+    fn header_reader(path: &Path) -> Result<Box<dyn Read>> {
+        if FastqIndex::is_gzip(path)? {
+            return Ok(Box::new(FastqIndex::gzip_reader(path)?));
+        }
+        let file = File::open(path)
+            .with_context(|| format!("Could not open FASTQ index: {}", path.display()))?;
+        Ok(Box::new(file))
+    }
+
+    /// Returns true if the file at the given path starts with the gzip magic bytes
+    fn is_gzip(path: &Path) -> Result<bool> {
+        let mut file = File::open(path)
+            .with_context(|| format!("Could not open FASTQ index: {}", path.display()))?;
+        let mut magic = [0u8; 2];
+        Ok(read_fully(&mut file, &mut magic)? == magic.len() && magic == GZIP_MAGIC)
+    }
+
+    /// Opens a reader that decompresses the gzip-compressed index at the given path, whatever its
+    /// extension.
+    fn gzip_reader(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+        let file = File::open(path)
+            .with_context(|| format!("Could not open FASTQ index: {}", path.display()))?;
+        let decoder = MultiGzDecoder::new(BufReader::with_capacity(BUFFERSIZE, file));
+        Ok(Box::new(BufReader::with_capacity(BUFFERSIZE, decoder)))
+    }
+
+    /// Reads the index header, which is either a versioned header starting with the magic bytes,
+    /// or a legacy header.
+    fn parse_header<R: Read>(reader: &mut R) -> Result<FastqIndexHeader> {
+        let mut prefix = [0u8; LEGACY_HEADER_BYTES as usize];
+        reader.read_exact(&mut prefix).context("FASTQ index is missing its header")?;
+        if prefix[0..4] != MAGIC {
+            return Ok(FastqIndexHeader {
+                total_records: LittleEndian::read_u64(&prefix[0..8]),
+                nth: LittleEndian::read_u64(&prefix[8..16]),
+                nth_bytes: None,
+                source_crc32: None,
+                has_bases: false,
+                estimated_bytes: false,
+                num_bytes: LEGACY_HEADER_BYTES,
+            });
+        }
+
+        let version = LittleEndian::read_u32(&prefix[4..8]);
+        ensure!(
+            (1..=ALIGNED_FORMAT_VERSION).contains(&version),
+            "Unsupported FASTQ index format version {} (supported: 1 to {})",
+            version,
+            ALIGNED_FORMAT_VERSION
+        );
+        let mut rest = [0u8; (HEADER_BYTES - LEGACY_HEADER_BYTES) as usize];
+        reader.read_exact(&mut rest).context("FASTQ index is missing its header")?;
+        let flags = LittleEndian::read_u32(&rest[8..12]);
+        let source_crc32 = if flags & FLAG_SOURCE_CRC32 == 0 {
+            None
+        } else {
+            Some(LittleEndian::read_u32(&rest[12..16]))
+        };
+        let num_bytes = if version == ALIGNED_FORMAT_VERSION {
+            let num_bytes =
+                reader.read_u64::<LittleEndian>().context("FASTQ index is missing its header")?;
+            ensure!(
+                ALIGNED_HEADER_BYTES <= num_bytes,
+                "FASTQ index entries start at byte {}, within the header",
+                num_bytes
+            );
+            // Skip the padding
+            let padding = num_bytes - ALIGNED_HEADER_BYTES;
+            let num_skipped = io::copy(&mut reader.take(padding), &mut io::sink())?;
+            ensure!(
+                num_skipped == padding,
+                "FASTQ index is missing its header padding: found {} of {} bytes",
+                num_skipped,
+                padding
+            );
+            num_bytes
+        } else {
+            HEADER_BYTES
+        };
+        // The header's nth is the number of bytes between entries when they are spaced by bytes
+        let (nth, nth_bytes) = match LittleEndian::read_u64(&rest[0..8]) {
+            0 if flags & FLAG_BYTE_SPACED != 0 => {
+                bail!("FASTQ index entries are spaced by bytes, but every 0 bytes")
+            }
+            nth_bytes if flags & FLAG_BYTE_SPACED != 0 => (0, Some(nth_bytes)),
+            nth => (nth, None),
+        };
+        Ok(FastqIndexHeader {
+            total_records: LittleEndian::read_u64(&prefix[8..16]),
+            nth,
+            nth_bytes,
+            source_crc32,
+            has_bases: flags & FLAG_TOTAL_BASES != 0,
+            estimated_bytes: flags & FLAG_ESTIMATED_BYTES != 0,
+            num_bytes,
+        })
+    }
+
+    /// Reads the index entries that follow the given header, pre-allocating space for the given
+    /// number of entries.
+    fn read_entries<R: Read>(
+        mut reader: R,
+        header: FastqIndexHeader,
+        num_entries: usize,
+    ) -> Result<FastqIndex> {
+        let mut entries: Vec<FastqIndexEntry> = Vec::with_capacity(num_entries);
+        let entry_bytes = header.entry_bytes() as usize;
+        let FastqIndexHeader {
+            total_records,
+            nth,
+            nth_bytes,
+            source_crc32,
+            has_bases,
+            estimated_bytes,
+            ..
+        } = header;
+        let mut buffer = [0u8; ENTRY_BYTES_WITH_BASES as usize];
+        let buffer = &mut buffer[..entry_bytes];
+        let mut checker = EntryChecker::default();
+        loop {
+            let num_read = read_fully(&mut reader, buffer)?;
+            if num_read == 0 {
+                break;
+            }
+            ensure!(
+                num_read == buffer.len(),
+                "FASTQ index entry #{} is truncated: found {} of {} bytes",
+                entries.len() + 1,
+                num_read,
+                buffer.len()
+            );
+            let entry = FastqIndexEntry {
+                total_records: LittleEndian::read_u64(&buffer[0..8]),
+                total_bytes: LittleEndian::read_u64(&buffer[8..16]),
+                total_bases: has_bases.then(|| LittleEndian::read_u64(&buffer[16..24])),
+            };
+            checker.check(&entry)?;
             entries.push(entry);
         }
-        FastqIndex { total_records, nth, entries }
+        checker.finish(total_records)?;
+        let nth = checker.nth(nth, nth_bytes);
+        Ok(FastqIndex { total_records, nth, nth_bytes, source_crc32, estimated_bytes, entries })
+    }
+
+    /// Infers the number of records between entries (nth) from the entries themselves, or returns
+    /// `None` if it cannot be inferred.  The entries must be evenly spaced, except for the final
+    /// entry, which may follow the last checkpoint by fewer records.  The spacing cannot be
+    /// inferred when there are no checkpoints other than the first entry (e.g. `nth == 0`, or
+    /// fewer than `nth` records), nor when the entries are unevenly spaced (e.g. merged indexes).
+    pub fn infer_nth(&self) -> Option<u64> {
+        let mut spacing = EntrySpacing::default();
+        for pair in self.entries.windows(2) {
+            spacing.add(pair[1].total_records - pair[0].total_records);
+        }
+        spacing.infer_nth()
+    }
+
+    /// Creates an index with no records, to which records may be appended.
+    pub fn new(nth: u64) -> FastqIndex {
+        let entries = vec![FastqIndexEntry::empty()];
+        FastqIndex {
+            total_records: 0,
+            nth,
+            nth_bytes: None,
+            source_crc32: None,
+            estimated_bytes: false,
+            entries,
+        }
+    }
+
+    /// Creates an index with no records, whose entries are spaced by `nth_bytes` uncompressed
+    /// bytes rather than by records (see `CheckpointSpacing::Bytes`), to which records may be
+    /// appended.  Errors if `nth_bytes` is zero.
+    pub fn with_nth_bytes(nth_bytes: u64) -> Result<FastqIndex> {
+        CheckpointSpacing::Bytes(nth_bytes).check()?;
+        Ok(FastqIndex { nth_bytes: Some(nth_bytes), ..FastqIndex::new(0) })
+    }
+
+    /// How the checkpoint entries are spaced
+    pub fn spacing(&self) -> CheckpointSpacing {
+        match self.nth_bytes {
+            Some(nth_bytes) => CheckpointSpacing::Bytes(nth_bytes),
+            None => CheckpointSpacing::Records(self.nth),
+        }
     }
 
+    /// Builds the index from the given FASTQ records, optionally writing each record to the given
+    /// writer.
+    ///
+    /// Records must be four-line FASTQ records: records whose sequence and quality are wrapped
+    /// across multiple lines cannot be parsed, and an error is returned naming the first record
+    /// that could not be parsed.
+    ///
+    /// The byte offsets are estimated from the parsed records, so unless the records are written,
+    /// the index is flagged as such (see `estimated_bytes`); prefer `from_raw_reader`, whose byte
+    /// offsets are exact.
     pub fn from(
         records: impl IntoIterator<Item = Result<OwnedRecord, Error>>,
         nth: u64,
         fastq_writer: &mut Option<BufWriter<Stdout>>,
-    ) -> FastqIndex {
-        let mut total_bytes: u64 = 0;
-        let mut total_records: u64 = 0;
-        let mut entries: Vec<FastqIndexEntry> = vec![];
-        for result in records {
-            let rec: OwnedRecord = result.unwrap();
-            let num_bytes = FastqIndex::record_to_num_bytes(&rec);
-
-            if total_records % nth == 0 {
-                entries.push(FastqIndexEntry { total_records, total_bytes });
-            }
+    ) -> Result<FastqIndex> {
+        let mut index = FastqIndex::new(nth);
+        index.append(records, fastq_writer)?;
+        Ok(index)
+    }
 
-            total_records += 1;
-            total_bytes += num_bytes;
+    /// Appends the given FASTQ records to the index, as if they followed the records already
+    /// indexed, optionally writing each record to the given writer.  See `from`.
+    pub fn append(
+        &mut self,
+        records: impl IntoIterator<Item = Result<OwnedRecord, Error>>,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<()> {
+        // Continue from the final entry, which is added back after the new records
+        let last = self.entries.pop().unwrap_or_else(FastqIndexEntry::empty);
+        let checkpoint_bytes = self.entries.last().map_or(0, |entry| entry.total_bytes);
+        let spacing = self.spacing();
+        let entries = &mut self.entries;
+        let last =
+            index_records(records, spacing, last, checkpoint_bytes, None, fastq_writer, |entry| {
+                entries.push(entry);
+                Ok(())
+            })?;
+        self.total_records = last.total_records;
+        self.estimated_bytes |= fastq_writer.is_none();
+        self.entries.push(last);
+        Ok(())
+    }
 
-            if let Some(ref mut writer) = fastq_writer {
-                rec.write(writer).unwrap();
-            }
-        }
-        entries.push(FastqIndexEntry { total_records, total_bytes });
-        FastqIndex { total_records, nth, entries }
+    /// Builds the index by scanning the raw FASTQ bytes, counting lines rather than parsing
+    /// records, optionally writing the bytes to the given writer.
+    ///
+    /// Each record must span exactly four lines.  Since the byte offsets are the literal offsets
+    /// in the input, they are exact even when the `+` line contains a comment.
+    pub fn from_raw_reader<R: BufRead>(
+        mut reader: R,
+        nth: u64,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<FastqIndex> {
+        check_fastq_start(&mut reader)?;
+        let mut index = FastqIndex::new(nth);
+        index.append_raw_reader(reader, fastq_writer)?;
+        Ok(index)
+    }
+
+    /// Builds the index by scanning the raw FASTQ bytes, as in `from_raw_reader`, with an entry for
+    /// the first record at or after every `nth_bytes` uncompressed bytes rather than every nth
+    /// record (see `CheckpointSpacing::Bytes`).
+    pub fn from_raw_reader_by_bytes<R: BufRead>(
+        mut reader: R,
+        nth_bytes: u64,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<FastqIndex> {
+        check_fastq_start(&mut reader)?;
+        let mut index = FastqIndex::with_nth_bytes(nth_bytes)?;
+        index.append_raw_reader(reader, fastq_writer)?;
+        Ok(index)
+    }
+
+    /// Appends the records in the raw FASTQ bytes to the index, as if they followed the records
+    /// already indexed, optionally writing the bytes to the given writer.  See `from_raw_reader`.
+    pub fn append_raw_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<()> {
+        // Continue from the final entry, which is added back after the new records
+        let last = self.entries.pop().unwrap_or_else(FastqIndexEntry::empty);
+        let checkpoint_bytes = self.entries.last().map_or(0, |entry| entry.total_bytes);
+        let (spacing, from) = (self.spacing(), (last, checkpoint_bytes));
+        let entries = &mut self.entries;
+        let last = index_raw_reader(reader, spacing, from, (false, None), fastq_writer, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        self.total_records = last.total_records;
+        self.entries.push(last);
+        Ok(())
     }
 
     pub fn write(self, output: &Path) {
         let mut writer = Io::default().new_writer(&output).unwrap();
-        writer.write_u64::<LittleEndian>(self.total_records).unwrap();
-        writer.write_u64::<LittleEndian>(self.nth).unwrap();
-        for entry in self.entries {
-            writer.write_u64::<LittleEndian>(entry.total_records).unwrap();
-            writer.write_u64::<LittleEndian>(entry.total_bytes).unwrap();
+        self.write_to(&mut writer);
+    }
+
+    /// Writes the index to a temporary file alongside the output, then renames it over the output,
+    /// so that the output is never left partially written.
+    pub fn write_atomically(&self, output: &Path) -> Result<()> {
+        let tmp_path = tmp_path(output);
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("Could not create {}", tmp_path.display()))?;
+        let mut writer = BufWriter::with_capacity(BUFFERSIZE, file);
+        self.write_to(&mut writer);
+        writer.flush()?;
+        fs::rename(&tmp_path, output).with_context(|| {
+            format!("Could not rename {} to {}", tmp_path.display(), output.display())
+        })?;
+        Ok(())
+    }
+
+    /// Writes the index to the given writer.  The number of bases is written only if every entry
+    /// has it.
+    pub fn write_to<W: Write>(&self, writer: &mut W) {
+        self.write_aligned_to(writer, 0).unwrap();
+    }
+
+    /// Writes the index to the given writer, with the header padded so that the first entry starts
+    /// at a multiple of `align` bytes, or unpadded if `align` is zero.
+    pub fn write_aligned_to<W: Write>(&self, writer: &mut W, align: u64) -> io::Result<()> {
+        let has_bases = self.has_bases();
+        let (total_records, spacing) = (self.total_records, self.spacing());
+        let (source_crc32, estimated_bytes) = (self.source_crc32, self.estimated_bytes);
+        write_header(
+            writer,
+            total_records,
+            spacing,
+            source_crc32,
+            has_bases,
+            estimated_bytes,
+            align,
+        )?;
+        for entry in &self.entries {
+            write_entry(writer, entry, has_bases)?;
+        }
+        Ok(())
+    }
+
+    /// True if every entry stores the total number of bases
+    pub fn has_bases(&self) -> bool {
+        self.entries.iter().all(|entry| entry.total_bases.is_some())
+    }
+
+    /// The mean number of bytes per record, from the final entry, or `None` if there are no records
+    pub fn mean_record_bytes(&self) -> Option<f64> {
+        match self.entries.last() {
+            Some(last) if 0 < last.total_records => {
+                Some(last.total_bytes as f64 / last.total_records as f64)
+            }
+            _ => None,
         }
     }
 
@@ -92,118 +643,1083 @@ impl FastqIndex {
         num_bytes as u64
     }
 
-    // NB: start_record and end_record are 1-based inclusive
-    pub fn range(&self, start_record: u64, end_record: u64) -> Option<FastqIndexRange> {
-        if end_record < start_record || end_record < 1 || self.total_records < start_record {
+    /// Returns the record that contains the given uncompressed byte, or `None` if the byte is past
+    /// the end of the FASTQ.
+    ///
+    /// The record is only exact when every record is indexed (`nth == 1`).  Otherwise, the first
+    /// record of the indexed chunk that contains the byte is returned, which is at most `nth - 1`
+    /// records before the record that contains the byte (or the first record when `nth == 0`).
+    pub fn record_at_byte(&self, byte: UncompressedOffset) -> Option<RecordNum> {
+        let total_bytes = self.entries.last().map_or(0, |entry| entry.total_bytes);
+        if total_bytes <= byte.0 {
             return None;
         }
-        if start_record < 1 {
-            return self.range(1, end_record);
+        let index = self.entries.partition_point(|entry| entry.total_bytes <= byte.0);
+        let entry = &self.entries[index.saturating_sub(1)];
+        Some(RecordNum(entry.total_records + 1))
+    }
+
+    // NB: start_record and end_record are 1-based inclusive
+    pub fn range(&self, start_record: RecordNum, end_record: RecordNum) -> Option<FastqIndexRange> {
+        let (start_record, end_record) = (u64::from(start_record), u64::from(end_record));
+        let (start_entry, end_entry) = self.covering_entries(start_record, end_record)?;
+
+        let start_entry = start_entry.map(|index| &self.entries[index]);
+        Some(FastqIndexRange::between(
+            start_entry,
+            &self.entries[end_entry],
+            start_record,
+            end_record,
+        ))
+    }
+
+    /// Returns the indices into `entries` of the checkpoints that `range` reads between for the
+    /// records from `start_record` to `end_record` (1-based inclusive): the last entry before the
+    /// start record, or `None` to read from the start of the FASTQ if there is no such entry, and
+    /// the first entry with the end record.  The records are clamped as in `range`, and `None` is
+    /// returned if no record is in range.
+    pub fn covering_entries(
+        &self,
+        start_record: u64,
+        end_record: u64,
+    ) -> Option<(Option<usize>, usize)> {
+        let entry = |index: usize| self.entries[index].clone();
+        covering_entries(self.entries.len(), entry, self.total_records, start_record, end_record)
+    }
+
+    /// Returns the exact uncompressed bytes `[start, end)` of the records from `start_record` to
+    /// `end_record` (1-based inclusive), clamped as in `range`.  The bytes are only exact when the
+    /// records start and end at indexed entries, which is always true when every record is indexed
+    /// (`nth == 1`), so `None` is returned if the range has leading or trailing records (see
+    /// `range` for the bytes of the indexed chunks instead), or if no record is in range.
+    pub fn exact_byte_range(
+        &self,
+        start_record: RecordNum,
+        end_record: RecordNum,
+    ) -> Option<(UncompressedOffset, UncompressedOffset)> {
+        self.range(start_record, end_record)
+            .filter(|range| range.leading_records == 0 && range.trailing_records == 0)
+            .map(|range| (range.start_byte, range.end_byte))
+    }
+
+    /// Returns the ranges of successive, non-overlapping chunks of `chunk_size` records, the last
+    /// chunk being the remainder, as found by `range`.  E.g. for splitting the FASTQ, or for
+    /// processing the chunks in parallel.  Panics if `chunk_size` is zero.
+    pub fn chunks(&self, chunk_size: u64) -> impl Iterator<Item = FastqIndexRange> + '_ {
+        assert!(chunk_size > 0, "The chunk size must be greater than zero");
+        (1..=self.total_records).step_by(chunk_size as usize).filter_map(move |start| {
+            let end = start.saturating_add(chunk_size - 1).min(self.total_records);
+            self.range(RecordNum(start), RecordNum(end))
+        })
+    }
+
+    /// Returns the records that span the bases `[start_base, end_base)` (0-based, end exclusive),
+    /// counting the bases of every record's sequence in file order, or `None` if no base is in
+    /// range.  The end is clamped to the total number of bases.
+    ///
+    /// The records are found at the granularity of the indexed chunks (every nth record): the
+    /// records run from the start of the chunk containing the first base to the end of the chunk
+    /// containing the last base, so the records must be read to find those that contain the bases
+    /// (starting from `start_base` bases at the first record).  Errors if the index does not store
+    /// the number of bases.
+    pub fn range_by_base(&self, start_base: u64, end_base: u64) -> Result<Option<FastqBaseRange>> {
+        let bases: Option<Vec<u64>> = self.entries.iter().map(|entry| entry.total_bases).collect();
+        let Some(bases) = bases else {
+            bail!(
+                "The FASTQ index does not store the number of bases, as it was written by an \
+                 older version; re-create it with `fqme index`"
+            )
+        };
+        let total_bases = bases.last().copied().unwrap_or(0);
+        if end_base <= start_base || total_bases <= start_base {
+            return Ok(None);
         }
-        if self.total_records < end_record {
-            return self.range(start_record, self.total_records);
+        let end_base = end_base.min(total_bases);
+
+        // The last entry at or before the first base, and the first entry at or after the end
+        let start_index = bases.partition_point(|bases| *bases <= start_base) - 1;
+        let end_index = bases.partition_point(|bases| *bases < end_base);
+        let start_record = RecordNum(self.entries[start_index].total_records + 1);
+        let end_record = RecordNum(self.entries[end_index].total_records);
+        let range = self.range(start_record, end_record).expect("the records are in the index");
+        Ok(Some(FastqBaseRange { start_record, end_record, start_base: bases[start_index], range }))
+    }
+}
+
+/// Errors if the FASTQ starts with a UTF-8 byte order mark (BOM) or whitespace (e.g. a blank line)
+/// rather than the first record, as the records' byte offsets would then not match the FASTQ.
+/// Only the buffered start of the reader is examined, so nothing is consumed.
+pub fn check_fastq_start<R: BufRead>(reader: &mut R) -> Result<()> {
+    let buffer = reader.fill_buf()?;
+    ensure!(
+        !buffer.starts_with(&UTF8_BOM),
+        "The FASTQ starts with a UTF-8 byte order mark (BOM), which is not supported; remove it \
+         before indexing, e.g. with: sed '1s/^\\xEF\\xBB\\xBF//'"
+    );
+    ensure!(
+        !buffer.first().is_some_and(u8::is_ascii_whitespace),
+        "The FASTQ starts with whitespace (e.g. a blank line) rather than '@', which is not \
+         supported; remove it before indexing"
+    );
+    Ok(())
+}
+
+/// Adds the given record number (1-based) to an error parsing a record, with a hint as to why the
+/// record could not be parsed when its header or '+' line is missing
+pub(crate) fn parse_error(error: Error, record_number: u64) -> anyhow::Error {
+    let hint = match error {
+        Error::InvalidStart { .. } => {
+            "; its header does not start with '@', so the FASTQ may be corrupt"
+        }
+        Error::InvalidSep { .. } => {
+            "; records must be four lines, as multi-line (wrapped) FASTQ is not supported"
         }
+        _ => "",
+    };
+    anyhow::Error::new(error)
+        .context(format!("Could not parse FASTQ record #{}{}", record_number, hint))
+}
 
-        let mut leading_records: u64 = start_record - 1;
-        let mut trailing_records: u64 = 0;
-        let mut start_byte: u64 = 0;
-        let mut end_byte: u64 = 0;
-        let mut total_records: u64 = 0;
-        let mut last_total_records: u64 = 0;
+/// Indexes the given FASTQ records, continuing from the given totals of the records already
+/// indexed and the bytes at the last checkpoint entry, optionally writing each record to the given
+/// writer.  Each checkpoint entry (per the spacing) is passed to `emit`, and the totals after the
+/// last record are returned.  The number of records indexed is logged every `count_interval`
+/// records, if given.  See `FastqIndex::from`.
+fn index_records<F: FnMut(FastqIndexEntry) -> Result<()>>(
+    records: impl IntoIterator<Item = Result<OwnedRecord, Error>>,
+    spacing: CheckpointSpacing,
+    from: FastqIndexEntry,
+    mut checkpoint_bytes: u64,
+    count_interval: Option<u64>,
+    fastq_writer: &mut Option<BufWriter<Stdout>>,
+    mut emit: F,
+) -> Result<FastqIndexEntry> {
+    let FastqIndexEntry { mut total_records, mut total_bytes, mut total_bases } = from;
+    for result in records {
+        let rec: OwnedRecord = result.map_err(|err| parse_error(err, total_records + 1))?;
+        let num_bytes = FastqIndex::record_to_num_bytes(&rec);
 
-        // TODO: binary search
-        let mut start_entry = 0;
-        for entry in &self.entries {
-            if start_record <= entry.total_records {
-                leading_records = (start_record - 1) % self.nth;
+        let totals = FastqIndexEntry { total_records, total_bytes, total_bases };
+        if spacing.is_checkpoint(&totals, checkpoint_bytes) {
+            checkpoint_bytes = total_bytes;
+            emit(totals)?;
+        }
+
+        total_records += 1;
+        total_bytes += num_bytes;
+        total_bases = total_bases.map(|bases| bases + rec.seq().len() as u64);
+        log_record_count(total_records, count_interval);
+
+        if let Some(ref mut writer) = fastq_writer {
+            rec.write(writer)?;
+        }
+    }
+    Ok(FastqIndexEntry { total_records, total_bytes, total_bases })
+}
+
+/// Logs the number of records indexed so far if it is a multiple of the interval, to show progress
+fn log_record_count(total_records: u64, count_interval: Option<u64>) {
+    if count_interval.is_some_and(|interval| total_records.is_multiple_of(interval)) {
+        info!("Indexed {} records", total_records);
+    }
+}
+
+/// The four lines of a FASTQ record read from the raw FASTQ bytes, including their line endings.
+/// Both `fqme index --raw` and `fqme locate` read records this way, so that the bytes of each
+/// record, including any comment on its '+' line, are counted the same.
+#[derive(Debug, Default)]
+pub(crate) struct RawRecord {
+    lines: [Vec<u8>; 4],
+    num_lines: usize,
+}
+
+impl RawRecord {
+    /// Reads the lines of the next record, returning the number of lines read: zero at the end of
+    /// the FASTQ, and fewer than four if the record is truncated.
+    pub(crate) fn read<R: BufRead>(&mut self, reader: &mut R) -> io::Result<usize> {
+        self.num_lines = 0;
+        self.lines.iter_mut().for_each(Vec::clear);
+        for line in self.lines.iter_mut() {
+            if reader.read_until(b'\n', line)? == 0 {
                 break;
             }
-            start_byte = entry.total_bytes;
-            last_total_records = entry.total_records;
-            start_entry += 1;
+            self.num_lines += 1;
+        }
+        Ok(self.num_lines)
+    }
+
+    /// The header line, including the leading '@' and the line ending
+    pub(crate) fn header(&self) -> &[u8] {
+        &self.lines[0]
+    }
+
+    /// Checks that the record has all four lines, that its header starts with '@', and that its
+    /// third line starts with '+'.
+    pub(crate) fn check(&self, record_number: RecordNum) -> Result<()> {
+        ensure!(
+            self.num_lines == 4,
+            "FASTQ record #{} is truncated: found {} of 4 lines",
+            record_number,
+            self.num_lines
+        );
+        ensure!(
+            self.lines[0].starts_with(b"@"),
+            "FASTQ record #{} does not start with '@'",
+            record_number
+        );
+        ensure!(
+            self.lines[2].starts_with(b"+"),
+            "FASTQ record #{} does not have a '+' on its third line",
+            record_number
+        );
+        Ok(())
+    }
+
+    /// The number of bytes that the record spans in the FASTQ
+    pub(crate) fn num_bytes(&self) -> u64 {
+        self.lines.iter().map(|line| line.len() as u64).sum()
+    }
+
+    /// The number of bases in the record
+    pub(crate) fn num_bases(&self) -> u64 {
+        trim_line_ending(&self.lines[1]).len() as u64
+    }
+
+    /// Writes the lines of the record as they were read
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.lines.iter().try_for_each(|line| writer.write_all(line))
+    }
+
+    /// Returns the record without its line endings or any comment on its '+' line.  The record
+    /// must have been checked with `check`.
+    pub(crate) fn to_owned_record(&self) -> OwnedRecord {
+        OwnedRecord {
+            head: trim_line_ending(&self.lines[0])[1..].to_vec(),
+            seq: trim_line_ending(&self.lines[1]).to_vec(),
+            qual: trim_line_ending(&self.lines[3]).to_vec(),
+        }
+    }
+}
+
+/// Returns the line without its trailing line ending ('\n' or "\r\n")
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let length = line.iter().rposition(|byte| !matches!(byte, b'\r' | b'\n')).map_or(0, |i| i + 1);
+    &line[..length]
+}
+
+/// Indexes the records in the raw FASTQ bytes, continuing from the given totals of the records
+/// already indexed and the bytes at the last checkpoint entry, optionally writing the bytes to the
+/// given writer.  Each checkpoint entry (per the spacing) is passed to `emit`, and the totals
+/// after the last record are returned.  With `skip_malformed`, records whose header does not start
+/// with '@' are logged and skipped (see `IndexWriter::set_skip_malformed`), and the number of
+/// records indexed is logged every `count_interval` records, if given.  See
+/// `FastqIndex::from_raw_reader`.
+fn index_raw_reader<R: BufRead, F: FnMut(FastqIndexEntry) -> Result<()>>(
+    mut reader: R,
+    spacing: CheckpointSpacing,
+    (from, mut checkpoint_bytes): (FastqIndexEntry, u64),
+    (skip_malformed, count_interval): (bool, Option<u64>),
+    fastq_writer: &mut Option<BufWriter<Stdout>>,
+    mut emit: F,
+) -> Result<FastqIndexEntry> {
+    let FastqIndexEntry { mut total_records, mut total_bytes, mut total_bases } = from;
+    let mut record = RawRecord::default();
+    while record.read(&mut reader)? > 0 {
+        if !record.header().starts_with(b"@") {
+            if skip_malformed {
+                warn!(
+                    "Skipping a malformed FASTQ record after record #{}, as its header does not \
+                     start with '@': {}",
+                    total_records,
+                    String::from_utf8_lossy(record.header()).trim_end()
+                );
+                continue;
+            }
+            bail!(
+                "FASTQ record #{} does not start with '@'; the FASTQ may be corrupt (see \
+                 --skip-malformed)",
+                total_records + 1
+            );
         }
+        record.check(RecordNum(total_records + 1))?;
+
+        let totals = FastqIndexEntry { total_records, total_bytes, total_bases };
+        if spacing.is_checkpoint(&totals, checkpoint_bytes) {
+            checkpoint_bytes = total_bytes;
+            emit(totals)?;
+        }
+
+        total_records += 1;
+        total_bytes += record.num_bytes();
+        total_bases = total_bases.map(|bases| bases + record.num_bases());
+        log_record_count(total_records, count_interval);
+
+        if let Some(ref mut writer) = fastq_writer {
+            record.write_to(writer)?;
+        }
+    }
+    Ok(FastqIndexEntry { total_records, total_bytes, total_bases })
+}
+
+/// Writes the index header, padded so that the entries start at a multiple of `align` bytes, or
+/// unpadded if `align` is zero.  A padded header is written in `ALIGNED_FORMAT_VERSION`.
+fn write_header<W: Write>(
+    writer: &mut W,
+    total_records: u64,
+    spacing: CheckpointSpacing,
+    source_crc32: Option<u32>,
+    has_bases: bool,
+    estimated_bytes: bool,
+    align: u64,
+) -> io::Result<()> {
+    let mut flags = if source_crc32.is_some() { FLAG_SOURCE_CRC32 } else { 0 };
+    if has_bases {
+        flags |= FLAG_TOTAL_BASES;
+    }
+    if estimated_bytes {
+        flags |= FLAG_ESTIMATED_BYTES;
+    }
+    let nth = match spacing {
+        CheckpointSpacing::Records(nth) => nth,
+        CheckpointSpacing::Bytes(nth_bytes) => {
+            flags |= FLAG_BYTE_SPACED;
+            nth_bytes
+        }
+    };
+    let version = if align == 0 { FORMAT_VERSION } else { ALIGNED_FORMAT_VERSION };
+    writer.write_all(&MAGIC)?;
+    writer.write_u32::<LittleEndian>(version)?;
+    writer.write_u64::<LittleEndian>(total_records)?;
+    writer.write_u64::<LittleEndian>(nth)?;
+    writer.write_u32::<LittleEndian>(flags)?;
+    writer.write_u32::<LittleEndian>(source_crc32.unwrap_or(0))?;
+    if align != 0 {
+        let num_bytes = ALIGNED_HEADER_BYTES.next_multiple_of(align);
+        writer.write_u64::<LittleEndian>(num_bytes)?;
+        io::copy(&mut io::repeat(0).take(num_bytes - ALIGNED_HEADER_BYTES), writer)?;
+    }
+    Ok(())
+}
+
+/// Writes an index entry, with the total number of bases if `has_bases` is true
+fn write_entry<W: Write>(
+    writer: &mut W,
+    entry: &FastqIndexEntry,
+    has_bases: bool,
+) -> io::Result<()> {
+    writer.write_u64::<LittleEndian>(entry.total_records)?;
+    writer.write_u64::<LittleEndian>(entry.total_bytes)?;
+    if has_bases {
+        writer.write_u64::<LittleEndian>(entry.total_bases.unwrap_or(0))?;
+    }
+    Ok(())
+}
+
+/// A function called with each checkpoint entry as it is written (see `IndexWriter::on_checkpoint`)
+pub type CheckpointCallback = Box<dyn FnMut(&FastqIndexEntry)>;
+
+/// Writes a FASTQ index as the FASTQ is indexed, rather than holding every entry in memory, so
+/// that memory use does not grow with the size of the FASTQ.  The header is written first with
+/// placeholder values, then each checkpoint entry as it is found, and finally the header is
+/// patched with the total number of records and source CRC32.  The output is byte-identical to
+/// `FastqIndex::write_to`.
+pub struct IndexWriter<W: Write + Seek> {
+    writer: W,
+    spacing: CheckpointSpacing,
+    // the number of bytes at the last checkpoint entry
+    checkpoint_bytes: u64,
+    // the number of entries written, excluding the final entry
+    num_entries: usize,
+    // the totals of the records indexed so far, which is the final entry
+    last: FastqIndexEntry,
+    // true if the entries store the total number of bases
+    has_bases: bool,
+    // true if the byte offsets were estimated from parsed records that were not written
+    estimated_bytes: bool,
+    // the alignment of the first entry, or zero if the header is not padded
+    align: u64,
+    // called with each checkpoint entry as it is written
+    on_checkpoint: Vec<CheckpointCallback>,
+    // true to skip records whose header does not start with '@' when reading raw FASTQ bytes
+    skip_malformed: bool,
+    // log the number of records indexed every this many records
+    count_interval: Option<u64>,
+}
+
+impl<W: Write + Seek> IndexWriter<W> {
+    /// Creates a writer for an index of every nth record, writing a placeholder header.
+    pub fn new(writer: W, nth: u64) -> Result<IndexWriter<W>> {
+        IndexWriter::with_align(writer, nth, 0)
+    }
+
+    /// Creates a writer for an index of every nth record, whose header is padded with zeros so
+    /// that the first entry starts at a multiple of `align` bytes (e.g. for memory mapping the
+    /// entries at an aligned offset).  The header is not padded if `align` is zero.
+    pub fn with_align(writer: W, nth: u64, align: u64) -> Result<IndexWriter<W>> {
+        IndexWriter::with_spacing(writer, CheckpointSpacing::Records(nth), align)
+    }
+
+    /// Creates a writer for an index whose entries are spaced as given (e.g. by uncompressed bytes
+    /// rather than records), whose header is padded per `align` (see `with_align`).  Errors if the
+    /// entries are spaced by zero bytes.
+    pub fn with_spacing(
+        mut writer: W,
+        spacing: CheckpointSpacing,
+        align: u64,
+    ) -> Result<IndexWriter<W>> {
+        spacing.check()?;
+        write_header(&mut writer, 0, spacing, None, true, false, align)?;
+        Ok(IndexWriter {
+            writer,
+            spacing,
+            checkpoint_bytes: 0,
+            num_entries: 0,
+            last: FastqIndexEntry::empty(),
+            has_bases: true,
+            estimated_bytes: false,
+            align,
+            on_checkpoint: vec![],
+            skip_malformed: false,
+            count_interval: None,
+        })
+    }
+
+    /// Creates a writer that continues the given index, for records appended to its FASTQ.  The
+    /// index's entries are written, except for its final entry, which is written by `finish`.  The
+    /// header is padded per `align` (see `with_align`).
+    pub fn from_index(writer: W, index: &FastqIndex, align: u64) -> Result<IndexWriter<W>> {
+        let mut index_writer = IndexWriter::with_spacing(writer, index.spacing(), align)?;
+        // An index without the number of bases (e.g. from an older version) continues without them
+        index_writer.has_bases = index.has_bases();
+        index_writer.estimated_bytes = index.estimated_bytes;
+        if let Some((last, entries)) = index.entries.split_last() {
+            for entry in entries {
+                write_entry(&mut index_writer.writer, entry, index_writer.has_bases)?;
+            }
+            index_writer.num_entries = entries.len();
+            index_writer.checkpoint_bytes = entries.last().map_or(0, |entry| entry.total_bytes);
+            index_writer.last = last.clone();
+        }
+        Ok(index_writer)
+    }
+
+    /// Indexes the given FASTQ records, optionally writing each record to the given writer.  See
+    /// `FastqIndex::append`.
+    pub fn append(
+        &mut self,
+        records: impl IntoIterator<Item = Result<OwnedRecord, Error>>,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<()> {
+        let (writer, num_entries, has_bases, on_checkpoint) =
+            (&mut self.writer, &mut self.num_entries, self.has_bases, &mut self.on_checkpoint);
+        let (spacing, last, count_interval) =
+            (self.spacing, self.last.clone(), self.count_interval);
+        let checkpoint_bytes = &mut self.checkpoint_bytes;
+        let from = *checkpoint_bytes;
+        self.last =
+            index_records(records, spacing, last, from, count_interval, fastq_writer, |entry| {
+                *checkpoint_bytes = entry.total_bytes;
+                *num_entries += 1;
+                for on_checkpoint in on_checkpoint.iter_mut() {
+                    on_checkpoint(&entry);
+                }
+                Ok(write_entry(writer, &entry, has_bases)?)
+            })?;
+        self.estimated_bytes |= fastq_writer.is_none();
+        Ok(())
+    }
+
+    /// Indexes the records in the raw FASTQ bytes, optionally writing the bytes to the given
+    /// writer.  See `FastqIndex::append_raw_reader`.
+    pub fn append_raw_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        fastq_writer: &mut Option<BufWriter<Stdout>>,
+    ) -> Result<()> {
+        let (writer, num_entries, has_bases, on_checkpoint) =
+            (&mut self.writer, &mut self.num_entries, self.has_bases, &mut self.on_checkpoint);
+        let (spacing, options) = (self.spacing, (self.skip_malformed, self.count_interval));
+        let (from, checkpoint_bytes) = (self.last.clone(), &mut self.checkpoint_bytes);
+        let from = (from, *checkpoint_bytes);
+        self.last = index_raw_reader(reader, spacing, from, options, fastq_writer, |entry| {
+            *checkpoint_bytes = entry.total_bytes;
+            *num_entries += 1;
+            for on_checkpoint in on_checkpoint.iter_mut() {
+                on_checkpoint(&entry);
+            }
+            Ok(write_entry(writer, &entry, has_bases)?)
+        })?;
+        Ok(())
+    }
+
+    /// Indexes a single record with the given number of bytes and bases, for records that are
+    /// written elsewhere (e.g. by `extract --reindex`) rather than read by the index writer.
+    pub fn add_record(&mut self, num_bytes: u64, num_bases: u64) -> Result<()> {
+        if self.spacing.is_checkpoint(&self.last, self.checkpoint_bytes) {
+            self.checkpoint_bytes = self.last.total_bytes;
+            for on_checkpoint in &mut self.on_checkpoint {
+                on_checkpoint(&self.last);
+            }
+            write_entry(&mut self.writer, &self.last, self.has_bases)?;
+            self.num_entries += 1;
+        }
+        self.last.total_records += 1;
+        self.last.total_bytes += num_bytes;
+        self.last.total_bases = self.last.total_bases.map(|bases| bases + num_bases);
+        Ok(())
+    }
+
+    /// Calls the given function with each checkpoint entry (every nth record) as it is written,
+    /// e.g. to report progress, after any functions given previously.  The final entry, written by
+    /// `finish`, is not a checkpoint.
+    pub fn on_checkpoint(&mut self, on_checkpoint: CheckpointCallback) {
+        self.on_checkpoint.push(on_checkpoint);
+    }
+
+    /// Skips the records whose header does not start with '@', rather than failing, when indexing
+    /// the raw FASTQ bytes (see `append_raw_reader`).  Each skipped record is logged, and neither
+    /// its bytes nor the record are indexed, nor is it written to the FASTQ writer, so the written
+    /// FASTQ must be the one that is indexed.
+    pub fn set_skip_malformed(&mut self, skip_malformed: bool) {
+        self.skip_malformed = skip_malformed;
+    }
+
+    /// Logs the number of records indexed so far every `count_interval` records as they are
+    /// indexed (by `append` or `append_raw_reader`), to show progress, regardless of how the
+    /// checkpoint entries are spaced.  Progress is not logged if `None`.
+    pub fn set_count_interval(&mut self, count_interval: Option<u64>) {
+        self.count_interval = count_interval;
+    }
+
+    /// The totals of the records indexed so far
+    pub fn totals(&self) -> &FastqIndexEntry {
+        &self.last
+    }
+
+    /// The number of records between entries, or zero if only the totals are stored or the
+    /// entries are spaced by bytes
+    pub fn nth(&self) -> u64 {
+        self.spacing.nth()
+    }
+
+    /// How the checkpoint entries are spaced
+    pub fn spacing(&self) -> CheckpointSpacing {
+        self.spacing
+    }
+
+    /// The number of entries in the index, including the final entry
+    pub fn num_entries(&self) -> usize {
+        self.num_entries + 1
+    }
+
+    /// Writes the final entry and patches the header, returning the inner writer.
+    pub fn finish(mut self, source_crc32: Option<u32>) -> Result<W> {
+        write_entry(&mut self.writer, &self.last, self.has_bases)?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        let (total_records, spacing) = (self.last.total_records, self.spacing);
+        let (has_bases, estimated_bytes, align) =
+            (self.has_bases, self.estimated_bytes, self.align);
+        write_header(
+            &mut self.writer,
+            total_records,
+            spacing,
+            source_crc32,
+            has_bases,
+            estimated_bytes,
+            align,
+        )?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+pub struct FastqIndexRange {
+    // the uncompressed start byte range
+    pub start_byte: UncompressedOffset,
+    // the uncompressed end byte range
+    pub end_byte: UncompressedOffset,
+    // the number of leading records in the range
+    pub leading_records: u64,
+    // the number of trailing records in the range
+    pub trailing_records: u64,
+    // the total number of records in the range, being the records in the indexed chunks that span
+    // the query (including the leading and trailing records), not only those selected.  The last
+    // chunk may have fewer than nth records.  See `selected_records` for the records queried.
+    pub total_records: u64,
+}
+
+impl FastqIndexRange {
+    /// The range of the records from `start_record` to `end_record` (1-based inclusive), clamped to
+    /// the end entry, read from the start entry (or the start of the FASTQ if `None`) to the end
+    /// entry (see `covering_entries`).
+    fn between(
+        start_entry: Option<&FastqIndexEntry>,
+        end_entry: &FastqIndexEntry,
+        start_record: u64,
+        end_record: u64,
+    ) -> FastqIndexRange {
+        let (start_byte, start_records) =
+            start_entry.map_or((0, 0), |entry| (entry.total_bytes, entry.total_records));
+        let start_record = start_record.max(1);
+        let end_record = end_record.min(end_entry.total_records);
+        FastqIndexRange {
+            start_byte: UncompressedOffset(start_byte),
+            end_byte: UncompressedOffset(end_entry.total_bytes),
+            leading_records: (start_record - 1) - start_records,
+            trailing_records: end_entry.total_records - end_record,
+            total_records: end_entry.total_records - start_records,
+        }
+    }
+
+    /// the total number of selected records in the range.  If the range is inconsistent (e.g.
+    /// read from a corrupt index), such that there are more leading and trailing records than
+    /// records in total, a warning is logged and zero is returned.
+    pub fn selected_records(self) -> u64 {
+        let selected = self
+            .total_records
+            .checked_sub(self.leading_records)
+            .and_then(|records| records.checked_sub(self.trailing_records));
+        selected.unwrap_or_else(|| {
+            warn!(
+                "Inconsistent FASTQ index range: {} leading and {} trailing records, but only {} \
+                 records in total",
+                self.leading_records, self.trailing_records, self.total_records
+            );
+            0
+        })
+    }
+
+    /// an estimate of the number of uncompressed bytes of the selected records, assuming the
+    /// records in the range are all the same size.  This is the number of bytes in the range
+    /// scaled by the fraction of its records that are selected, rounded to the nearest byte, so is
+    /// exact only when the leading and trailing records are the size of the selected records.
+    /// Returns zero if the range has no records.
+    pub fn selected_byte_estimate(&self) -> u64 {
+        if self.total_records == 0 {
+            return 0;
+        }
+        let selected_records = self.clone().selected_records();
+        (self.num_bytes() as f64 * selected_records as f64 / self.total_records as f64).round()
+            as u64
+    }
+
+    /// the number of uncompressed bytes in the range.  If the range is inconsistent (e.g. read
+    /// from a corrupt index), such that it ends before it starts, a warning is logged and zero is
+    /// returned.
+    pub fn num_bytes(&self) -> u64 {
+        if self.end_byte < self.start_byte {
+            warn!(
+                "Inconsistent FASTQ index range: ends at byte {} before it starts at byte {}",
+                self.end_byte, self.start_byte
+            );
+            return 0;
+        }
+        self.end_byte - self.start_byte
+    }
+
+    /// the `bgzip` command that decompresses the uncompressed bytes in the range from the given
+    /// bgzip'ed FASTQ, using its BGZF index (`.gzi`).
+    pub fn bgzip_command(&self, input: &Path) -> String {
+        format!("bgzip -b {} -s {} {}", self.start_byte, self.num_bytes(), input.display())
+    }
+}
+
+/// The number of records between successive entries of an index, tracked as the entries are read
+/// to infer nth (see `FastqIndex::infer_nth`) without holding the entries.
+#[derive(Debug, Default)]
+struct EntrySpacing {
+    // the spacing of the first checkpoint, being every spacing but the last
+    first: Option<u64>,
+    // true if a checkpoint has a different spacing than the first
+    uneven: bool,
+    // the spacing of the last entry added, which may be shorter than the checkpoints
+    last: Option<u64>,
+}
+
+impl EntrySpacing {
+    /// Adds the number of records between the next entry and the one before it
+    fn add(&mut self, spacing: u64) {
+        // The previous spacing is now known to be a checkpoint, rather than the final entry
+        if let Some(checkpoint) = self.last {
+            match self.first {
+                None => self.first = Some(checkpoint),
+                Some(first) => self.uneven |= checkpoint != first,
+            }
+        }
+        self.last = Some(spacing);
+    }
+
+    /// The spacing of the checkpoints, if evenly spaced and followed by the final entry
+    fn infer_nth(&self) -> Option<u64> {
+        let (nth, last) = (self.first?, self.last?);
+        if 0 < nth && !self.uneven && 0 < last && last <= nth {
+            Some(nth)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks the entries of an index in order as they are read, so that an index read into memory
+/// (`FastqIndex::read_from`) and a memory-mapped index (`MmapIndex`) are validated alike: each entry
+/// must not precede the previous entry, and the last entry must have the header's total number of
+/// records.
+#[derive(Debug, Default)]
+struct EntryChecker {
+    num_entries: usize,
+    last: Option<FastqIndexEntry>,
+    spacing: EntrySpacing,
+}
+
+impl EntryChecker {
+    /// Checks the next entry against the previous entry
+    fn check(&mut self, entry: &FastqIndexEntry) -> Result<()> {
+        if let Some(last) = &self.last {
+            ensure!(
+                last.total_records <= entry.total_records
+                    && last.total_bytes <= entry.total_bytes
+                    && last.total_bases <= entry.total_bases,
+                "FASTQ index entry #{} ({:?}) precedes the previous entry ({:?})",
+                self.num_entries + 1,
+                entry,
+                last
+            );
+            self.spacing.add(entry.total_records - last.total_records);
+        }
+        self.num_entries += 1;
+        self.last = Some(entry.clone());
+        Ok(())
+    }
+
+    /// Checks that there are entries, and that the last has the given total number of records
+    fn finish(&self, total_records: u64) -> Result<()> {
+        match &self.last {
+            None => bail!("FASTQ index has no entries"),
+            Some(last) => ensure!(
+                last.total_records == total_records,
+                "FASTQ index header has {} records but the last entry has {} records",
+                total_records,
+                last.total_records
+            ),
+        }
+        Ok(())
+    }
+
+    /// The nth to use given the header's nth, which is the spacing of the entries if it can be
+    /// inferred from them (e.g. for a hand-edited index), as the entries are what is used to find
+    /// records.  Entries spaced by bytes are not every nth, so the header's nth is kept.  Warns
+    /// once if the index has no checkpoints, as every range is then read from the start.
+    fn nth(&self, nth: u64, nth_bytes: Option<u64>) -> u64 {
+        if nth == 0 && self.num_entries <= 2 {
+            warn!(
+                "FASTQ index has no checkpoints (nth = 0), so reading from the start of the FASTQ"
+            );
+        }
+        match self.spacing.infer_nth().filter(|_| nth_bytes.is_none()) {
+            Some(inferred) if inferred != nth => {
+                warn!(
+                    "FASTQ index header has nth = {} but its entries are every {} records; using \
+                     {}",
+                    nth, inferred, inferred
+                );
+                inferred
+            }
+            _ => nth,
+        }
+    }
+}
+
+/// A memory-mapped FASTQ index (see `FastqIndex::open_mmap`).  The entries are fixed-size and
+/// follow the header, so each entry is read from its offset in the mapped bytes, and a range is
+/// found by binary searching the entries.  The entries are validated once when the index is
+/// opened, but never held in memory, so memory use does not grow with the size of the index.
+pub struct MmapIndex {
+    mmap: Mmap,
+    total_records: u64,
+    nth: u64,
+    nth_bytes: Option<u64>,
+    source_crc32: Option<u32>,
+    estimated_bytes: bool,
+    // the number of bytes in the header, where the entries start
+    header_bytes: usize,
+    // the number of bytes per entry
+    entry_bytes: usize,
+    has_bases: bool,
+    num_entries: usize,
+}
+
+impl MmapIndex {
+    /// Validates the header and entries of the mapped index
+    fn new(mmap: Mmap) -> Result<MmapIndex> {
+        ensure!(
+            LEGACY_HEADER_BYTES <= mmap.len() as u64,
+            "FASTQ index is too short ({} bytes) to contain a header",
+            mmap.len()
+        );
+        let header = FastqIndex::parse_header(&mut &mmap[..])?;
+        let entries_bytes = mmap.len() as u64 - header.num_bytes;
+        let entry_bytes = header.entry_bytes();
+        ensure!(
+            entries_bytes.is_multiple_of(entry_bytes),
+            "FASTQ index has a truncated entry ({} trailing bytes)",
+            entries_bytes % entry_bytes
+        );
+        let mut index = MmapIndex {
+            total_records: header.total_records,
+            nth: header.nth,
+            nth_bytes: header.nth_bytes,
+            source_crc32: header.source_crc32,
+            estimated_bytes: header.estimated_bytes,
+            header_bytes: header.num_bytes as usize,
+            entry_bytes: entry_bytes as usize,
+            has_bases: header.has_bases,
+            num_entries: (entries_bytes / entry_bytes) as usize,
+            mmap,
+        };
+        // The entries are checked as when read into memory, reading each from the mapping in turn
+        // rather than holding them
+        let mut checker = EntryChecker::default();
+        for i in 0..index.num_entries {
+            checker.check(&index.entry(i))?;
+        }
+        checker.finish(index.total_records)?;
+        index.nth = checker.nth(header.nth, header.nth_bytes);
+        Ok(index)
+    }
+
+    /// The total number of records in the FASTQ
+    pub fn total_records(&self) -> u64 {
+        self.total_records
+    }
+
+    /// The number of records between entries, as inferred from the entries when they disagree with
+    /// the header (see `FastqIndex::read_from`)
+    pub fn nth(&self) -> u64 {
+        self.nth
+    }
+
+    /// How the checkpoint entries are spaced
+    pub fn spacing(&self) -> CheckpointSpacing {
+        match self.nth_bytes {
+            Some(nth_bytes) => CheckpointSpacing::Bytes(nth_bytes),
+            None => CheckpointSpacing::Records(self.nth),
+        }
+    }
+
+    /// The number of entries in the index
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Reads the entry at the given 0-based index, which must be less than `num_entries`
+    pub fn entry(&self, index: usize) -> FastqIndexEntry {
+        let offset = self.header_bytes + index * self.entry_bytes;
+        let bytes = &self.mmap[offset..offset + self.entry_bytes];
+        FastqIndexEntry {
+            total_records: LittleEndian::read_u64(&bytes[0..8]),
+            total_bytes: LittleEndian::read_u64(&bytes[8..16]),
+            total_bases: self.has_bases.then(|| LittleEndian::read_u64(&bytes[16..24])),
+        }
+    }
+
+    /// Returns the same range as `FastqIndex::range`, reading only the entries needed to find it.
+    // NB: start_record and end_record are 1-based inclusive
+    pub fn range(&self, start_record: RecordNum, end_record: RecordNum) -> Option<FastqIndexRange> {
+        let (start_record, end_record) = (u64::from(start_record), u64::from(end_record));
+        let (start_entry, end_entry) = self.covering_entries(start_record, end_record)?;
+
+        let start_entry = start_entry.map(|index| self.entry(index));
+        Some(FastqIndexRange::between(
+            start_entry.as_ref(),
+            &self.entry(end_entry),
+            start_record,
+            end_record,
+        ))
+    }
+
+    /// Returns the same entries as `FastqIndex::covering_entries`, binary searching the entries in
+    /// the mapping.
+    pub fn covering_entries(
+        &self,
+        start_record: u64,
+        end_record: u64,
+    ) -> Option<(Option<usize>, usize)> {
+        let entry = |index: usize| self.entry(index);
+        covering_entries(self.num_entries, entry, self.total_records, start_record, end_record)
+    }
+}
+
+/// Returns the indices of the checkpoint entries that a range reads between for the records from
+/// `start_record` to `end_record` (1-based inclusive), given the number of entries and a function
+/// to read each entry (see `FastqIndex::covering_entries`).
+fn covering_entries<F: Fn(usize) -> FastqIndexEntry>(
+    num_entries: usize,
+    entry: F,
+    total_records: u64,
+    start_record: u64,
+    end_record: u64,
+) -> Option<(Option<usize>, usize)> {
+    if end_record < start_record || end_record < 1 || total_records < start_record {
+        return None;
+    }
+    let last_entry = num_entries.checked_sub(1)?;
+    let start_record = start_record.max(1);
+    let end_record = end_record.min(total_records);
+    // The index of the first entry with at least the given number of records, or `num_entries` if
+    // there is none, found by binary search
+    let first_entry_with = |records: u64| {
+        let (mut low, mut high) = (0, num_entries);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if entry(mid).total_records < records {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    };
+    // Reading starts at the entry before the first entry with the start record, if any, as the
+    // first entry may have records (e.g. a hand-edited index)
+    let start_entry = first_entry_with(start_record).checked_sub(1);
+    // The last entry has every record, unless the entries are out of order (e.g. corrupt)
+    let end_entry = first_entry_with(end_record).min(last_entry);
+    Some((start_entry, end_entry))
+}
+
+/// The queries used to extract records from a FASTQ index, so that the index may either be read
+/// into memory (`FastqIndex`) or memory mapped (`MmapIndex`).
+pub trait RecordIndex: Send + Sync {
+    /// The total number of records in the FASTQ
+    fn total_records(&self) -> u64;
+
+    /// The number of records between entries
+    fn nth(&self) -> u64;
+
+    /// How the checkpoint entries are spaced, e.g. to index records extracted from the FASTQ alike
+    fn spacing(&self) -> CheckpointSpacing;
+
+    /// The total number of uncompressed bytes in the FASTQ
+    fn total_bytes(&self) -> u64;
+
+    /// The mean number of bytes per record, or `None` if there are no records; see
+    /// `FastqIndex::mean_record_bytes`
+    fn mean_record_bytes(&self) -> Option<f64>;
+
+    /// The CRC32 of the uncompressed FASTQ bytes that were indexed, if stored
+    fn source_crc32(&self) -> Option<u32>;
+
+    /// True if the byte offsets were estimated from the parsed records, so may be incorrect (see
+    /// `FastqIndex::from`)
+    fn estimated_bytes(&self) -> bool;
+
+    /// The uncompressed bytes spanning the records from `start_record` to `end_record` (1-based
+    /// inclusive); see `FastqIndex::range`
+    fn range(&self, start_record: RecordNum, end_record: RecordNum) -> Option<FastqIndexRange>;
+}
+
+impl RecordIndex for FastqIndex {
+    fn total_records(&self) -> u64 {
+        self.total_records
+    }
+
+    fn nth(&self) -> u64 {
+        self.nth
+    }
+
+    fn spacing(&self) -> CheckpointSpacing {
+        FastqIndex::spacing(self)
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.entries.last().map_or(0, |entry| entry.total_bytes)
+    }
+
+    fn mean_record_bytes(&self) -> Option<f64> {
+        FastqIndex::mean_record_bytes(self)
+    }
+
+    fn source_crc32(&self) -> Option<u32> {
+        self.source_crc32
+    }
+
+    fn estimated_bytes(&self) -> bool {
+        self.estimated_bytes
+    }
+
+    fn range(&self, start_record: RecordNum, end_record: RecordNum) -> Option<FastqIndexRange> {
+        FastqIndex::range(self, start_record, end_record)
+    }
+}
+
+impl RecordIndex for MmapIndex {
+    fn total_records(&self) -> u64 {
+        self.total_records
+    }
+
+    fn nth(&self) -> u64 {
+        self.nth
+    }
 
-        // TODO: binary search
-        for i in start_entry..self.entries.len() {
-            let entry = &self.entries[i];
+    fn spacing(&self) -> CheckpointSpacing {
+        MmapIndex::spacing(self)
+    }
 
-            if end_record <= entry.total_records {
-                trailing_records = entry.total_records - end_record;
-                end_byte = entry.total_bytes;
-                total_records = entry.total_records - last_total_records;
-                break;
-            }
-        }
+    fn total_bytes(&self) -> u64 {
+        self.entry(self.num_entries - 1).total_bytes
+    }
 
-        Some(FastqIndexRange {
-            start_byte,
-            end_byte,
-            leading_records,
-            trailing_records,
-            total_records,
-        })
+    fn mean_record_bytes(&self) -> Option<f64> {
+        let last = self.entry(self.num_entries - 1);
+        (0 < last.total_records).then(|| last.total_bytes as f64 / last.total_records as f64)
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
-pub struct FastqIndexRange {
-    // the uncompressed start byte range
-    pub start_byte: u64,
-    // the uncompressed end byte range
-    pub end_byte: u64,
-    // the number of leading records in the range
-    pub leading_records: u64,
-    // the number of trailing records in the range
-    pub trailing_records: u64,
-    // the total number of records in the range
-    pub total_records: u64,
-}
+    fn source_crc32(&self) -> Option<u32> {
+        self.source_crc32
+    }
 
-impl FastqIndexRange {
-    /// the total number of selected records in the ragne
-    pub fn selected_records(self) -> u64 {
-        self.total_records - self.leading_records - self.trailing_records
+    fn estimated_bytes(&self) -> bool {
+        self.estimated_bytes
     }
 
-    pub fn num_bytes(&self) -> u64 {
-        self.end_byte - self.start_byte
+    fn range(&self, start_record: RecordNum, end_record: RecordNum) -> Option<FastqIndexRange> {
+        MmapIndex::range(self, start_record, end_record)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::tools::fastq_index::{FastqIndex, FastqIndexEntry};
-    use seq_io::fastq::{Error, OwnedRecord};
+    use std::{
+        cell::RefCell,
+        io::{BufReader, Cursor},
+        path::Path,
+        rc::Rc,
+    };
 
-    use super::FastqIndexRange;
+    use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+    use tempfile::TempDir;
 
-    fn record() -> OwnedRecord {
-        OwnedRecord {
-            head: b"some-read-name".to_vec(),
-            seq: b"GATTACA".to_vec(),
-            qual: b"IIIIIII".to_vec(),
-        }
-    }
+    use crate::{
+        testutil::{capture_logs, captured_logs, fixed_record, index, to_fastq},
+        tools::fastq_index::{
+            CheckpointSpacing, FastqIndex, FastqIndexEntry, IndexWriter, ALIGNED_FORMAT_VERSION,
+            FORMAT_VERSION,
+        },
+        types::{RecordNum, UncompressedOffset},
+        utils::TrailingBlankLines,
+    };
+    use seq_io::{
+        fastq::{Error, OwnedRecord},
+        BaseRecord,
+    };
 
-    fn index() -> FastqIndex {
-        let records: Vec<Result<OwnedRecord, Error>> = {
-            vec![
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-                Ok(record()),
-            ]
-        };
-        FastqIndex::from(records, 3, &mut None)
-    }
+    use super::FastqIndexRange;
 
     #[test]
     fn test_fastq_index_record_to_num_bytes() {
-        assert_eq!(FastqIndex::record_to_num_bytes(&record()), 34);
+        assert_eq!(FastqIndex::record_to_num_bytes(&fixed_record()), 34);
     }
 
     fn test_fastq_index_from(
@@ -212,14 +1728,15 @@ mod test {
         index_entries: usize,
     ) {
         let num_input_records = records.len();
-        let index = FastqIndex::from(records, nth, &mut None);
-        let record_num_bytes = FastqIndex::record_to_num_bytes(&record());
+        let index = FastqIndex::from(records, nth, &mut None).unwrap();
+        let record_num_bytes = FastqIndex::record_to_num_bytes(&fixed_record());
         assert_eq!(index.entries.len(), index_entries);
         for i in 0..nth as usize {
             let num_records: u64 = std::cmp::min(num_input_records as u64, nth * i as u64);
             let expected: FastqIndexEntry = FastqIndexEntry {
                 total_records: num_records,
                 total_bytes: record_num_bytes * num_records as u64,
+                total_bases: Some(7 * num_records),
             };
             assert_eq!(index.entries[i], expected);
         }
@@ -229,15 +1746,20 @@ mod test {
     fn test_fastq_index_from_even_with_even_nth() {
         // even # of inputs, even nth
         let records: Vec<Result<OwnedRecord, Error>> =
-            vec![Ok(record()), Ok(record()), Ok(record()), Ok(record())];
+            vec![Ok(fixed_record()), Ok(fixed_record()), Ok(fixed_record()), Ok(fixed_record())];
         test_fastq_index_from(records, 2, 3);
     }
     #[test]
 
     fn test_fastq_index_from_odd_with_even_nth() {
         // even # of inputs, even nth
-        let records: Vec<Result<OwnedRecord, Error>> =
-            vec![Ok(record()), Ok(record()), Ok(record()), Ok(record()), Ok(record())];
+        let records: Vec<Result<OwnedRecord, Error>> = vec![
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+        ];
         test_fastq_index_from(records, 2, 4);
     }
 
@@ -245,23 +1767,406 @@ mod test {
     fn test_fastq_index_from_even_with_odd_nth() {
         // even # of inputs, even nth
         let records: Vec<Result<OwnedRecord, Error>> =
-            vec![Ok(record()), Ok(record()), Ok(record()), Ok(record())];
+            vec![Ok(fixed_record()), Ok(fixed_record()), Ok(fixed_record()), Ok(fixed_record())];
         test_fastq_index_from(records, 3, 3);
     }
 
     #[test]
     fn test_fastq_index_from_odd_with_odd_nth() {
         // even # of inputs, even nth
-        let records: Vec<Result<OwnedRecord, Error>> =
-            vec![Ok(record()), Ok(record()), Ok(record()), Ok(record()), Ok(record())];
+        let records: Vec<Result<OwnedRecord, Error>> = vec![
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+            Ok(fixed_record()),
+        ];
         test_fastq_index_from(records, 3, 3);
     }
 
+    #[test]
+    fn test_fastq_index_from_multiple_of_nth() {
+        // the final entry is not duplicated when the number of records is a multiple of nth, as
+        // entries are added before the Nth record is read
+        let records = vec![fixed_record(); 9].into_iter().map(Ok);
+        let index = FastqIndex::from(records, 3, &mut None).unwrap();
+        let totals: Vec<u64> = index.entries.iter().map(|entry| entry.total_records).collect();
+        assert_eq!(totals, vec![0, 3, 6, 9]);
+
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..9 {
+            fixed_record().write(&mut fastq).unwrap();
+        }
+        assert_eq!(
+            estimated(FastqIndex::from_raw_reader(&fastq[..], 3, &mut None).unwrap()),
+            index
+        );
+
+        // boundary queries
+        let range = index.range(RecordNum(9), RecordNum(9)).unwrap();
+        assert_eq!((range.start_byte.0, range.end_byte.0), (204, 306));
+        assert_eq!((range.leading_records, range.trailing_records), (2, 0));
+        assert_eq!(range.selected_records(), 1);
+        let range = index.range(RecordNum(7), RecordNum(9)).unwrap();
+        assert_eq!((range.start_byte.0, range.end_byte.0), (204, 306));
+        assert_eq!(range.selected_records(), 3);
+        let range = index.range(RecordNum(3), RecordNum(4)).unwrap();
+        assert_eq!((range.start_byte.0, range.end_byte.0), (0, 204));
+        assert_eq!(range.selected_records(), 2);
+        let range = index.range(RecordNum(1), RecordNum(9)).unwrap();
+        assert_eq!((range.start_byte.0, range.end_byte.0), (0, 306));
+        assert_eq!(range.selected_records(), 9);
+        assert_eq!(index.range(RecordNum(10), RecordNum(10)), None);
+    }
+
+    #[test]
+    fn test_fastq_index_header_only() {
+        let records = vec![fixed_record(); 8].into_iter().map(Ok);
+        let index = FastqIndex::from(records, 0, &mut None).unwrap();
+        let expected = vec![
+            FastqIndexEntry { total_records: 0, total_bytes: 0, total_bases: Some(0) },
+            FastqIndexEntry { total_records: 8, total_bytes: 272, total_bases: Some(56) },
+        ];
+        assert_eq!(index.entries, expected);
+        assert_eq!(index.total_records, 8);
+
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            fixed_record().write(&mut fastq).unwrap();
+        }
+        assert_eq!(
+            estimated(FastqIndex::from_raw_reader(&fastq[..], 0, &mut None).unwrap()),
+            index
+        );
+
+        // ranges read from the start of the FASTQ
+        let expected = FastqIndexRange {
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(272),
+            leading_records: 4,
+            trailing_records: 2,
+            total_records: 8,
+        };
+        assert_eq!(index.range(RecordNum(5), RecordNum(6)), Some(expected));
+        assert_eq!(index.range(RecordNum(1), RecordNum(8)).unwrap().selected_records(), 8);
+        assert_eq!(index.range(RecordNum(8), RecordNum(8)).unwrap().leading_records, 7);
+        assert_eq!(index.range(RecordNum(9), RecordNum(9)), None);
+    }
+
+    #[test]
+    fn test_fastq_index_append() {
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            fixed_record().write(&mut fastq).unwrap();
+        }
+        for nth in [0, 1, 2, 3, 4, 5] {
+            let expected =
+                FastqIndex::from(vec![fixed_record(); 8].into_iter().map(Ok), nth, &mut None);
+            let expected = expected.unwrap();
+
+            let mut index =
+                FastqIndex::from(vec![fixed_record(); 4].into_iter().map(Ok), nth, &mut None)
+                    .unwrap();
+            index.append(vec![fixed_record(); 4].into_iter().map(Ok), &mut None).unwrap();
+            assert_eq!(index, expected, "nth: {}", nth);
+
+            let mut index = FastqIndex::from_raw_reader(&fastq[..136], nth, &mut None).unwrap();
+            index.append_raw_reader(&fastq[136..], &mut None).unwrap();
+            assert_eq!(estimated(index), expected, "nth: {}", nth);
+
+            // appending nothing
+            let mut index = expected.clone();
+            index.append_raw_reader(&fastq[..0], &mut None).unwrap();
+            assert_eq!(index, expected, "nth: {}", nth);
+        }
+    }
+
+    #[test]
+    fn test_fastq_index_read_from() {
+        let index: FastqIndex = index(8);
+        let mut bytes: Vec<u8> = vec![];
+        index.write_to(&mut bytes);
+        assert_eq!(bytes.len(), 32 + 24 * index.entries.len());
+        assert_eq!(FastqIndex::read_from(Cursor::new(bytes)).unwrap(), index);
+
+        // with a source CRC32
+        let index = FastqIndex { source_crc32: Some(0xdeadbeef), ..index };
+        let mut bytes: Vec<u8> = vec![];
+        index.write_to(&mut bytes);
+        assert_eq!(FastqIndex::read_from(Cursor::new(bytes)).unwrap(), index);
+
+        // without the number of bases, as in a version 1 index
+        let index = without_bases(index);
+        let mut bytes: Vec<u8> = vec![];
+        index.write_to(&mut bytes);
+        assert_eq!(bytes.len(), 32 + 16 * index.entries.len());
+        assert_eq!(FastqIndex::read_from(Cursor::new(bytes.clone())).unwrap(), index);
+        bytes[4] = 1;
+        assert_eq!(FastqIndex::read_from(Cursor::new(bytes)).unwrap(), index);
+    }
+
+    /// Returns the index as built from the parsed records, whose byte offsets are estimated
+    fn estimated(index: FastqIndex) -> FastqIndex {
+        FastqIndex { estimated_bytes: true, ..index }
+    }
+
+    /// Returns the index as read from an index that does not store the number of bases
+    fn without_bases(mut index: FastqIndex) -> FastqIndex {
+        for entry in &mut index.entries {
+            entry.total_bases = None;
+        }
+        index
+    }
+
+    #[test]
+    fn test_fastq_index_read_legacy() {
+        // a legacy index has no magic bytes, version, flags, source CRC32, or number of bases
+        let expected = FastqIndex { estimated_bytes: false, ..without_bases(index(8)) };
+        let mut bytes: Vec<u8> = vec![];
+        bytes.write_u64::<LittleEndian>(expected.total_records).unwrap();
+        bytes.write_u64::<LittleEndian>(expected.nth).unwrap();
+        for entry in &expected.entries {
+            bytes.write_u64::<LittleEndian>(entry.total_records).unwrap();
+            bytes.write_u64::<LittleEndian>(entry.total_bytes).unwrap();
+        }
+        assert_eq!(FastqIndex::read_from(Cursor::new(bytes.clone())).unwrap(), expected);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(FastqIndex::read(&path).unwrap(), expected);
+
+        // an unknown version
+        let mut bytes: Vec<u8> = vec![];
+        expected.write_to(&mut bytes);
+        bytes[4] = 99;
+        let err = FastqIndex::read_from(Cursor::new(bytes)).unwrap_err();
+        assert!(format!("{:#}", err).contains("Unsupported FASTQ index format version 99"));
+    }
+
+    #[test]
+    fn test_fastq_index_read() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        let expected: FastqIndex = index(8);
+        expected.clone().write(&path);
+        assert_eq!(FastqIndex::read(&path).unwrap(), expected);
+        assert_eq!(FastqIndex::read_header(&path).unwrap(), (8, 3));
+
+        // only the header is read, so the entries are not validated
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        std::fs::write(&path, bytes).unwrap();
+        assert!(FastqIndex::read(&path).is_err());
+        assert_eq!(FastqIndex::read_header(&path).unwrap(), (8, 3));
+    }
+
+    #[test]
+    fn test_fastq_index_read_gzip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi.gz");
+        let expected: FastqIndex = index(8);
+        expected.clone().write(&path);
+        assert_eq!(&std::fs::read(&path).unwrap()[0..2], &[0x1f, 0x8b]);
+        assert_eq!(FastqIndex::read(&path).unwrap(), expected);
+        assert_eq!(FastqIndex::read_version(&path).unwrap(), Some(FORMAT_VERSION));
+        assert_eq!(FastqIndex::read_header(&path).unwrap(), (8, 3));
+
+        // a gzip-compressed index without the .gz extension
+        let renamed = dir.path().join("test.fastq.gz.fqi");
+        std::fs::rename(&path, &renamed).unwrap();
+        assert_eq!(FastqIndex::read(&renamed).unwrap(), expected);
+        assert_eq!(FastqIndex::read_header(&renamed).unwrap(), (8, 3));
+    }
+
+    #[test]
+    fn test_fastq_index_read_truncated_entry() {
+        let mut bytes: Vec<u8> = vec![];
+        index(8).write_to(&mut bytes);
+        bytes.extend_from_slice(&[0u8; 8]);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        std::fs::write(&path, &bytes).unwrap();
+        let err = FastqIndex::read(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("truncated entry (8 trailing bytes)"));
+
+        let err = FastqIndex::read_from(Cursor::new(bytes)).unwrap_err();
+        assert!(format!("{:#}", err).contains("entry #5 is truncated: found 8 of 24 bytes"));
+    }
+
+    #[test]
+    fn test_fastq_index_read_invalid() {
+        // too short for a header
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        std::fs::write(&path, [0u8; 12]).unwrap();
+        let err = FastqIndex::read(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("too short (12 bytes)"));
+
+        // no entries
+        assert!(FastqIndex::read_from(Cursor::new(vec![0u8; 16])).is_err());
+
+        // the header does not agree with the last entry
+        let mut index = index(8);
+        index.total_records = 100;
+        let mut bytes: Vec<u8> = vec![];
+        index.write_to(&mut bytes);
+        let err = FastqIndex::read_from(Cursor::new(bytes)).unwrap_err();
+        assert!(format!("{:#}", err).contains("header has 100 records"));
+
+        // the entries are out of order
+        index.total_records = 8;
+        index.entries.swap(1, 2);
+        let mut bytes: Vec<u8> = vec![];
+        index.write_to(&mut bytes);
+        let err = FastqIndex::read_from(Cursor::new(bytes)).unwrap_err();
+        assert!(format!("{:#}", err).contains("precedes the previous entry"));
+
+        // the memory-mapped index is validated alike
+        index.write(&path);
+        let err = FastqIndex::open_mmap(&path).err().unwrap();
+        assert!(format!("{:#}", err).contains("precedes the previous entry"));
+
+        // byte-spaced entries must be a non-zero number of bytes apart
+        FastqIndex { nth: 0, nth_bytes: Some(0), ..crate::testutil::index(8) }.write(&path);
+        let err = FastqIndex::read(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("spaced by bytes, but every 0 bytes"));
+        let err = FastqIndex::open_mmap(&path).err().unwrap();
+        assert!(format!("{:#}", err).contains("spaced by bytes, but every 0 bytes"));
+    }
+
+    #[test]
+    fn test_fastq_index_from_multi_line_records() {
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\nAC\n+\nIIII\nII\n";
+        let records = seq_io::fastq::Reader::new(&fastq[..]).into_records();
+        let err = FastqIndex::from(records, 1, &mut None).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("Could not parse FASTQ record #2"));
+        assert!(message.contains("multi-line (wrapped) FASTQ is not supported"));
+
+        // other errors only have the record number
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nII\n";
+        let records = seq_io::fastq::Reader::new(&fastq[..]).into_records();
+        let err = FastqIndex::from(records, 1, &mut None).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::UnequalLengths { .. })));
+        assert_eq!(err.to_string(), "Could not parse FASTQ record #2");
+    }
+
+    #[test]
+    fn test_fastq_index_from_raw_reader() {
+        // the same as parsing the records
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            fixed_record().write(&mut fastq).unwrap();
+        }
+        let index = FastqIndex::from_raw_reader(&fastq[..], 3, &mut None).unwrap();
+        assert_eq!(estimated(index), crate::testutil::index(8));
+
+        // '+' line comments are counted
+        let fastq = b"@r1\nACGT\n+r1\nIIII\n@r2\nAC\n+\nII\n@r3\nA\n+r3 comment\nI\n";
+        let index = FastqIndex::from_raw_reader(&fastq[..], 1, &mut None).unwrap();
+        let expected = FastqIndex {
+            total_records: 3,
+            nth: 1,
+            nth_bytes: None,
+            source_crc32: None,
+            estimated_bytes: false,
+            entries: vec![
+                FastqIndexEntry { total_records: 0, total_bytes: 0, total_bases: Some(0) },
+                FastqIndexEntry { total_records: 1, total_bytes: 18, total_bases: Some(4) },
+                FastqIndexEntry { total_records: 2, total_bytes: 30, total_bases: Some(6) },
+                FastqIndexEntry {
+                    total_records: 3,
+                    total_bytes: fastq.len() as u64,
+                    total_bases: Some(7),
+                },
+            ],
+        };
+        assert_eq!(index, expected);
+    }
+
+    #[test]
+    fn test_fastq_index_from_trailing_blank_line() {
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            fixed_record().write(&mut fastq).unwrap();
+        }
+        let index_from = |fastq: &[u8]| {
+            let records = seq_io::fastq::Reader::new(TrailingBlankLines::new(fastq)).into_records();
+            FastqIndex::from(records, 3, &mut None)
+        };
+        let index_from_raw = |fastq: &[u8]| {
+            let reader = BufReader::new(TrailingBlankLines::new(fastq));
+            FastqIndex::from_raw_reader(reader, 3, &mut None).map(estimated)
+        };
+
+        // trailing blank lines are ignored
+        let mut trailing = fastq.clone();
+        trailing.extend_from_slice(b"\n");
+        assert_eq!(index_from(&trailing).unwrap(), index(8));
+        assert_eq!(index_from_raw(&trailing).unwrap(), index(8));
+        trailing.extend_from_slice(b"  \r\n\n");
+        assert_eq!(index_from(&trailing).unwrap(), index(8));
+        assert_eq!(index_from_raw(&trailing).unwrap(), index(8));
+
+        // a blank line between records is an error
+        let mut blank: Vec<u8> = fastq[..34].to_vec();
+        blank.extend_from_slice(b"\n");
+        blank.extend_from_slice(&fastq[34..]);
+        assert!(index_from(&blank).is_err());
+        assert!(index_from_raw(&blank).is_err());
+    }
+
+    #[test]
+    fn test_fastq_index_from_raw_reader_invalid() {
+        let err = FastqIndex::from_raw_reader(&b"@r1\nACGT\n+\n"[..], 1, &mut None).unwrap_err();
+        assert!(format!("{:#}", err).contains("record #1 is truncated: found 3 of 4 lines"));
+
+        let fastq = b"@r1\nACGT\n+\nIIII\nr2\nACGT\n+\nIIII\n";
+        let err = FastqIndex::from_raw_reader(&fastq[..], 1, &mut None).unwrap_err();
+        assert!(format!("{:#}", err).contains("record #2 does not start with '@'"));
+
+        let fastq = b"@r1\nACGT\nIIII\n+\n";
+        let err = FastqIndex::from_raw_reader(&fastq[..], 1, &mut None).unwrap_err();
+        assert!(format!("{:#}", err).contains("record #1 does not have a '+'"));
+
+        // parsing the records gives the record number and a hint
+        let fastq = b"@r1\nACGT\n+\nIIII\nr2\nACGT\n+\nIIII\n";
+        let records = seq_io::fastq::Reader::new(&fastq[..]).into_records();
+        let err = FastqIndex::from(records, 1, &mut None).unwrap_err();
+        assert!(err.to_string().starts_with("Could not parse FASTQ record #2"), "{}", err);
+        assert!(err.to_string().contains("its header does not start with '@'"), "{}", err);
+        assert!(!err.to_string().contains("multi-line"), "{}", err);
+    }
+
+    #[test]
+    fn test_index_writer_skip_malformed() {
+        capture_logs();
+        let fastq = b"@r1\nACGT\n+\nIIII\nr2\nACGT\n+\nIIII\n@r3\nAC\n+\nII\n";
+        let mut index_writer = IndexWriter::new(Cursor::new(vec![]), 1).unwrap();
+        index_writer.set_skip_malformed(true);
+        index_writer.append_raw_reader(&fastq[..], &mut None).unwrap();
+        let bytes = index_writer.finish(None).unwrap().into_inner();
+
+        // the malformed record is neither counted nor are its bytes
+        let expected =
+            FastqIndex::from_raw_reader(&b"@r1\nACGT\n+\nIIII\n@r3\nAC\n+\nII\n"[..], 1, &mut None);
+        assert_eq!(FastqIndex::read_from(&bytes[..]).unwrap(), expected.unwrap());
+        assert_eq!(
+            captured_logs(),
+            vec![
+                "Skipping a malformed FASTQ record after record #1, as its header does not start \
+                  with '@': r2"
+            ]
+        );
+    }
+
     #[test]
     fn test_fastq_index_range_num_bytes() {
         let entry = FastqIndexRange {
-            start_byte: 15,
-            end_byte: 20,
+            start_byte: UncompressedOffset(15),
+            end_byte: UncompressedOffset(20),
             leading_records: 0,
             trailing_records: 0,
             total_records: 0,
@@ -269,130 +2174,705 @@ mod test {
         assert_eq!(entry.num_bytes(), 5);
     }
 
+    #[test]
+    fn test_fastq_index_range_bgzip_command() {
+        let range = index(8).range(RecordNum(5), RecordNum(7)).unwrap();
+        assert_eq!(
+            range.bgzip_command(Path::new("data/test.fastq.gz")),
+            "bgzip -b 102 -s 170 data/test.fastq.gz"
+        );
+    }
+
+    #[test]
+    fn test_index_writer() {
+        let fastq: Vec<u8> = {
+            let mut bytes: Vec<u8> = vec![];
+            for _ in 0..10 {
+                fixed_record().write(&mut bytes).unwrap();
+            }
+            bytes
+        };
+        let buffered = |index: &FastqIndex| {
+            let mut bytes: Vec<u8> = vec![];
+            index.write_to(&mut bytes);
+            bytes
+        };
+
+        for nth in [0, 1, 3, 10, 11] {
+            // parsed records
+            let records = || vec![fixed_record(); 10].into_iter().map(Ok);
+            let mut index_writer = IndexWriter::new(Cursor::new(vec![]), nth).unwrap();
+            index_writer.append(records(), &mut None).unwrap();
+            let streamed = index_writer.finish(None).unwrap().into_inner();
+            let expected = FastqIndex::from(records(), nth, &mut None).unwrap();
+            assert_eq!(streamed, buffered(&expected), "nth: {}", nth);
+
+            // raw records, with a source CRC32
+            let mut index_writer = IndexWriter::new(Cursor::new(vec![]), nth).unwrap();
+            index_writer.append_raw_reader(&fastq[..], &mut None).unwrap();
+            let streamed = index_writer.finish(Some(42)).unwrap().into_inner();
+            let mut expected = FastqIndex::from_raw_reader(&fastq[..], nth, &mut None).unwrap();
+            expected.source_crc32 = Some(42);
+            assert_eq!(streamed, buffered(&expected), "nth: {}", nth);
+
+            // continuing an existing index
+            let prefix = FastqIndex::from_raw_reader(&fastq[..136], nth, &mut None).unwrap();
+            let mut index_writer =
+                IndexWriter::from_index(Cursor::new(vec![]), &prefix, 0).unwrap();
+            index_writer.append_raw_reader(&fastq[136..], &mut None).unwrap();
+            let streamed = index_writer.finish(None).unwrap().into_inner();
+            let expected = FastqIndex::from_raw_reader(&fastq[..], nth, &mut None).unwrap();
+            assert_eq!(streamed, buffered(&expected), "nth: {}", nth);
+
+            // one record at a time
+            let mut index_writer = IndexWriter::new(Cursor::new(vec![]), nth).unwrap();
+            for _ in 0..10 {
+                index_writer.add_record(34, 7).unwrap();
+            }
+            let streamed = index_writer.finish(None).unwrap().into_inner();
+            assert_eq!(streamed, buffered(&expected), "nth: {}", nth);
+        }
+
+        // no records
+        let streamed = IndexWriter::new(Cursor::new(vec![]), 3).unwrap().finish(None).unwrap();
+        assert_eq!(streamed.into_inner(), buffered(&FastqIndex::new(3)));
+    }
+
+    #[test]
+    fn test_fastq_index_record_at_byte() {
+        let at = |index: &FastqIndex, byte: u64| {
+            index.record_at_byte(UncompressedOffset(byte)).map(|record| record.0)
+        };
+
+        // every record is indexed, so the record is exact; each record is 34 bytes
+        let records = vec![fixed_record(); 9].into_iter().map(Ok);
+        let every = FastqIndex::from(records, 1, &mut None).unwrap();
+        assert_eq!(at(&every, 0), Some(1));
+        assert_eq!(at(&every, 33), Some(1));
+        assert_eq!(at(&every, 34), Some(2));
+        assert_eq!(at(&every, 200), Some(6));
+        assert_eq!(at(&every, 305), Some(9));
+        assert_eq!(at(&every, 306), None);
+
+        // every third record is indexed, so the first record in the chunk is returned
+        let every_third: FastqIndex = index(8);
+        assert_eq!(at(&every_third, 0), Some(1));
+        assert_eq!(at(&every_third, 40), Some(1));
+        assert_eq!(at(&every_third, 101), Some(1));
+        assert_eq!(at(&every_third, 102), Some(4));
+        assert_eq!(at(&every_third, 271), Some(7));
+        assert_eq!(at(&every_third, 272), None);
+
+        // no records
+        assert_eq!(at(&FastqIndex::new(3), 0), None);
+    }
+
+    #[test]
+    fn test_fastq_index_range_inconsistent() {
+        capture_logs();
+        let range = FastqIndexRange {
+            start_byte: UncompressedOffset(20),
+            end_byte: UncompressedOffset(15),
+            leading_records: 3,
+            trailing_records: 2,
+            total_records: 4,
+        };
+        assert_eq!(range.num_bytes(), 0);
+        assert_eq!(range.clone().selected_records(), 0);
+        let logs = captured_logs();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().all(|log| log.starts_with("Inconsistent FASTQ index range")));
+
+        // a consistent range does not warn
+        let range = FastqIndexRange { end_byte: UncompressedOffset(25), total_records: 5, ..range };
+        assert_eq!(range.num_bytes(), 5);
+        assert_eq!(range.selected_records(), 0);
+        assert!(captured_logs().is_empty());
+    }
+
+    #[test]
+    fn test_covering_entries() {
+        // entries with 0, 3, 6, and 8 records
+        let index: FastqIndex = index(8);
+        assert_eq!(index.covering_entries(1, 1), Some((Some(0), 1)));
+        assert_eq!(index.covering_entries(1, 3), Some((Some(0), 1)));
+        assert_eq!(index.covering_entries(3, 4), Some((Some(0), 2)));
+        assert_eq!(index.covering_entries(4, 4), Some((Some(1), 2)));
+        assert_eq!(index.covering_entries(4, 6), Some((Some(1), 2)));
+        assert_eq!(index.covering_entries(7, 8), Some((Some(2), 3)));
+        assert_eq!(index.covering_entries(1, 8), Some((Some(0), 3)));
+
+        // clamped to the records in the index
+        assert_eq!(index.covering_entries(0, 2), Some((Some(0), 1)));
+        assert_eq!(index.covering_entries(5, 20), Some((Some(1), 3)));
+
+        // no records in range
+        assert_eq!(index.covering_entries(0, 0), None);
+        assert_eq!(index.covering_entries(4, 3), None);
+        assert_eq!(index.covering_entries(9, 9), None);
+
+        // the range is read between the entries
+        let range = index.range(RecordNum(4), RecordNum(8)).unwrap();
+        let (start_entry, end_entry) = index.covering_entries(4, 8).unwrap();
+        assert_eq!(range.start_byte.0, index.entries[start_entry.unwrap()].total_bytes);
+        assert_eq!(range.end_byte.0, index.entries[end_entry].total_bytes);
+
+        // the memory-mapped index has the same entries
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        index.clone().write(&path);
+        let mmap = FastqIndex::open_mmap(&path).unwrap();
+        for (start, end) in [(1, 1), (3, 4), (4, 6), (7, 8), (0, 2), (5, 20), (0, 0), (9, 9)] {
+            assert_eq!(mmap.covering_entries(start, end), index.covering_entries(start, end));
+        }
+
+        // when the first entry has records, the records before it are read from the start of the
+        // FASTQ
+        let entries = index.entries[1..].to_vec();
+        let index = FastqIndex { entries, ..index };
+        assert_eq!(index.covering_entries(2, 3), Some((None, 0)));
+        assert_eq!(index.covering_entries(3, 5), Some((None, 1)));
+        assert_eq!(index.covering_entries(4, 5), Some((Some(0), 1)));
+        let expected = FastqIndexRange {
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(index.entries[0].total_bytes),
+            leading_records: 1,
+            trailing_records: 0,
+            total_records: 3,
+        };
+        assert_eq!(index.range(RecordNum(2), RecordNum(3)), Some(expected.clone()));
+        index.clone().write(&path);
+        let mmap = FastqIndex::open_mmap(&path).unwrap();
+        assert_eq!(mmap.range(RecordNum(2), RecordNum(3)), Some(expected));
+    }
+
     #[test]
     fn test_fastq_index_range_out_of_range() {
-        let index: FastqIndex = index();
+        let index: FastqIndex = index(8);
 
         // [0, 0] should yield zero selected records
-        assert_eq!(index.range(0, 0), None);
+        assert_eq!(index.range(RecordNum(0), RecordNum(0)), None);
 
         // [4, 3] should yield zero selected records
-        assert_eq!(index.range(4, 3), None);
+        assert_eq!(index.range(RecordNum(4), RecordNum(3)), None);
 
         // 10, 10
-        assert_eq!(index.range(9, 9), None);
+        assert_eq!(index.range(RecordNum(9), RecordNum(9)), None);
     }
 
     #[test]
     fn test_fastq_index_range_in_range() {
-        let index: FastqIndex = index();
+        let index: FastqIndex = index(8);
 
         // [0, 2] should yield two selected records
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 102,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(102),
             leading_records: 0,
             trailing_records: 1,
             total_records: 3,
         };
-        let range = index.range(0, 2).unwrap();
+        let range = index.range(RecordNum(0), RecordNum(2)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 2);
 
         // [1, 3]
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 102,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(102),
             leading_records: 0,
             trailing_records: 0,
             total_records: 3,
         };
-        let range = index.range(1, 3).unwrap();
+        let range = index.range(RecordNum(1), RecordNum(3)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 3);
 
         // [2, 2] should yield a single selected record
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 102,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(102),
             leading_records: 1,
             trailing_records: 1,
             total_records: 3,
         };
-        let range = index.range(2, 2).unwrap();
+        let range = index.range(RecordNum(2), RecordNum(2)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 1);
 
         // [3, 3] should yield a single selected record
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 102,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(102),
             leading_records: 2,
             trailing_records: 0,
             total_records: 3,
         };
-        let range = index.range(3, 3).unwrap();
+        let range = index.range(RecordNum(3), RecordNum(3)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 1);
 
         // [2, 3]
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 102,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(102),
             leading_records: 1,
             trailing_records: 0,
             total_records: 3,
         };
-        let range = index.range(2, 3).unwrap();
+        let range = index.range(RecordNum(2), RecordNum(3)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 2);
 
         // // [2, 4]
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 204,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(204),
             leading_records: 1,
             trailing_records: 2,
             total_records: 6,
         };
-        let range = index.range(2, 4).unwrap();
+        let range = index.range(RecordNum(2), RecordNum(4)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 3);
 
         // [3, 4]
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 204,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(204),
             leading_records: 2,
             trailing_records: 2,
             total_records: 6,
         };
-        let range = index.range(3, 4).unwrap();
+        let range = index.range(RecordNum(3), RecordNum(4)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 2);
 
         // [1, 8]
         let expected = FastqIndexRange {
-            start_byte: 0,
-            end_byte: 272,
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(272),
             leading_records: 0,
             trailing_records: 0,
             total_records: 8,
         };
-        let range = index.range(1, 8).unwrap();
+        let range = index.range(RecordNum(1), RecordNum(8)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 8);
 
         // [8, 9]
         let expected = FastqIndexRange {
-            start_byte: 204,
-            end_byte: 272,
+            start_byte: UncompressedOffset(204),
+            end_byte: UncompressedOffset(272),
             leading_records: 1,
             trailing_records: 0,
             total_records: 2,
         };
-        let range = index.range(8, 9).unwrap();
+        let range = index.range(RecordNum(8), RecordNum(9)).unwrap();
         assert_eq!(range, expected);
         assert_eq!(range.selected_records(), 1);
     }
+
+    #[test]
+    fn test_fastq_index_range_selected_byte_estimate() {
+        // records are 34 bytes, with the last chunk (records 7-8) shorter than nth = 3
+        let index: FastqIndex = index(8);
+        for (start, end, total_records, selected_records, num_bytes, estimate) in [
+            (8, 8, 2, 1, 68, 34),
+            (7, 8, 2, 2, 68, 68),
+            (8, 9, 2, 1, 68, 34),
+            (6, 8, 5, 3, 170, 102),
+            (2, 2, 3, 1, 102, 34),
+            (1, 8, 8, 8, 272, 272),
+        ] {
+            let range = index.range(RecordNum(start), RecordNum(end)).unwrap();
+            assert_eq!(range.total_records, total_records, "{}-{}", start, end);
+            assert_eq!(range.clone().selected_records(), selected_records, "{}-{}", start, end);
+            assert_eq!(range.num_bytes(), num_bytes, "{}-{}", start, end);
+            assert_eq!(range.selected_byte_estimate(), estimate, "{}-{}", start, end);
+        }
+
+        // an empty range
+        let range = FastqIndexRange {
+            start_byte: UncompressedOffset(0),
+            end_byte: UncompressedOffset(0),
+            leading_records: 0,
+            trailing_records: 0,
+            total_records: 0,
+        };
+        assert_eq!(range.selected_byte_estimate(), 0);
+    }
+
+    #[test]
+    fn test_exact_byte_range() {
+        let exact_byte_range = |index: &FastqIndex, start: u64, end: u64| {
+            index
+                .exact_byte_range(RecordNum(start), RecordNum(end))
+                .map(|(start, end)| (start.0, end.0))
+        };
+
+        // every record is indexed, so every range is exact (each record is 34 bytes)
+        let records: Vec<OwnedRecord> = (0..8).map(|_| fixed_record()).collect();
+        let every_record = FastqIndex::from(records.into_iter().map(Ok), 1, &mut None).unwrap();
+        assert_eq!(exact_byte_range(&every_record, 1, 1), Some((0, 34)));
+        assert_eq!(exact_byte_range(&every_record, 2, 4), Some((34, 136)));
+        assert_eq!(exact_byte_range(&every_record, 8, 8), Some((238, 272)));
+        assert_eq!(exact_byte_range(&every_record, 0, 20), Some((0, 272)));
+        assert_eq!(exact_byte_range(&every_record, 9, 10), None);
+
+        // every third record is indexed, so only ranges of whole chunks are exact
+        let index: FastqIndex = index(8);
+        assert_eq!(exact_byte_range(&index, 1, 3), Some((0, 102)));
+        assert_eq!(exact_byte_range(&index, 4, 8), Some((102, 272)));
+        assert_eq!(exact_byte_range(&index, 1, 2), None);
+        assert_eq!(exact_byte_range(&index, 2, 3), None);
+        assert_eq!(exact_byte_range(&index, 4, 7), None);
+    }
+
+    #[test]
+    fn test_range_by_base() {
+        // records spanning bases [0, 3), [3, 8), [8, 9), [9, 13), [13, 19), [19, 21), and [21, 28)
+        let records: Vec<OwnedRecord> = [3, 5, 1, 4, 6, 2, 7]
+            .iter()
+            .map(|length| OwnedRecord {
+                head: b"r".to_vec(),
+                seq: vec![b'A'; *length],
+                qual: vec![b'I'; *length],
+            })
+            .collect();
+        let index = FastqIndex::from(records.clone().into_iter().map(Ok), 2, &mut None).unwrap();
+        let range_by_base = |index: &FastqIndex, start_base: u64, end_base: u64| {
+            index
+                .range_by_base(start_base, end_base)
+                .unwrap()
+                .map(|range| (range.start_record.0, range.end_record.0, range.start_base))
+        };
+
+        // rounded to the chunks of two records
+        assert_eq!(range_by_base(&index, 0, 1), Some((1, 2, 0)));
+        assert_eq!(range_by_base(&index, 8, 9), Some((3, 4, 8)));
+        assert_eq!(range_by_base(&index, 7, 9), Some((1, 4, 0)));
+        // the end is clamped to the last base, in the final, shorter chunk
+        assert_eq!(range_by_base(&index, 12, 100), Some((3, 7, 8)));
+        assert_eq!(range_by_base(&index, 27, 28), Some((7, 7, 21)));
+        // no bases in range
+        assert_eq!(range_by_base(&index, 28, 30), None);
+        assert_eq!(range_by_base(&index, 5, 5), None);
+        assert_eq!(range_by_base(&index, 4, 3), None);
+
+        // the byte range is that of the records
+        let base_range = index.range_by_base(8, 9).unwrap().unwrap();
+        assert_eq!(Some(base_range.range), index.range(RecordNum(3), RecordNum(4)));
+
+        // exact when every record is indexed
+        let index = FastqIndex::from(records.into_iter().map(Ok), 1, &mut None).unwrap();
+        assert_eq!(range_by_base(&index, 3, 8), Some((2, 2, 3)));
+        assert_eq!(range_by_base(&index, 7, 9), Some((2, 3, 3)));
+
+        // an index without the number of bases
+        let err = without_bases(index).range_by_base(0, 1).unwrap_err();
+        assert!(err.to_string().contains("does not store the number of bases"));
+    }
+
+    #[test]
+    fn test_chunks() {
+        let chunks = |index: &FastqIndex, chunk_size: u64| {
+            index
+                .chunks(chunk_size)
+                .map(|range| (range.start_byte.0, range.end_byte.0, range.selected_records()))
+                .collect::<Vec<_>>()
+        };
+
+        // records 1-3, 4-6, and 7-8, each 34 bytes
+        let index = index(8);
+        assert_eq!(chunks(&index, 3), vec![(0, 102, 3), (102, 204, 3), (204, 272, 2)]);
+        let ranges: Vec<FastqIndexRange> = index.chunks(3).collect();
+        assert_eq!(ranges[0], index.range(RecordNum(1), RecordNum(3)).unwrap());
+        assert_eq!(ranges[2], index.range(RecordNum(7), RecordNum(8)).unwrap());
+
+        // chunks that do not align with the entries span the indexed chunks around them
+        assert_eq!(chunks(&index, 4), vec![(0, 204, 4), (102, 272, 4)]);
+        assert_eq!(chunks(&index, 8), vec![(0, 272, 8)]);
+        assert_eq!(chunks(&index, 100), vec![(0, 272, 8)]);
+        assert!(FastqIndex::new(3).chunks(3).next().is_none());
+    }
+
+    #[test]
+    fn test_mmap_index_range() {
+        let dir = TempDir::new().unwrap();
+        let records: Vec<OwnedRecord> = (0..10).map(|_| fixed_record()).collect();
+        for (nth, has_bases) in [(1, true), (3, true), (4, false), (0, true)] {
+            let mut index =
+                FastqIndex::from(records.clone().into_iter().map(Ok), nth, &mut None).unwrap();
+            if !has_bases {
+                index = without_bases(index);
+            }
+            let path = dir.path().join("test.fastq.gz.fqi");
+            index.clone().write(&path);
+            let mmap_index = FastqIndex::open_mmap(&path).unwrap();
+            assert_eq!(mmap_index.total_records(), 10);
+            assert_eq!(mmap_index.num_entries(), index.entries.len());
+            for (i, entry) in index.entries.iter().enumerate() {
+                assert_eq!(&mmap_index.entry(i), entry);
+            }
+            for (start, end) in [
+                (1, 1),
+                (1, 10),
+                (2, 3),
+                (3, 4),
+                (4, 4),
+                (5, 9),
+                (10, 10),
+                (0, 2),
+                (8, 20),
+                (11, 12),
+            ] {
+                assert_eq!(
+                    mmap_index.range(RecordNum(start), RecordNum(end)),
+                    index.range(RecordNum(start), RecordNum(end)),
+                    "nth: {} start: {} end: {}",
+                    nth,
+                    start,
+                    end
+                );
+            }
+        }
+
+        // a gzip-compressed index cannot be mapped
+        let path = dir.path().join("test.fastq.gz.fqi.gz");
+        index(8).write(&path);
+        let err = FastqIndex::open_mmap(&path).err().unwrap();
+        assert!(format!("{:#}", err).contains("gzip-compressed, so cannot be memory mapped"));
+
+        // the last entry must match the header
+        let path = dir.path().join("test.fastq.gz.fqi");
+        FastqIndex { total_records: 9, ..index(8) }.write(&path);
+        let err = FastqIndex::open_mmap(&path).err().unwrap();
+        assert!(format!("{:#}", err)
+            .ends_with("FASTQ index header has 9 records but the last entry has 8 records"));
+    }
+
+    #[test]
+    fn test_nth_bytes() {
+        // records of 9, 9, 27, 9, 9, 9, and 9 bytes, ending at bytes 9, 18, 45, 54, 63, 72, and 81
+        let records: Vec<OwnedRecord> = [1, 1, 10, 1, 1, 1, 1]
+            .iter()
+            .map(|&length| OwnedRecord {
+                head: b"r".to_vec(),
+                seq: vec![b'A'; length],
+                qual: vec![b'I'; length],
+            })
+            .collect();
+        let fastq = to_fastq(&records);
+        let entry = |total_records: u64, total_bytes: u64| FastqIndexEntry {
+            total_records,
+            total_bytes,
+            total_bases: Some(total_records + 9 * u64::from(total_records > 2)),
+        };
+
+        // an entry for the first record at or after every 20 bytes: record 4 starts at byte 45,
+        // past both 20 and 40, and record 6 at byte 63
+        let index = FastqIndex::from_raw_reader_by_bytes(&fastq[..], 20, &mut None).unwrap();
+        assert_eq!(index.nth, 0);
+        assert_eq!(index.nth_bytes, Some(20));
+        assert_eq!(index.spacing(), CheckpointSpacing::Bytes(20));
+        assert_eq!(index.entries, vec![entry(0, 0), entry(3, 45), entry(5, 63), entry(7, 81)]);
+
+        // the spacing is kept when written and read back
+        let mut bytes = vec![];
+        index.clone().write_to(&mut bytes);
+        assert_eq!(FastqIndex::read_from(&bytes[..]).unwrap(), index);
+
+        // the index writer places the same entries, whether reading the FASTQ or given each record
+        let mut index_writer =
+            IndexWriter::with_spacing(Cursor::new(vec![]), CheckpointSpacing::Bytes(20), 0)
+                .unwrap();
+        index_writer.append_raw_reader(&fastq[..], &mut None).unwrap();
+        assert_eq!(index_writer.nth(), 0);
+        assert_eq!(index_writer.finish(None).unwrap().into_inner(), bytes);
+        let mut index_writer =
+            IndexWriter::with_spacing(Cursor::new(vec![]), CheckpointSpacing::Bytes(20), 0)
+                .unwrap();
+        for record in &records {
+            let num_bytes = FastqIndex::record_to_num_bytes(record);
+            index_writer.add_record(num_bytes, record.seq.len() as u64).unwrap();
+        }
+        assert_eq!(index_writer.finish(None).unwrap().into_inner(), bytes);
+
+        // appending continues from the last checkpoint: record 6 starts at byte 63, past 60
+        let mut appended =
+            FastqIndex::from_raw_reader_by_bytes(&fastq[..45], 20, &mut None).unwrap();
+        appended.append_raw_reader(&fastq[45..], &mut None).unwrap();
+        assert_eq!(appended, index);
+
+        // a range is found from the entries around it, whatever the number of records between them
+        let range = |start: u64, end: u64| {
+            let range = index.range(RecordNum(start), RecordNum(end)).unwrap();
+            let (start_byte, end_byte) = (range.start_byte.0, range.end_byte.0);
+            (start_byte, end_byte, range.leading_records, range.trailing_records)
+        };
+        assert_eq!(range(1, 1), (0, 45, 0, 2));
+        assert_eq!(range(3, 4), (0, 63, 2, 1));
+        assert_eq!(range(4, 5), (45, 63, 0, 0));
+        assert_eq!(range(5, 7), (45, 81, 1, 0));
+        assert_eq!(range(6, 7), (63, 81, 0, 0));
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        index.clone().write(&path);
+        let mmap_index = FastqIndex::open_mmap(&path).unwrap();
+        for (start, end) in [(1, 1), (3, 4), (4, 5), (5, 7), (6, 7), (1, 7)] {
+            let (start, end) = (RecordNum(start), RecordNum(end));
+            assert_eq!(mmap_index.range(start, end), index.range(start, end));
+        }
+
+        // the entries must be a non-zero number of bytes apart
+        assert!(FastqIndex::with_nth_bytes(0).is_err());
+        assert!(FastqIndex::from_raw_reader_by_bytes(&fastq[..], 0, &mut None).is_err());
+        let index_writer =
+            IndexWriter::with_spacing(Cursor::new(vec![]), CheckpointSpacing::Bytes(0), 0);
+        assert!(index_writer.is_err());
+    }
+
+    #[test]
+    fn test_index_writer_on_checkpoint() {
+        let fastq = to_fastq(&vec![fixed_record(); 10]);
+        for (nth, expected) in [(1, 10), (3, 4), (10, 1), (11, 1)] {
+            let checkpoints = Rc::new(RefCell::new(vec![]));
+            let mut index_writer = IndexWriter::new(Cursor::new(vec![]), nth).unwrap();
+            let seen = Rc::clone(&checkpoints);
+            index_writer
+                .on_checkpoint(Box::new(move |entry| seen.borrow_mut().push(entry.clone())));
+            // every function is called
+            let counted = Rc::new(RefCell::new(0));
+            let count = Rc::clone(&counted);
+            index_writer.on_checkpoint(Box::new(move |_| *count.borrow_mut() += 1));
+            index_writer.append_raw_reader(&fastq[..], &mut None).unwrap();
+            let index =
+                FastqIndex::read_from(index_writer.finish(None).unwrap().get_ref().as_slice());
+
+            // every entry but the final entry is a checkpoint
+            let mut entries = index.unwrap().entries;
+            entries.pop();
+            assert_eq!(checkpoints.borrow().len(), expected, "nth: {}", nth);
+            assert_eq!(*counted.borrow(), expected, "nth: {}", nth);
+            assert_eq!(*checkpoints.borrow(), entries, "nth: {}", nth);
+        }
+    }
+
+    #[test]
+    fn test_write_aligned() {
+        let index = index(8);
+        let mut unaligned: Vec<u8> = vec![];
+        index.write_to(&mut unaligned);
+        let mut bytes: Vec<u8> = vec![];
+        index.write_aligned_to(&mut bytes, 64).unwrap();
+
+        // the offset of the entries follows the header, then zero padding up to the entries
+        assert_eq!(LittleEndian::read_u32(&bytes[4..8]), ALIGNED_FORMAT_VERSION);
+        assert_eq!(LittleEndian::read_u64(&bytes[32..40]), 64);
+        assert!(bytes[40..64].iter().all(|byte| *byte == 0));
+        assert_eq!(bytes[64..], unaligned[32..]);
+
+        // the same entries are read, streamed, from a file, and memory mapped
+        assert_eq!(FastqIndex::read_from(&bytes[..]).unwrap(), index);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(FastqIndex::read(&path).unwrap(), index);
+        let mmap_index = FastqIndex::open_mmap(&path).unwrap();
+        assert_eq!(mmap_index.num_entries(), index.entries.len());
+        for (i, entry) in index.entries.iter().enumerate() {
+            assert_eq!(&mmap_index.entry(i), entry);
+        }
+
+        // the streaming writer pads the header in the same way
+        let mut index_writer = IndexWriter::with_align(Cursor::new(vec![]), 3, 64).unwrap();
+        index_writer.append(vec![fixed_record(); 8].into_iter().map(Ok), &mut None).unwrap();
+        assert_eq!(index_writer.finish(None).unwrap().into_inner(), bytes);
+
+        // no padding is needed when the header is already aligned
+        let mut bytes: Vec<u8> = vec![];
+        index.write_aligned_to(&mut bytes, 8).unwrap();
+        assert_eq!(bytes.len(), unaligned.len() + 8);
+        assert_eq!(FastqIndex::read_from(&bytes[..]).unwrap(), index);
+
+        // the padding must not be truncated
+        let mut bytes: Vec<u8> = vec![];
+        index.write_aligned_to(&mut bytes, 64).unwrap();
+        let err = FastqIndex::read_from(&bytes[..50]).unwrap_err();
+        assert!(format!("{:#}", err).contains("missing its header padding: found 10 of 24 bytes"));
+    }
+
+    #[test]
+    fn test_infer_nth() {
+        // the final entry is at most nth records after the last checkpoint
+        for (nth, expected) in [(0, None), (1, Some(1)), (3, Some(3)), (4, Some(4)), (5, Some(5))] {
+            let index =
+                FastqIndex::from(vec![fixed_record(); 8].into_iter().map(Ok), nth, &mut None);
+            assert_eq!(index.unwrap().infer_nth(), expected, "nth: {}", nth);
+        }
+        // only the first and final entries
+        let index =
+            FastqIndex::from(vec![fixed_record(); 8].into_iter().map(Ok), 8, &mut None).unwrap();
+        assert_eq!(index.infer_nth(), None);
+        assert_eq!(FastqIndex::new(3).infer_nth(), None);
+
+        // unevenly spaced entries
+        let entry = |total_records| FastqIndexEntry {
+            total_records,
+            total_bytes: total_records,
+            total_bases: None,
+        };
+        let entries = vec![entry(0), entry(3), entry(5), entry(8)];
+        let index = FastqIndex {
+            total_records: 8,
+            nth: 3,
+            nth_bytes: None,
+            source_crc32: None,
+            estimated_bytes: false,
+            entries,
+        };
+        assert_eq!(index.infer_nth(), None);
+    }
+
+    #[test]
+    fn test_read_inconsistent_nth() {
+        capture_logs();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        let expected = FastqIndex::from(vec![fixed_record(); 8].into_iter().map(Ok), 3, &mut None);
+        let expected = expected.unwrap();
+
+        // a consistent header is read as is
+        expected.clone().write(&path);
+        captured_logs();
+        assert_eq!(FastqIndex::read(&path).unwrap(), expected);
+        assert!(captured_logs().is_empty());
+
+        // the nth in the header is replaced with the spacing of the entries
+        FastqIndex { nth: 5, ..expected.clone() }.write(&path);
+        captured_logs();
+        let index = FastqIndex::read(&path).unwrap();
+        assert_eq!(index, expected);
+        assert_eq!(
+            captured_logs(),
+            vec!["FASTQ index header has nth = 5 but its entries are every 3 records; using 3"]
+        );
+        let range = index.range(RecordNum(5), RecordNum(7)).unwrap();
+        assert_eq!(range.leading_records, 1);
+
+        // as is the memory-mapped index's
+        let mmap_index = FastqIndex::open_mmap(&path).unwrap();
+        assert_eq!(mmap_index.nth(), 3);
+        assert_eq!(captured_logs().len(), 1);
+    }
+
+    #[test]
+    fn test_mean_record_bytes() {
+        // 34 bytes per record
+        let index =
+            FastqIndex::from(vec![fixed_record(); 8].into_iter().map(Ok), 3, &mut None).unwrap();
+        assert_eq!(index.mean_record_bytes(), Some(34.0));
+        assert_eq!(FastqIndex::new(3).mean_record_bytes(), None);
+    }
 }
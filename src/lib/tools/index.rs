@@ -1,37 +1,223 @@
-use std::{io, io::BufReader, io::BufWriter, path::PathBuf};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, Stdout, Write},
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use clap::Parser;
 use env_logger::Env;
+use libdeflater::Crc;
+use log::{debug, info, warn};
+use seq_io::BaseRecord;
 
-use crate::utils::{built_info, BUFFERSIZE};
+use crate::utils::{
+    built_info, crc32_combine, read_fully, tmp_path, ParallelCrc32, TrailingBlankLines, BUFFERSIZE,
+};
 
-use super::fastq_index::FastqIndex;
+use super::{
+    fastq_index::{check_fastq_start, CheckpointSpacing, FastqIndex, FastqIndexEntry, IndexWriter},
+    info::IndexInfo,
+};
 
 /// Index a FASTQ
 #[derive(Parser, Debug)]
 #[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
 pub struct Opts {
-    /// The output index file.
+    /// The output index file, or `-` to write the index to stdout, in which case the FASTQ is not
+    /// written to stdout (as with --no-stdout).
     #[clap(short = 'o', long, display_order = 2)]
     pub output: PathBuf,
 
-    /// Index every Nth entry
-    #[clap(short = 'n', long, default_value = "100000", display_order = 3)]
-    pub nth: u64,
+    /// Index every Nth entry, or 0 to store only the total number of records and bytes.  Extracting
+    /// with such an index reads from the start of the FASTQ, so is slow.  Defaults to the
+    /// `FQME_NTH` environment variable if set, otherwise 100000; --nth takes precedence over it.
+    #[clap(short = 'n', long, display_order = 3)]
+    pub nth: Option<u64>,
 
     /// True to omit emitting the FASTQ to stdout
     #[clap(long, display_order = 4)]
     pub no_stdout: bool,
+
+    /// True to index by scanning the raw FASTQ bytes rather than parsing each record.  The byte
+    /// offsets are exact even when the '+' line contains a comment.
+    #[clap(long, display_order = 5)]
+    pub raw: bool,
+
+    /// True to store the CRC32 of the uncompressed FASTQ (as written to stdout) in the index, so
+    /// that extract can detect when the bgzip'ed FASTQ no longer matches the index.  The CRC32
+    /// covers the FASTQ content, rather than the file size and modification time, as these are not
+    /// known when indexing from stdin and change when the file is copied.
+    #[clap(long, display_order = 6)]
+    pub checksum: bool,
+
+    /// True to append to the existing output index, for a FASTQ that has grown since it was
+    /// indexed.  The input must be the full FASTQ: the bytes already indexed are written to stdout
+    /// unchanged, and must match the CRC32 stored in the index (see --checksum), while only the new
+    /// records are indexed.  The existing index's nth is used.
+    #[clap(long, display_order = 8)]
+    pub append: bool,
+
+    /// Write a human-readable summary of the index to this file once indexing completes, in the
+    /// same format as `fqme info`.
+    #[clap(long, display_order = 9)]
+    pub report: Option<PathBuf>,
+
+    /// True to overwrite the output index file if it already exists.
+    #[clap(long, display_order = 7)]
+    pub force: bool,
+
+    /// Pad the index header with zeros so that the entries start at a multiple of this many bytes,
+    /// e.g. to memory map the entries at an aligned offset.  Must be a power of two, or 0 to not
+    /// pad the header.  A padded index cannot be read by versions of fqme before format version 3.
+    #[clap(long, default_value = "0", display_order = 10)]
+    pub align: u64,
+
+    /// Log each checkpoint entry (every Nth record) as it is written, at debug level (e.g. with
+    /// `RUST_LOG=debug`), to monitor indexing.
+    #[clap(long, display_order = 11)]
+    pub log_checkpoints: bool,
+
+    /// Log the number of records indexed so far every this many records, to show progress when
+    /// indexing a large FASTQ, however the checkpoint entries are spaced (see --nth).  The log is
+    /// written to stderr, so the FASTQ written to stdout is unchanged.  By default, progress is not
+    /// logged.
+    #[clap(long, display_order = 11)]
+    pub count_interval: Option<u64>,
+
+    /// Log and skip the records whose header does not start with '@' (e.g. in a corrupt FASTQ),
+    /// rather than failing.  The skipped records are not written to stdout, so the FASTQ written
+    /// to stdout is the one that is indexed.  Implies --raw, and cannot be used with --no-stdout,
+    /// --append, --checksum, or --output -.
+    #[clap(long, display_order = 12)]
+    pub skip_malformed: bool,
+
+    /// Index the first record at or after every this many uncompressed bytes, rather than every
+    /// Nth record (see --nth), so that the entries are evenly spaced in the FASTQ when the read
+    /// lengths vary (e.g. long reads).  The index stores an nth of 0.  Cannot be used with --nth
+    /// or --append, and takes precedence over the `FQME_NTH` environment variable and the config
+    /// file.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "append",
+        display_order = 13
+    )]
+    pub nth_bytes: Option<u64>,
+}
+
+/// The environment variable that sets the default for --nth
+pub const NTH_ENV: &str = "FQME_NTH";
+
+/// The default for --nth, when neither it nor `FQME_NTH` is given
+pub const DEFAULT_NTH: u64 = 100_000;
+
+impl Opts {
+    /// How the checkpoint entries are spaced, per --nth or --nth-bytes, otherwise per `FQME_NTH`
+    /// or the default nth
+    pub fn spacing(&self) -> Result<CheckpointSpacing> {
+        if let Some(nth_bytes) = self.nth_bytes {
+            return Ok(CheckpointSpacing::Bytes(nth_bytes));
+        }
+        let nth = match (self.nth, std::env::var(NTH_ENV)) {
+            (Some(nth), _) => nth,
+            (None, Ok(value)) => {
+                value.parse().with_context(|| format!("Invalid {}: {}", NTH_ENV, value))?
+            }
+            (None, Err(_)) => DEFAULT_NTH,
+        };
+        Ok(CheckpointSpacing::Records(nth))
+    }
+}
+
+/// Reads the bytes that were indexed by the given index from the start of the input, optionally
+/// writing them to the given writer, and returns their CRC32.  Errors if there are fewer bytes than
+/// were indexed, or if the index stores a CRC32 that does not match.
+pub fn read_indexed_prefix<R: Read>(
+    reader: &mut R,
+    index: &FastqIndex,
+    fastq_writer: &mut Option<BufWriter<Stdout>>,
+) -> Result<u32> {
+    let total_bytes = index.entries.last().map_or(0, |entry| entry.total_bytes);
+    let mut buffer = vec![0u8; BUFFERSIZE];
+    let mut crc = Crc::new();
+    let mut num_bytes: u64 = 0;
+    while num_bytes < total_bytes {
+        let length = std::cmp::min(total_bytes - num_bytes, BUFFERSIZE as u64) as usize;
+        let num_read = read_fully(reader, &mut buffer[..length])?;
+        num_bytes += num_read as u64;
+        ensure!(
+            num_read == length,
+            "The input has {} bytes but {} bytes were previously indexed; was the FASTQ rewritten \
+             rather than appended to?",
+            num_bytes,
+            total_bytes
+        );
+        crc.update(&buffer[..length]);
+        if let Some(ref mut writer) = fastq_writer {
+            writer.write_all(&buffer[..length])?;
+        }
+    }
+
+    match index.source_crc32 {
+        Some(expected) => ensure!(
+            crc.sum() == expected,
+            "The first {} bytes of the input do not match the index (CRC32 {:08x}, but the index \
+             has CRC32 {:08x}); was the FASTQ rewritten rather than appended to?",
+            total_bytes,
+            crc.sum(),
+            expected
+        ),
+        None => warn!(
+            "The index has no CRC32 (see --checksum), so cannot verify that the FASTQ was only \
+             appended to"
+        ),
+    }
+    Ok(crc.sum())
+}
+
+/// Checks that the output index file may be written: it must not already exist unless `force`
+/// is true.
+pub fn check_output(output: &Path, force: bool) -> Result<()> {
+    ensure!(
+        force || !output.exists(),
+        "Output FASTQ index already exists, use --force to overwrite: {}",
+        output.display()
+    );
+    Ok(())
 }
 
 // Run index
 #[allow(clippy::too_many_lines)]
 pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
-    let reader = {
-        let reader = BufReader::with_capacity(BUFFERSIZE, io::stdin());
-        seq_io::fastq::Reader::new(reader).into_records()
-    };
+    ensure!(
+        opts.align == 0 || opts.align.is_power_of_two(),
+        "--align must be a power of two, or 0 to not pad the header: {}",
+        opts.align
+    );
+    ensure!(opts.count_interval != Some(0), "--count-interval must be greater than zero");
+    ensure!(opts.nth_bytes != Some(0), "--nth-bytes must be greater than zero");
+    ensure!(
+        opts.nth.is_none() || opts.nth_bytes.is_none(),
+        "--nth-bytes cannot be used with --nth"
+    );
+    ensure!(
+        !opts.skip_malformed
+            || !(opts.no_stdout || opts.append || opts.checksum || opts.output == Path::new("-")),
+        "--skip-malformed requires the FASTQ to be written to stdout, so cannot be used with \
+         --no-stdout, --append, --checksum, or --output -"
+    );
+    if opts.output == Path::new("-") {
+        return index_to_stdout(opts);
+    }
+
+    // Check the output before consuming the input
+    if !opts.append {
+        check_output(opts.output.as_path(), opts.force)?;
+    }
+
+    // Ignore blank lines at the end of the input
+    let mut reader = TrailingBlankLines::new(BufReader::with_capacity(BUFFERSIZE, io::stdin()));
 
     let mut fastq_writer = {
         if opts.no_stdout {
@@ -41,11 +227,193 @@ pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
         }
     };
 
-    FastqIndex::from(reader, opts.nth, &mut fastq_writer).write(opts.output.as_path());
+    // When appending, skip over the bytes that were previously indexed
+    let (existing, prefix_crc32, prefix_bytes) = if opts.append {
+        let index = FastqIndex::read(opts.output.as_path())?;
+        let prefix_crc32 = read_indexed_prefix(&mut reader, &index, &mut fastq_writer)?;
+        let prefix_bytes = index.entries.last().map_or(0, |entry| entry.total_bytes);
+        (Some(index), prefix_crc32, prefix_bytes)
+    } else {
+        (None, 0, 0)
+    };
+    let checksum =
+        opts.checksum || existing.as_ref().and_then(|index| index.source_crc32).is_some();
+
+    // Write the index to a temporary file as the FASTQ is indexed, so that the entries are not
+    // held in memory, then rename it over the output
+    let tmp_path = tmp_path(&opts.output);
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("Could not create {}", tmp_path.display()))?;
+    let writer = BufWriter::with_capacity(BUFFERSIZE, file);
+    let mut index_writer = match &existing {
+        Some(index) => IndexWriter::from_index(writer, index, opts.align)?,
+        None => IndexWriter::with_spacing(writer, opts.spacing()?, opts.align)?,
+    };
+    IndexProgress::from(opts).apply(&mut index_writer);
+    index_writer.set_skip_malformed(opts.skip_malformed);
+
+    // Malformed records are skipped when counting lines, rather than parsing records
+    let raw = opts.raw || opts.skip_malformed;
+    let result = write_index(&mut index_writer, reader, checksum, &mut fastq_writer, raw);
+    let source_crc32 = match result {
+        Ok(source_crc32) => source_crc32,
+        Err(err) => {
+            drop(index_writer);
+            let _ignored = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+    let source_crc32 = if checksum {
+        let total_bytes = index_writer.totals().total_bytes;
+        Some(crc32_combine(prefix_crc32, source_crc32, total_bytes - prefix_bytes))
+    } else {
+        None
+    };
+    let info = report_info(&index_writer);
+    index_writer.finish(source_crc32)?;
+    fs::rename(&tmp_path, &opts.output).with_context(|| {
+        format!("Could not rename {} to {}", tmp_path.display(), opts.output.display())
+    })?;
+
+    if let Some(report) = &opts.report {
+        write_report(report, &info)?;
+    }
 
     Ok(())
 }
 
+/// Indexes the FASTQ from stdin, writing the index to stdout rather than to a file, for
+/// `--output -`.  The FASTQ is not written to stdout, as the index is.
+fn index_to_stdout(opts: &Opts) -> Result<()> {
+    ensure!(!opts.append, "--append cannot be used when writing the index to stdout");
+    if !opts.no_stdout {
+        info!("Writing the index to stdout, so the FASTQ is not written (implies --no-stdout)");
+    }
+    let reader = TrailingBlankLines::new(BufReader::with_capacity(BUFFERSIZE, io::stdin()));
+    let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
+    let (spacing, checksum, raw, align) = (opts.spacing()?, opts.checksum, opts.raw, opts.align);
+    let progress = IndexProgress::from(opts);
+    let info = write_index_to(&mut writer, reader, spacing, checksum, raw, align, progress)?;
+    writer.flush()?;
+    if let Some(report) = &opts.report {
+        write_report(report, &info)?;
+    }
+    Ok(())
+}
+
+/// Indexes the FASTQ from the given reader, writing the serialized index to the given writer, and
+/// returns its summary.  As the header is only known once the FASTQ is indexed, the index is built
+/// in memory, and is byte-identical to one written to a file.  The entries are spaced as given
+/// (see `CheckpointSpacing`), and progress is logged as given.
+pub fn write_index_to<W: Write, R: Read>(
+    writer: &mut W,
+    reader: R,
+    spacing: CheckpointSpacing,
+    checksum: bool,
+    raw: bool,
+    align: u64,
+    progress: IndexProgress,
+) -> Result<IndexInfo> {
+    let mut index_writer = IndexWriter::with_spacing(Cursor::new(vec![]), spacing, align)?;
+    progress.apply(&mut index_writer);
+    let source_crc32 = write_index(&mut index_writer, reader, checksum, &mut None, raw)?;
+    let info = report_info(&index_writer);
+    let bytes = index_writer.finish(if checksum { Some(source_crc32) } else { None })?;
+    writer.write_all(bytes.get_ref())?;
+    Ok(info)
+}
+
+/// How progress is logged as the FASTQ is indexed, for --log-checkpoints and --count-interval
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndexProgress {
+    /// Log each checkpoint entry as it is written (at the debug level)
+    pub log_checkpoints: bool,
+    /// Log the number of records indexed every this many records
+    pub count_interval: Option<u64>,
+}
+
+impl IndexProgress {
+    /// Sets the index writer to log progress as it indexes the FASTQ
+    fn apply<W: Write + Seek>(self, index_writer: &mut IndexWriter<W>) {
+        if self.log_checkpoints {
+            index_writer.on_checkpoint(Box::new(log_checkpoint));
+        }
+        index_writer.set_count_interval(self.count_interval);
+    }
+}
+
+impl From<&Opts> for IndexProgress {
+    fn from(opts: &Opts) -> Self {
+        IndexProgress { log_checkpoints: opts.log_checkpoints, count_interval: opts.count_interval }
+    }
+}
+
+/// Logs a checkpoint entry as it is written, for --log-checkpoints
+fn log_checkpoint(entry: &FastqIndexEntry) {
+    debug!("checkpoint: records={} bytes={}", entry.total_records, entry.total_bytes);
+}
+
+/// Summarizes the index being written
+fn report_info<W: Write + Seek>(index_writer: &IndexWriter<W>) -> IndexInfo {
+    let totals = index_writer.totals();
+    IndexInfo::from_totals(
+        index_writer.spacing(),
+        totals.total_records,
+        index_writer.num_entries(),
+        totals.total_bytes,
+    )
+}
+
+/// Writes the summary of the index to the given report file
+pub fn write_report(path: &Path, info: &IndexInfo) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    info.write_table(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Indexes the FASTQ from the given reader, returning the CRC32 of the FASTQ bytes that are
+/// indexed, which are those written to stdout.  The CRC32 is only computed if `checksum` is true,
+/// and is otherwise zero.  The CRC32 is computed on a separate thread (see `ParallelCrc32`), so
+/// indexing is not slowed by checksumming.  The uncompressed bytes are checksummed, rather than
+/// the bgzip'ed bytes, as the FASTQ is compressed after it is indexed, and as `extract` checks the
+/// CRC32 by combining the CRC32s of the uncompressed blocks stored in the BGZF footers.
+fn write_index<W: Write + Seek, R: Read>(
+    index_writer: &mut IndexWriter<W>,
+    reader: R,
+    checksum: bool,
+    fastq_writer: &mut Option<BufWriter<Stdout>>,
+    raw: bool,
+) -> Result<u32> {
+    let mut reader = BufReader::with_capacity(BUFFERSIZE, reader);
+    check_fastq_start(&mut reader)?;
+    match (raw, checksum) {
+        (true, true) => {
+            let mut reader = BufReader::with_capacity(BUFFERSIZE, ParallelCrc32::new(reader));
+            index_writer.append_raw_reader(&mut reader, fastq_writer)?;
+            Ok(reader.into_inner().sum())
+        }
+        (true, false) => {
+            index_writer.append_raw_reader(&mut reader, fastq_writer)?;
+            Ok(0)
+        }
+        (false, _) => {
+            // A failure to checksum a record is returned as an error in its place
+            let mut crc32 = checksum.then(|| ParallelCrc32::new(io::sink()));
+            let records = seq_io::fastq::Reader::new(reader).into_records().map(|result| {
+                if let (Some(crc32), Ok(rec)) = (&mut crc32, &result) {
+                    rec.write(crc32)?;
+                }
+                result
+            });
+            index_writer.append(records, fastq_writer)?;
+            Ok(crc32.map_or(0, ParallelCrc32::sum))
+        }
+    }
+}
+
 /// Parse args and set up logging / tracing
 pub fn setup() -> Opts {
     if std::env::var("RUST_LOG").is_err() {
@@ -55,3 +423,329 @@ pub fn setup() -> Opts {
 
     Opts::parse()
 }
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use libdeflater::Crc;
+    use seq_io::BaseRecord;
+    use tempfile::TempDir;
+
+    use std::{fs::File, io::Cursor};
+
+    use crate::{
+        testutil::{
+            capture_logs, captured_logs, fixed_record, index, to_fastq, write_synthetic_fastq,
+        },
+        tools::{
+            fastq_index::{CheckpointSpacing, FastqIndex, FastqIndexEntry, IndexWriter},
+            info::IndexInfo,
+        },
+        types::RecordNum,
+    };
+
+    use super::{
+        check_output, read_indexed_prefix, report_info, run, write_index, write_index_to,
+        write_report, IndexProgress, Opts,
+    };
+
+    #[test]
+    fn test_run_nth_conflict() {
+        // --nth-bytes cannot be used with --nth, which is checked before the input is read
+        let args = ["index", "--output", "test.fqi", "--nth", "3", "--nth-bytes", "64"];
+        let err = run(&Opts::parse_from(args)).unwrap_err();
+        assert_eq!(err.to_string(), "--nth-bytes cannot be used with --nth");
+    }
+
+    #[test]
+    fn test_nth_bytes_zero() {
+        // --nth-bytes must be greater than zero, as the entries are placed every that many bytes
+        let args = ["index", "--output", "test.fqi", "--nth-bytes", "0"];
+        assert!(Opts::try_parse_from(args).is_err());
+        let opts = Opts::parse_from(["index", "--output", "test.fqi", "--nth-bytes", "1"]);
+        assert_eq!(opts.nth_bytes, Some(1));
+    }
+
+    #[test]
+    fn test_check_output() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("test.fastq.gz.fqi");
+
+        // a fresh path
+        check_output(&output, false).unwrap();
+        index(2).write(&output);
+        assert_eq!(FastqIndex::read(&output).unwrap().total_records, 2);
+
+        // an existing path without --force
+        let result = check_output(&output, false);
+        assert!(result.unwrap_err().to_string().contains("use --force to overwrite"));
+
+        // an existing path with --force
+        check_output(&output, true).unwrap();
+        index(5).write(&output);
+        assert_eq!(FastqIndex::read(&output).unwrap().total_records, 5);
+    }
+
+    #[test]
+    fn test_read_indexed_prefix() {
+        let record = fixed_record();
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            record.write(&mut fastq).unwrap();
+        }
+        let mut prefix = FastqIndex::from_raw_reader(&fastq[..136], 3, &mut None).unwrap();
+        let mut crc = libdeflater::Crc::new();
+        crc.update(&fastq[..136]);
+        prefix.source_crc32 = Some(crc.sum());
+
+        // index four records, append four more, and compare to indexing all eight
+        let mut reader = &fastq[..];
+        assert_eq!(read_indexed_prefix(&mut reader, &prefix, &mut None).unwrap(), crc.sum());
+        let mut index = prefix.clone();
+        index.append_raw_reader(reader, &mut None).unwrap();
+        index.source_crc32 = None;
+        assert_eq!(index, FastqIndex::from_raw_reader(&fastq[..], 3, &mut None).unwrap());
+
+        // the FASTQ was rewritten
+        let mut rewritten = fastq.clone();
+        rewritten[1] = b'S';
+        let err = read_indexed_prefix(&mut &rewritten[..], &prefix, &mut None).unwrap_err();
+        assert!(err.to_string().contains("rewritten rather than appended"));
+
+        // the FASTQ was truncated
+        let err = read_indexed_prefix(&mut &fastq[..100], &prefix, &mut None).unwrap_err();
+        assert!(err.to_string().contains("The input has 100 bytes but 136 bytes"));
+    }
+
+    #[test]
+    fn test_write_report() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("test.fastq.gz.fqi");
+        let report = dir.path().join("test.fastq.gz.fqi.txt");
+        let record = fixed_record();
+
+        let file = File::create(&output).unwrap();
+        let mut index_writer = IndexWriter::new(file, 3).unwrap();
+        index_writer.append(vec![record; 8].into_iter().map(Ok), &mut None).unwrap();
+        let info = report_info(&index_writer);
+        index_writer.finish(None).unwrap();
+        write_report(&report, &info).unwrap();
+
+        // the report matches the index as read back
+        let index = FastqIndex::read(&output).unwrap();
+        let mut expected: Vec<u8> = vec![];
+        IndexInfo::new(&index).write_table(&mut expected).unwrap();
+        let report = std::fs::read_to_string(&report).unwrap();
+        assert_eq!(report, String::from_utf8(expected).unwrap());
+        assert!(report.contains("total_records      8\n"));
+        assert!(report.contains("num_entries        4\n"));
+    }
+
+    #[test]
+    fn test_write_index_nth_zero() {
+        // five records of 34 bytes each
+        let fastq = b"@some-read-name\nGATTACA\n+\nIIIIIII\n".repeat(5);
+
+        // with --nth 0, only the first and final entries are stored, whether or not parsing records
+        for raw in [false, true] {
+            let mut index_writer = IndexWriter::new(Cursor::new(vec![]), 0).unwrap();
+            write_index(&mut index_writer, &fastq[..], false, &mut None, raw).unwrap();
+            let bytes = index_writer.finish(None).unwrap().into_inner();
+            let index = FastqIndex::read_from(&bytes[..]).unwrap();
+            assert_eq!(index.nth, 0, "raw: {}", raw);
+            assert_eq!(index.total_records, 5, "raw: {}", raw);
+            assert_eq!(
+                index.entries,
+                vec![
+                    FastqIndexEntry { total_records: 0, total_bytes: 0, total_bases: Some(0) },
+                    FastqIndexEntry { total_records: 5, total_bytes: 170, total_bases: Some(35) }
+                ],
+                "raw: {}",
+                raw
+            );
+
+            // ranges are read from the start of the FASTQ
+            let range = index.range(RecordNum(3), RecordNum(4)).unwrap();
+            assert_eq!(range.start_byte.0, 0, "raw: {}", raw);
+            assert_eq!(range.leading_records, 2, "raw: {}", raw);
+        }
+    }
+
+    #[test]
+    fn test_write_index_leading_bom() {
+        let fastq = b"@some-read-name\nGATTACA\n+\nIIIIIII\n".repeat(2);
+        for raw in [false, true] {
+            for (prefix, message) in [
+                (&b"\xef\xbb\xbf"[..], "The FASTQ starts with a UTF-8 byte order mark (BOM)"),
+                (&b"\n"[..], "The FASTQ starts with whitespace (e.g. a blank line)"),
+            ] {
+                let input = [prefix, &fastq[..]].concat();
+                let mut index_writer = IndexWriter::new(Cursor::new(vec![]), 1).unwrap();
+                let result = write_index(&mut index_writer, &input[..], false, &mut None, raw);
+                let err = result.unwrap_err().to_string();
+                assert!(err.starts_with(message), "raw: {} {}", raw, err);
+            }
+        }
+        let err = FastqIndex::from_raw_reader(
+            &[&b"\xef\xbb\xbf"[..], &fastq[..]].concat()[..],
+            1,
+            &mut None,
+        );
+        assert!(err.unwrap_err().to_string().contains("(BOM)"));
+    }
+
+    #[test]
+    fn test_write_index_to() {
+        let record = fixed_record();
+        let mut fastq: Vec<u8> = vec![];
+        for _ in 0..8 {
+            record.write(&mut fastq).unwrap();
+        }
+        let expected = FastqIndex::from(vec![record; 8].into_iter().map(Ok), 3, &mut None).unwrap();
+
+        // the bytes written (e.g. to stdout) are the index, as written to a file
+        let mut stdout: Vec<u8> = vec![];
+        let info = write_index_to(
+            &mut stdout,
+            &fastq[..],
+            CheckpointSpacing::Records(3),
+            false,
+            false,
+            0,
+            IndexProgress::default(),
+        )
+        .unwrap();
+        assert_eq!(FastqIndex::read_from(&stdout[..]).unwrap(), expected);
+        assert_eq!(info, IndexInfo::new(&expected));
+        let mut bytes: Vec<u8> = vec![];
+        expected.write_to(&mut bytes);
+        assert_eq!(stdout, bytes);
+
+        // with the CRC32 of the FASTQ
+        let mut stdout: Vec<u8> = vec![];
+        write_index_to(
+            &mut stdout,
+            &fastq[..],
+            CheckpointSpacing::Records(3),
+            true,
+            true,
+            0,
+            IndexProgress::default(),
+        )
+        .unwrap();
+        let index = FastqIndex::read_from(&stdout[..]).unwrap();
+        let mut crc = Crc::new();
+        crc.update(&fastq);
+        assert_eq!(index.source_crc32, Some(crc.sum()));
+        assert_eq!(index.entries, expected.entries);
+    }
+
+    /// Returns a description of the first entry that differs between the two indexes, if any
+    fn first_divergence(expected: &FastqIndex, actual: &FastqIndex) -> Option<String> {
+        let num_entries = expected.entries.len().max(actual.entries.len());
+        (0..num_entries)
+            .find(|&i| expected.entries.get(i) != actual.entries.get(i))
+            .map(|i| {
+                format!(
+                    "entry #{} differs: {:?} vs {:?}",
+                    i + 1,
+                    expected.entries.get(i),
+                    actual.entries.get(i)
+                )
+            })
+            .or_else(|| {
+                (expected.total_records != actual.total_records).then(|| {
+                    format!(
+                        "total records differ: {} vs {}",
+                        expected.total_records, actual.total_records
+                    )
+                })
+            })
+    }
+
+    #[test]
+    fn test_index_paths_agree() {
+        // Each way of indexing a FASTQ must match the serial reference, `from_raw_reader`, exactly,
+        // including when the number of records is zero, one, or either side of a multiple of nth
+        for nth in [1, 2, 3, 7, 100] {
+            for num_records in [0, 1, nth - 1, nth, nth + 1, 2 * nth, 2 * nth + 1, 250] {
+                let read_len = 1 + (num_records + nth) % 13;
+                let mut fastq: Vec<u8> = vec![];
+                write_synthetic_fastq(&mut fastq, num_records as usize, read_len as usize, nth)
+                    .unwrap();
+                let expected = FastqIndex::from_raw_reader(&fastq[..], nth, &mut None).unwrap();
+                for raw in [false, true] {
+                    let mut bytes: Vec<u8> = vec![];
+                    write_index_to(
+                        &mut bytes,
+                        &fastq[..],
+                        CheckpointSpacing::Records(nth),
+                        false,
+                        raw,
+                        0,
+                        IndexProgress::default(),
+                    )
+                    .unwrap();
+                    let actual = FastqIndex::read_from(&bytes[..]).unwrap();
+                    if let Some(divergence) = first_divergence(&expected, &actual) {
+                        panic!(
+                            "nth: {} records: {} raw: {}: {}",
+                            nth, num_records, raw, divergence
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_index_to_empty() {
+        for raw in [false, true] {
+            let mut stdout: Vec<u8> = vec![];
+            let info = write_index_to(
+                &mut stdout,
+                &b""[..],
+                CheckpointSpacing::Records(3),
+                true,
+                raw,
+                0,
+                IndexProgress::default(),
+            )
+            .unwrap();
+            let index = FastqIndex::read_from(&stdout[..]).unwrap();
+            assert_eq!(index.total_records, 0, "raw: {}", raw);
+            let empty = FastqIndexEntry { total_records: 0, total_bytes: 0, total_bases: Some(0) };
+            assert_eq!(index.entries, vec![empty]);
+            assert_eq!(index.source_crc32, Some(0), "raw: {}", raw);
+            assert_eq!(info, IndexInfo::new(&index), "raw: {}", raw);
+            assert_eq!(info.total_records, 0, "raw: {}", raw);
+            assert_eq!(info.mean_record_bytes, None, "raw: {}", raw);
+        }
+    }
+
+    #[test]
+    fn test_count_interval() {
+        capture_logs();
+        let record = fixed_record();
+        let fastq = to_fastq(&vec![record; 10]);
+        let count_logs = |nth: u64, interval: u64, raw: bool| {
+            let progress = IndexProgress { count_interval: Some(interval), ..Default::default() };
+            let spacing = CheckpointSpacing::Records(nth);
+            write_index_to(&mut vec![], &fastq[..], spacing, false, raw, 0, progress).unwrap();
+            captured_logs()
+        };
+        let expected = |records: &[u64]| -> Vec<String> {
+            records.iter().map(|records| format!("Indexed {} records", records)).collect()
+        };
+
+        for raw in [false, true] {
+            // every three records, however the checkpoints are spaced
+            for nth in [0, 1, 4, 20] {
+                assert_eq!(count_logs(nth, 3, raw), expected(&[3, 6, 9]), "nth: {}", nth);
+            }
+            assert_eq!(count_logs(2, 1, raw), expected(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]));
+            // not until the interval is reached
+            assert!(count_logs(1, 20, raw).is_empty());
+        }
+    }
+}
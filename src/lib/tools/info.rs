@@ -0,0 +1,205 @@
+use std::{
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::Parser;
+use env_logger::Env;
+
+use crate::utils::{built_info, BUFFERSIZE};
+
+use super::fastq_index::{CheckpointSpacing, FastqIndex};
+
+/// Summarizes a FASTQ index
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The input FASTQ index file, or `-` to read it from stdin.
+    #[clap(short = 'i', long, display_order = 1)]
+    pub input: PathBuf,
+
+    /// True to output JSON rather than a key/value table.
+    #[clap(long, display_order = 2)]
+    pub json: bool,
+}
+
+/// Summary information about a FASTQ index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    /// Index every Nth entry, or zero if only the totals are stored or the entries are spaced by
+    /// bytes
+    pub nth: u64,
+    /// The number of uncompressed bytes between entries, if spaced by bytes rather than records
+    pub nth_bytes: Option<u64>,
+    /// The total number of records in the FASTQ
+    pub total_records: u64,
+    /// The number of entries in the index
+    pub num_entries: usize,
+    /// The total number of uncompressed bytes in the FASTQ
+    pub total_bytes: u64,
+    /// The average number of bytes per record, or `None` if there are no records
+    pub mean_record_bytes: Option<f64>,
+}
+
+impl IndexInfo {
+    pub fn new(index: &FastqIndex) -> IndexInfo {
+        let total_bytes = index.entries.last().map_or(0, |entry| entry.total_bytes);
+        let (spacing, num_entries) = (index.spacing(), index.entries.len());
+        IndexInfo::from_totals(spacing, index.total_records, num_entries, total_bytes)
+    }
+
+    /// Builds the info from the index's totals, e.g. while the index is being written
+    pub fn from_totals(
+        spacing: CheckpointSpacing,
+        total_records: u64,
+        num_entries: usize,
+        total_bytes: u64,
+    ) -> IndexInfo {
+        let nth_bytes = match spacing {
+            CheckpointSpacing::Records(_) => None,
+            CheckpointSpacing::Bytes(nth_bytes) => Some(nth_bytes),
+        };
+        let mean_record_bytes =
+            if total_records == 0 { None } else { Some(total_bytes as f64 / total_records as f64) };
+        let nth = spacing.nth();
+        IndexInfo { nth, nth_bytes, total_records, num_entries, total_bytes, mean_record_bytes }
+    }
+
+    /// The key/value pairs to display, in display order.  Values that are not available are `None`.
+    fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("nth", Some(self.nth.to_string())),
+            ("nth_bytes", self.nth_bytes.map(|nth_bytes| nth_bytes.to_string())),
+            ("total_records", Some(self.total_records.to_string())),
+            ("num_entries", Some(self.num_entries.to_string())),
+            ("total_bytes", Some(self.total_bytes.to_string())),
+            ("mean_record_bytes", self.mean_record_bytes.map(|mean| format!("{:.2}", mean))),
+        ]
+    }
+
+    /// Writes the info as an aligned key/value table
+    pub fn write_table<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let fields = self.fields();
+        let width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        for (key, value) in fields {
+            let value = value.unwrap_or_else(|| "NA".to_string());
+            writeln!(writer, "{:width$}  {}", key, value, width = width)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the info as a single JSON object
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let values: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(key, value)| {
+                format!("\"{}\":{}", key, value.unwrap_or_else(|| "null".to_string()))
+            })
+            .collect();
+        writeln!(writer, "{{{}}}", values.join(","))
+    }
+}
+
+// Run info
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    let index = if opts.input == Path::new("-") {
+        FastqIndex::read_from(io::stdin())?
+    } else {
+        FastqIndex::read(opts.input.as_path())?
+    };
+    let info = IndexInfo::new(&index);
+
+    let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
+    if opts.json {
+        info.write_json(&mut writer)?;
+    } else {
+        info.write_table(&mut writer)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{testutil::index, tools::fastq_index::FastqIndex};
+
+    use super::IndexInfo;
+
+    #[test]
+    fn test_info_from_index() {
+        // write and read back the index to mimic the command
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        index(8).write(&path);
+        let info = IndexInfo::new(&FastqIndex::read(&path).unwrap());
+
+        let expected = IndexInfo {
+            nth: 3,
+            nth_bytes: None,
+            total_records: 8,
+            num_entries: 4,
+            total_bytes: 272,
+            mean_record_bytes: Some(34.0),
+        };
+        assert_eq!(info, expected);
+
+        let mut table: Vec<u8> = vec![];
+        info.write_table(&mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "nth                3\n\
+             nth_bytes          NA\n\
+             total_records      8\n\
+             num_entries        4\n\
+             total_bytes        272\n\
+             mean_record_bytes  34.00\n"
+        );
+
+        let mut json: Vec<u8> = vec![];
+        info.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"nth\":3,\"nth_bytes\":null,\"total_records\":8,\"num_entries\":4,\"total_bytes\":272,\
+             \"mean_record_bytes\":34.00}\n"
+        );
+    }
+
+    #[test]
+    fn test_info_nth_bytes() {
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nIIII\n@r3\nACGT\n+\nIIII\n";
+        let index = FastqIndex::from_raw_reader_by_bytes(&fastq[..], 20, &mut None).unwrap();
+        let info = IndexInfo::new(&index);
+        assert_eq!((info.nth, info.nth_bytes, info.num_entries), (0, Some(20), 3));
+        let mut table: Vec<u8> = vec![];
+        info.write_table(&mut table).unwrap();
+        assert!(String::from_utf8(table)
+            .unwrap()
+            .starts_with("nth                0\nnth_bytes          20\n"));
+    }
+
+    #[test]
+    fn test_info_empty_index() {
+        let info = IndexInfo::new(&index(0));
+        assert_eq!(info.total_records, 0);
+        assert_eq!(info.mean_record_bytes, None);
+
+        let mut json: Vec<u8> = vec![];
+        info.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json).unwrap().contains("\"mean_record_bytes\":null"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use env_logger::Env;
+use log::info;
+use seq_io::{fastq::OwnedRecord, BaseRecord};
+
+use crate::{
+    tools::{
+        bgzf_index::BgzfIndex,
+        extract::BgzfReader,
+        fastq_index::{FastqIndex, RawRecord},
+    },
+    types::{RecordNum, UncompressedOffset},
+    utils::{built_info, BUFFERSIZE},
+};
+
+/// Prints the record at an uncompressed byte offset in a bgzip'ed FASTQ
+///
+/// The first record that starts at or after the byte is written to stdout, and its record number
+/// and uncompressed offset are logged.  Useful for debugging byte offsets.
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The input bgzip'ed FASTQ file.
+    #[clap(short = 'f', long, display_order = 1)]
+    pub input: PathBuf,
+
+    /// The 0-based uncompressed byte offset.
+    #[clap(short = 'b', long, display_order = 2)]
+    pub byte: u64,
+}
+
+/// A record found by `locate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocatedRecord {
+    /// The 1-based record number
+    pub record_number: RecordNum,
+    /// The uncompressed offset at which the record starts
+    pub start_byte: UncompressedOffset,
+    pub record: OwnedRecord,
+}
+
+// Run locate
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    let located = locate(&opts.input, UncompressedOffset(opts.byte))?;
+    info!(
+        "Record #{} starts at byte {} (searched from byte {})",
+        located.record_number, located.start_byte, opts.byte
+    );
+    let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
+    located.record.write(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns the first record that starts at or after the given uncompressed byte.
+///
+/// The FASTQ index gives the first record of the indexed chunk that contains the byte (see
+/// `FastqIndex::record_at_byte`), so the BGZF reader is seeked to the start of that record and the
+/// records are read forward from there.  The record numbers are therefore exact, rather than
+/// relying on the first line starting with `@`, which may also be a quality line.  Errors if no
+/// record starts at or after the byte.
+pub fn locate(input: &Path, byte: UncompressedOffset) -> Result<LocatedRecord> {
+    let fqi_path = format!("{}.{}", input.to_string_lossy(), "fqi");
+    let fastq_index = FastqIndex::read(Path::new(&fqi_path))?;
+    let gzi_path = format!("{}.{}", input.to_string_lossy(), "gzi");
+    let gzi = BgzfIndex::from(gzi_path)?;
+
+    let total_bytes = fastq_index.entries.last().map_or(0, |entry| entry.total_bytes);
+    let first = match fastq_index.record_at_byte(byte) {
+        Some(record_number) => record_number,
+        None => bail!("Byte {} is past the end of the FASTQ ({} bytes)", byte, total_bytes),
+    };
+    // The first record of the chunk starts at an indexed entry, so there are no leading records
+    let fqi_range = fastq_index
+        .range(first, RecordNum(fastq_index.total_records))
+        .expect("the record containing the byte is in the index");
+    let start_entry = gzi.block_start(fqi_range.start_byte);
+
+    let file = File::open(input).with_context(|| format!("Could not open {}", input.display()))?;
+    let bgzf_reader = BgzfReader::new(file, fqi_range.start_byte, start_entry, fqi_range.end_byte)?;
+    let mut reader = BufReader::with_capacity(BUFFERSIZE, bgzf_reader);
+    let mut start_byte = fqi_range.start_byte;
+    let mut record_number = first;
+    while let Some((record, num_bytes)) = read_record(&mut reader, record_number)? {
+        if byte <= start_byte {
+            return Ok(LocatedRecord { record_number, start_byte, record });
+        }
+        start_byte += num_bytes;
+        record_number = RecordNum(record_number.0 + 1);
+    }
+    bail!("No record starts at or after byte {} (the FASTQ has {} bytes)", byte, total_bytes)
+}
+
+/// Reads the next record, returning the record and the number of bytes that it spans in the
+/// FASTQ, which includes any comment on the '+' line (as counted by `fqme index --raw`).  Returns
+/// `None` at the end of the FASTQ.
+fn read_record<R: BufRead>(
+    reader: &mut R,
+    record_number: RecordNum,
+) -> Result<Option<(OwnedRecord, u64)>> {
+    let mut record = RawRecord::default();
+    if record.read(reader)? == 0 {
+        return Ok(None);
+    }
+    record.check(record_number)?;
+    Ok(Some((record.to_owned_record(), record.num_bytes())))
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        testutil::{records, write_fastq},
+        types::{RecordNum, UncompressedOffset},
+    };
+
+    use super::locate;
+
+    #[test]
+    fn test_locate() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        // records 1-9 are 26 bytes, and record 10 is 27 bytes
+        let input = write_fastq(dir.path(), &records, 3, 50);
+
+        for (byte, record_number, start_byte) in
+            [(0, 1, 0), (1, 2, 26), (26, 2, 26), (130, 6, 130), (131, 7, 156), (234, 10, 234)]
+        {
+            let located = locate(&input, UncompressedOffset(byte)).unwrap();
+            assert_eq!(located.record_number, RecordNum(record_number), "byte: {}", byte);
+            assert_eq!(located.start_byte, UncompressedOffset(start_byte), "byte: {}", byte);
+            assert_eq!(located.record, records[record_number as usize - 1], "byte: {}", byte);
+        }
+
+        // within the last record, and past the end of the FASTQ
+        let err = locate(&input, UncompressedOffset(235)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No record starts at or after byte 235 (the FASTQ has 261 bytes)"
+        );
+        let err = locate(&input, UncompressedOffset(261)).unwrap_err();
+        assert_eq!(err.to_string(), "Byte 261 is past the end of the FASTQ (261 bytes)");
+    }
+}
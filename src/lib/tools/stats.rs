@@ -0,0 +1,316 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use env_logger::Env;
+use seq_io::{fastq::OwnedRecord, BaseRecord};
+
+use crate::{
+    tools::{bgzf_index::BgzfIndex, extract::BgzfReader, fastq_index::FastqIndex},
+    types::RecordNum,
+    utils::{built_info, BUFFERSIZE},
+};
+
+/// Computes statistics over a bgzip'ed FASTQ
+///
+/// All statistics require reading the full FASTQ, so may be slow for large files.
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The input bgzip'ed FASTQ file.
+    #[clap(short = 'f', long, display_order = 1)]
+    pub input: PathBuf,
+
+    /// Output a histogram of read lengths, followed by the read length quartiles.
+    #[clap(long, display_order = 2)]
+    pub lengths: bool,
+
+    /// Output the minimum and maximum quality score ASCII values, and the probable quality
+    /// encoding (Phred+33 or Phred+64).
+    #[clap(long, display_order = 3)]
+    pub detect_quality: bool,
+}
+
+/// A histogram of read lengths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LengthHistogram {
+    /// The number of reads for each read length
+    pub counts: BTreeMap<usize, u64>,
+    /// The total number of reads
+    pub total: u64,
+}
+
+impl LengthHistogram {
+    /// Adds a read with the given length
+    pub fn add(&mut self, length: usize) {
+        *self.counts.entry(length).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    /// Returns the read length at the given quantile (between zero and one, inclusive) using the
+    /// nearest-rank method, or `None` if there are no reads.
+    pub fn quantile(&self, quantile: f64) -> Option<usize> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((quantile * self.total as f64).ceil() as u64).max(1);
+        let mut num_seen: u64 = 0;
+        for (length, count) in &self.counts {
+            num_seen += count;
+            if rank <= num_seen {
+                return Some(*length);
+            }
+        }
+        self.counts.keys().next_back().copied()
+    }
+
+    /// Writes the histogram as `length\tcount` rows, followed by the quartiles as comment lines.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "length\tcount")?;
+        for (length, count) in &self.counts {
+            writeln!(writer, "{}\t{}", length, count)?;
+        }
+        let quartiles = [("min", 0.0), ("q1", 0.25), ("median", 0.5), ("q3", 0.75), ("max", 1.0)];
+        for (name, quantile) in quartiles {
+            match self.quantile(quantile) {
+                Some(length) => writeln!(writer, "# {}\t{}", name, length)?,
+                None => writeln!(writer, "# {}\tNA", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A quality score encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityEncoding {
+    /// Sanger and Illumina 1.8+, with quality scores from `!` (33)
+    Phred33,
+    /// Illumina 1.3 to 1.7, with quality scores from `@` (64)
+    Phred64,
+    /// No quality scores were observed, or all were in the range shared by both encodings
+    Unknown,
+}
+
+impl fmt::Display for QualityEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityEncoding::Phred33 => write!(f, "Phred+33"),
+            QualityEncoding::Phred64 => write!(f, "Phred+64"),
+            QualityEncoding::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The range of quality score ASCII values observed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QualityRange {
+    /// The minimum and maximum quality score ASCII values, or `None` if none were observed
+    pub min_max: Option<(u8, u8)>,
+}
+
+impl QualityRange {
+    /// Adds the quality scores of a read
+    pub fn add(&mut self, qual: &[u8]) {
+        for &q in qual {
+            self.min_max = Some(match self.min_max {
+                Some((min, max)) => (min.min(q), max.max(q)),
+                None => (q, q),
+            });
+        }
+    }
+
+    /// Returns the probable quality encoding.  Phred+64 scores are at least 64 (`@`), while
+    /// Phred+33 scores rarely exceed 74 (`J`, Q41), so the encoding is unknown if every score is
+    /// within 64 to 74.
+    pub fn encoding(&self) -> QualityEncoding {
+        match self.min_max {
+            Some((min, _)) if min < b'@' => QualityEncoding::Phred33,
+            Some((_, max)) if max > b'J' => QualityEncoding::Phred64,
+            _ => QualityEncoding::Unknown,
+        }
+    }
+
+    /// Writes the observed range and probable encoding as `name\tvalue` rows
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self.min_max {
+            Some((min, max)) => {
+                writeln!(writer, "min_quality\t{}", min)?;
+                writeln!(writer, "max_quality\t{}", max)?;
+            }
+            None => {
+                writeln!(writer, "min_quality\tNA")?;
+                writeln!(writer, "max_quality\tNA")?;
+            }
+        }
+        writeln!(writer, "encoding\t{}", self.encoding())
+    }
+}
+
+// Run stats
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    let mut writer = BufWriter::with_capacity(BUFFERSIZE, io::stdout());
+    stats(opts, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Computes the requested statistics, writing them to the given writer
+pub fn stats<W: Write>(opts: &Opts, writer: &mut W) -> Result<(), anyhow::Error> {
+    if !opts.lengths && !opts.detect_quality {
+        bail!("No statistics were requested; use --lengths or --detect-quality");
+    }
+
+    // Compute all the requested statistics in one pass over the FASTQ
+    let mut histogram = LengthHistogram::default();
+    let mut quality = QualityRange::default();
+    for_each_record(&opts.input, |rec| {
+        if opts.lengths {
+            histogram.add(rec.seq().len());
+        }
+        if opts.detect_quality {
+            quality.add(&rec.qual);
+        }
+    })?;
+
+    if opts.lengths {
+        histogram.write(writer)?;
+    }
+    if opts.detect_quality {
+        quality.write(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Builds a histogram of read lengths by reading all the records in the bgzip'ed FASTQ
+pub fn length_histogram(input: &Path) -> Result<LengthHistogram> {
+    let mut histogram = LengthHistogram::default();
+    for_each_record(input, |rec| histogram.add(rec.seq().len()))?;
+    Ok(histogram)
+}
+
+/// Calls the function on each of the records in the bgzip'ed FASTQ, in order
+fn for_each_record<F: FnMut(&OwnedRecord)>(input: &Path, mut f: F) -> Result<()> {
+    let fqi_path = format!("{}.{}", input.to_string_lossy(), "fqi");
+    let gzi_path = format!("{}.{}", input.to_string_lossy(), "gzi");
+
+    let fastq_index = FastqIndex::read(Path::new(&fqi_path))?;
+    let fqi_range = match fastq_index.range(RecordNum(1), RecordNum(fastq_index.total_records)) {
+        Some(range) => range,
+        None => return Ok(()),
+    };
+
+    let gzi = BgzfIndex::from(gzi_path)?;
+    let start_entry = gzi.block_start(fqi_range.start_byte);
+    let file = File::open(input)?;
+    let bgzf_reader = BgzfReader::new(file, fqi_range.start_byte, start_entry, fqi_range.end_byte)?;
+    // Only read the indexed bytes, so any data after the last record (e.g. blank lines) is ignored
+    let reader = bgzf_reader.take(fqi_range.num_bytes());
+    for result in seq_io::fastq::Reader::new(reader).into_records() {
+        f(&result?);
+    }
+    Ok(())
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use seq_io::fastq::OwnedRecord;
+    use tempfile::TempDir;
+
+    use crate::testutil::{self, write_fastq};
+
+    use super::{stats, LengthHistogram, Opts, QualityEncoding, QualityRange};
+
+    fn record(index: usize, length: usize) -> OwnedRecord {
+        OwnedRecord { seq: vec![b'A'; length], qual: vec![b'I'; length], ..testutil::record(index) }
+    }
+
+    #[test]
+    fn test_stats_lengths() {
+        let dir = TempDir::new().unwrap();
+        let lengths = [10, 25, 10, 10, 25, 10, 10];
+        let records: Vec<OwnedRecord> =
+            lengths.iter().enumerate().map(|(i, length)| record(i + 1, *length)).collect();
+        let input = write_fastq(dir.path(), &records, 3, 100);
+
+        let opts = Opts { input, lengths: true, detect_quality: false };
+        let mut output: Vec<u8> = vec![];
+        stats(&opts, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "length\tcount\n\
+             10\t5\n\
+             25\t2\n\
+             # min\t10\n\
+             # q1\t10\n\
+             # median\t10\n\
+             # q3\t25\n\
+             # max\t25\n"
+        );
+    }
+
+    #[test]
+    fn test_length_histogram_empty() {
+        let histogram = LengthHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        let mut output: Vec<u8> = vec![];
+        histogram.write(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("# median\tNA\n"));
+    }
+
+    #[test]
+    fn test_stats_detect_quality() {
+        let dir = TempDir::new().unwrap();
+        let quals: [&[u8]; 3] = [b"II#5", b"?+II", b"F:,F"];
+        let records: Vec<OwnedRecord> = quals
+            .iter()
+            .enumerate()
+            .map(|(i, qual)| OwnedRecord {
+                head: format!("read-{}", i + 1).into_bytes(),
+                seq: b"ACGT".to_vec(),
+                qual: qual.to_vec(),
+            })
+            .collect();
+        let input = write_fastq(dir.path(), &records, 1, 100);
+
+        let opts = Opts { input, lengths: false, detect_quality: true };
+        let mut output: Vec<u8> = vec![];
+        stats(&opts, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "min_quality\t35\nmax_quality\t73\nencoding\tPhred+33\n"
+        );
+    }
+
+    #[test]
+    fn test_quality_encoding() {
+        let encoding = |quals: &[&[u8]]| {
+            let mut range = QualityRange::default();
+            quals.iter().for_each(|qual| range.add(qual));
+            range.encoding()
+        };
+        assert_eq!(encoding(&[]), QualityEncoding::Unknown);
+        assert_eq!(encoding(&[b""]), QualityEncoding::Unknown);
+        assert_eq!(encoding(&[b"IIII", b"!"]), QualityEncoding::Phred33);
+        assert_eq!(encoding(&[b"hhhB", b"@"]), QualityEncoding::Phred64);
+        // every score is valid in both encodings
+        assert_eq!(encoding(&[b"@@JJ"]), QualityEncoding::Unknown);
+    }
+}
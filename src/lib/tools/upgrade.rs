@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::Parser;
+use env_logger::Env;
+use log::info;
+
+use crate::utils::built_info;
+
+use super::fastq_index::{FastqIndex, ALIGNED_FORMAT_VERSION, FORMAT_VERSION};
+
+/// Upgrades a FASTQ index to the current format
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The FASTQ index file to upgrade in place.
+    #[clap(short = 'i', long, display_order = 1)]
+    pub input: PathBuf,
+}
+
+/// Rewrites the FASTQ index in the current format, returning true if it was upgraded, or false if
+/// it was already in the current format, including an aligned index (see `ALIGNED_FORMAT_VERSION`).
+/// The index is replaced atomically.
+pub fn upgrade(path: &Path) -> Result<bool> {
+    let version = FastqIndex::read_version(path)?;
+    if matches!(version, Some(FORMAT_VERSION | ALIGNED_FORMAT_VERSION)) {
+        return Ok(false);
+    }
+    // Errors if the version is not supported
+    let index = FastqIndex::read(path)?;
+    index.write_atomically(path)?;
+    Ok(true)
+}
+
+// Run upgrade
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    if upgrade(&opts.input)? {
+        info!("Upgraded to format version {}: {}", FORMAT_VERSION, opts.input.display());
+    } else {
+        info!(
+            "Already in the current format (version {}): {}",
+            FORMAT_VERSION,
+            opts.input.display()
+        );
+    }
+    Ok(())
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use tempfile::TempDir;
+
+    use crate::{
+        testutil::fixed_record,
+        tools::fastq_index::{FastqIndex, FORMAT_VERSION},
+        types::RecordNum,
+    };
+
+    use super::upgrade;
+
+    #[test]
+    fn test_upgrade() {
+        let record = fixed_record();
+        let expected =
+            FastqIndex::from(vec![record; 10].into_iter().map(Ok), 3, &mut None).unwrap();
+
+        // a legacy index has no magic bytes, version, flags, or source CRC32
+        let mut bytes: Vec<u8> = vec![];
+        bytes.write_u64::<LittleEndian>(expected.total_records).unwrap();
+        bytes.write_u64::<LittleEndian>(expected.nth).unwrap();
+        for entry in &expected.entries {
+            bytes.write_u64::<LittleEndian>(entry.total_records).unwrap();
+            bytes.write_u64::<LittleEndian>(entry.total_bytes).unwrap();
+        }
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.fastq.gz.fqi");
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(FastqIndex::read_version(&path).unwrap(), None);
+        let legacy = FastqIndex::read(&path).unwrap();
+
+        assert!(upgrade(&path).unwrap());
+        assert_eq!(FastqIndex::read_version(&path).unwrap(), Some(FORMAT_VERSION));
+        let upgraded = FastqIndex::read(&path).unwrap();
+        assert_eq!(upgraded, legacy);
+        for (start, end) in [(1, 1), (2, 7), (4, 10), (10, 10)] {
+            let (start, end) = (RecordNum(start), RecordNum(end));
+            assert_eq!(upgraded.range(start, end), legacy.range(start, end));
+        }
+
+        // upgrading again is a no-op
+        let current = std::fs::read(&path).unwrap();
+        assert!(!upgrade(&path).unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), current);
+        assert!(!dir.path().join("test.fastq.gz.fqi.tmp").exists());
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use env_logger::Env;
+use log::info;
+
+use crate::{
+    tools::{
+        bgzf_index::BgzfIndex,
+        extract::{bgzf_crc32, BgzfReader},
+        fastq_index::FastqIndex,
+    },
+    types::UncompressedOffset,
+    utils::{built_info, TrailingBlankLines, BUFFERSIZE},
+};
+
+/// Verifies that a bgzip'ed FASTQ matches its FASTQ index
+///
+/// By default, the number of uncompressed bytes (and the CRC32, if stored in the index) is
+/// checked using only the BGZF block footers.  Use --full to decompress and parse the FASTQ.
+#[derive(Parser, Debug)]
+#[clap(name = "fq2bam", verbatim_doc_comment, version = built_info::VERSION.as_str())]
+pub struct Opts {
+    /// The input bgzip'ed FASTQ file.
+    #[clap(short = 'f', long, display_order = 1)]
+    pub input: PathBuf,
+
+    /// Decompress the whole FASTQ and check that its records match every entry in the index.
+    #[clap(long, display_order = 2)]
+    pub full: bool,
+}
+
+// Run verify
+pub fn run(opts: &Opts) -> Result<(), anyhow::Error> {
+    verify(opts)?;
+    info!("The bgzip'ed FASTQ matches its index: {}", opts.input.display());
+    Ok(())
+}
+
+/// Verifies the bgzip'ed FASTQ against its index, returning an error describing the first
+/// discrepancy found.
+pub fn verify(opts: &Opts) -> Result<()> {
+    let fqi_path = format!("{}.{}", opts.input.to_string_lossy(), "fqi");
+    let fastq_index = FastqIndex::read(Path::new(&fqi_path))?;
+    let mut file = File::open(&opts.input)
+        .with_context(|| format!("Could not open {}", opts.input.display()))?;
+
+    if !opts.full {
+        let (crc32, num_bytes) = bgzf_crc32(&mut file)?;
+        let total_bytes = fastq_index.entries.last().map_or(0, |entry| entry.total_bytes);
+        ensure!(
+            num_bytes == total_bytes,
+            "The bgzip'ed FASTQ has {} bytes, but the index has {} bytes",
+            num_bytes,
+            total_bytes
+        );
+        if let Some(expected_crc32) = fastq_index.source_crc32 {
+            ensure!(
+                crc32 == expected_crc32,
+                "The bgzip'ed FASTQ has CRC32 {:08x}, but the index has CRC32 {:08x}",
+                crc32,
+                expected_crc32
+            );
+        }
+        return Ok(());
+    }
+
+    let gzi_path = format!("{}.{}", opts.input.to_string_lossy(), "gzi");
+    let gzi = BgzfIndex::from(gzi_path)?;
+    let bgzf_reader = BgzfReader::new(
+        file,
+        UncompressedOffset(0),
+        gzi.block_start(UncompressedOffset(0)),
+        UncompressedOffset(u64::MAX),
+    )?;
+    // Ignore trailing blank lines, as `fqme index` does.  The bytes are counted as they are in the
+    // FASTQ, rather than as the parsed records would be written, so that a comment on the '+' line
+    // (kept by `fqme index --raw`) is counted.
+    let reader = TrailingBlankLines::new(BufReader::with_capacity(BUFFERSIZE, bgzf_reader));
+    let reader = BufReader::with_capacity(BUFFERSIZE, reader);
+    let mut actual = match fastq_index.nth_bytes {
+        Some(nth_bytes) => FastqIndex::from_raw_reader_by_bytes(reader, nth_bytes, &mut None)?,
+        None => FastqIndex::from_raw_reader(reader, fastq_index.nth, &mut None)?,
+    };
+    // An index written by an older version does not store the number of bases to compare
+    if !fastq_index.has_bases() {
+        for entry in &mut actual.entries {
+            entry.total_bases = None;
+        }
+    }
+    compare(&fastq_index, &actual)
+}
+
+/// Compares the index with one built from the FASTQ, reporting the first entry that differs.  The
+/// total number of records in the header is checked against the last entry when the index is read.
+fn compare(expected: &FastqIndex, actual: &FastqIndex) -> Result<()> {
+    for (i, (expected_entry, actual_entry)) in
+        expected.entries.iter().zip(actual.entries.iter()).enumerate()
+    {
+        ensure!(
+            expected_entry == actual_entry,
+            "The bgzip'ed FASTQ diverges from its index at entry #{}: the index has {} records in \
+             {} bytes, but the FASTQ has {} records in {} bytes",
+            i + 1,
+            expected_entry.total_records,
+            expected_entry.total_bytes,
+            actual_entry.total_records,
+            actual_entry.total_bytes
+        );
+    }
+    if expected.entries.len() != actual.entries.len() {
+        let (last_expected, last_actual) = (expected.entries.last(), actual.entries.last());
+        bail!(
+            "The index has {} entries, but the bgzip'ed FASTQ has {} ({} records in {} bytes, but \
+             the FASTQ has {} records in {} bytes)",
+            expected.entries.len(),
+            actual.entries.len(),
+            last_expected.map_or(0, |entry| entry.total_records),
+            last_expected.map_or(0, |entry| entry.total_bytes),
+            last_actual.map_or(0, |entry| entry.total_records),
+            last_actual.map_or(0, |entry| entry.total_bytes)
+        );
+    }
+    Ok(())
+}
+
+/// Parse args and set up logging / tracing
+pub fn setup() -> Opts {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    Opts::parse()
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        testutil::{records, write_fastq},
+        tools::fastq_index::FastqIndex,
+    };
+
+    use super::{verify, Opts};
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new().unwrap();
+        let records = records(10);
+        let input = write_fastq(dir.path(), &records, 3, 50);
+        for full in [false, true] {
+            verify(&Opts { input: input.clone(), full }).unwrap();
+        }
+
+        // the wrong number of records in the header
+        let fqi_path = dir.path().join("test.fastq.gz.fqi");
+        let index = FastqIndex::read(&fqi_path).unwrap();
+        FastqIndex { total_records: 11, ..index.clone() }.write(&fqi_path);
+        let err = verify(&Opts { input: input.clone(), full: true }).unwrap_err();
+        assert!(format!("{:#}", err)
+            .ends_with("FASTQ index header has 11 records but the last entry has 10 records"));
+
+        // an entry that does not match the FASTQ is reported
+        let mut entries = index.entries.clone();
+        entries[2].total_bytes += 1;
+        FastqIndex { entries, ..index.clone() }.write(&fqi_path);
+        let err = verify(&Opts { input: input.clone(), full: true }).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The bgzip'ed FASTQ diverges from its index at entry #3: the index has 6 records in \
+             157 bytes, but the FASTQ has 6 records in 156 bytes"
+        );
+
+        // an index missing the last record is found without decompressing
+        let mut entries = index.entries.clone();
+        entries.pop();
+        FastqIndex { total_records: 9, entries, ..index }.write(&fqi_path);
+        let err = verify(&Opts { input: input.clone(), full: false }).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The bgzip'ed FASTQ has 261 bytes, but the index has 234 bytes"
+        );
+        let err = verify(&Opts { input, full: true }).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The index has 4 entries, but the bgzip'ed FASTQ has 5 (9 records in 234 bytes, but \
+             the FASTQ has 10 records in 261 bytes)"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Newtypes for record numbers and byte offsets, so that one cannot be passed in place of another.
+//!
+//! Each wraps a `u64`, which is what is stored in the on-disk indexes.
+
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+/// Defines a newtype over `u64` with conversions to and from `u64`.
+macro_rules! u64_newtype {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub u64);
+
+        impl From<u64> for $name {
+            fn from(value: u64) -> Self {
+                $name(value)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+/// Defines a byte offset newtype: a number of bytes may be added to or subtracted from an offset,
+/// and the difference between two offsets is a number of bytes.
+macro_rules! offset_newtype {
+    ($(#[$meta:meta])* $name:ident) => {
+        u64_newtype!($(#[$meta])* $name);
+
+        impl Add<u64> for $name {
+            type Output = $name;
+
+            fn add(self, num_bytes: u64) -> $name {
+                $name(self.0 + num_bytes)
+            }
+        }
+
+        impl AddAssign<u64> for $name {
+            fn add_assign(&mut self, num_bytes: u64) {
+                self.0 += num_bytes;
+            }
+        }
+
+        impl Sub<u64> for $name {
+            type Output = $name;
+
+            fn sub(self, num_bytes: u64) -> $name {
+                $name(self.0 - num_bytes)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = u64;
+
+            fn sub(self, other: $name) -> u64 {
+                self.0 - other.0
+            }
+        }
+    };
+}
+
+u64_newtype!(
+    /// A 1-based record number in the FASTQ
+    RecordNum
+);
+
+/// A record given on the command line: either a 1-based record number, or a number of records
+/// counting back from the last record, such that `FromEnd(1)` is the last record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordPosition {
+    Number(RecordNum),
+    FromEnd(u64),
+}
+
+/// Converts a signed record, where a negative record counts back from the last record
+impl From<i64> for RecordPosition {
+    fn from(record: i64) -> Self {
+        if record < 0 {
+            RecordPosition::FromEnd(record.unsigned_abs())
+        } else {
+            RecordPosition::Number(RecordNum(record.unsigned_abs()))
+        }
+    }
+}
+
+impl From<RecordNum> for RecordPosition {
+    fn from(record: RecordNum) -> Self {
+        RecordPosition::Number(record)
+    }
+}
+
+/// Formats the record as it is given on the command line, with a record counting back from the
+/// last record as negative
+impl fmt::Display for RecordPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordPosition::Number(record) => record.fmt(f),
+            RecordPosition::FromEnd(from_end) => write!(f, "-{}", from_end),
+        }
+    }
+}
+
+offset_newtype!(
+    /// A 0-based byte offset in the uncompressed FASTQ
+    UncompressedOffset
+);
+
+offset_newtype!(
+    /// A 0-based byte offset in the bgzip'ed FASTQ
+    CompressedOffset
+);
+
+#[cfg(test)]
+mod test {
+    use super::{CompressedOffset, RecordNum, RecordPosition, UncompressedOffset};
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(RecordNum::from(3), RecordNum(3));
+        assert_eq!(u64::from(CompressedOffset(7)), 7);
+        let offset: UncompressedOffset = 10.into();
+        assert_eq!(offset + 5, UncompressedOffset(15));
+        assert_eq!(offset - 5, UncompressedOffset(5));
+        assert_eq!(UncompressedOffset(15) - offset, 5);
+        assert_eq!(offset.to_string(), "10");
+    }
+
+    #[test]
+    fn test_record_position() {
+        assert_eq!(RecordPosition::from(3), RecordPosition::Number(RecordNum(3)));
+        assert_eq!(RecordPosition::from(-2), RecordPosition::FromEnd(2));
+        assert_eq!(RecordPosition::from(RecordNum(4)), RecordPosition::Number(RecordNum(4)));
+        assert_eq!(RecordPosition::FromEnd(2).to_string(), "-2");
+        assert_eq!(RecordPosition::from(5).to_string(), "5");
+    }
+}
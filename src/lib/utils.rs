@@ -1,7 +1,337 @@
 //! Utility functions.
 
+use std::{
+    io::{self, BufRead, ErrorKind, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use libdeflater::Crc;
+
 pub const BUFFERSIZE: usize = 64 * 1024;
 
+/// Reads from the reader until the buffer is full or the end of the input is reached, returning
+/// the number of bytes read.  Unlike `Read::read_exact`, a short read at the end of the input is
+/// not an error, so callers can distinguish a clean end of input from a truncated one.
+pub fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut num_read = 0;
+    while num_read < buf.len() {
+        match reader.read(&mut buf[num_read..]) {
+            Ok(0) => break,
+            Ok(n) => num_read += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(num_read)
+}
+
+/// Returns the path of the temporary file to which the given output is written before it is
+/// renamed over the output.
+pub fn tmp_path(output: &Path) -> PathBuf {
+    let mut tmp_path = output.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// A reader that drops blank (whitespace-only) lines at the end of the input, so that a FASTQ
+/// with a trailing blank line ends cleanly.  Only blank lines that follow a complete four-line
+/// record are dropped, so the empty sequence and quality lines of a zero-length record are kept.
+/// Blank lines that are followed by other data are passed through unchanged, so a blank line
+/// between records is still an error when parsed.
+pub struct TrailingBlankLines<R: BufRead> {
+    inner: R,
+    /// Blank lines that have been read but not output, as they may be at the end of the input
+    withheld: Vec<u8>,
+    /// The number of withheld bytes that have been output
+    withheld_pos: usize,
+    /// True if the withheld bytes are followed by other data and so must be output
+    flush_withheld: bool,
+    /// True if the last byte output was a newline
+    after_newline: bool,
+    /// The number of complete lines output
+    num_lines: u64,
+}
+
+impl<R: BufRead> TrailingBlankLines<R> {
+    pub fn new(inner: R) -> TrailingBlankLines<R> {
+        TrailingBlankLines {
+            inner,
+            withheld: vec![],
+            withheld_pos: 0,
+            flush_withheld: false,
+            after_newline: true,
+            num_lines: 0,
+        }
+    }
+
+    /// Updates the line count and whether the output ends with a newline after outputting `data`
+    fn output(&mut self, data: &[u8]) {
+        self.num_lines += data.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.after_newline = data.last() == Some(&b'\n');
+    }
+}
+
+impl<R: BufRead> Read for TrailingBlankLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.flush_withheld {
+                let remaining = &self.withheld[self.withheld_pos..];
+                let n = std::cmp::min(buf.len(), remaining.len());
+                buf[..n].copy_from_slice(&remaining[..n]);
+                self.withheld_pos += n;
+                if self.withheld_pos == self.withheld.len() {
+                    self.withheld.clear();
+                    self.withheld_pos = 0;
+                    self.flush_withheld = false;
+                }
+                self.output(&buf[..n]);
+                return Ok(n);
+            }
+
+            let chunk = self.inner.fill_buf()?;
+            if chunk.is_empty() {
+                // Any withheld blank lines are at the end of the input, so drop them
+                return Ok(0);
+            }
+            let is_blank = chunk.iter().all(u8::is_ascii_whitespace);
+
+            // Withhold blank lines after a complete record until they are followed by other data
+            let after_record = self.after_newline && self.num_lines.is_multiple_of(4);
+            if is_blank && (after_record || !self.withheld.is_empty()) {
+                self.withheld.extend_from_slice(chunk);
+                let len = chunk.len();
+                self.inner.consume(len);
+                continue;
+            } else if !self.withheld.is_empty() {
+                self.flush_withheld = true;
+                continue;
+            }
+
+            // Output up to the end of the last non-blank line, or if there is none, the first line,
+            // so that the trailing whitespace is only withheld after a complete record
+            let whitespace_start =
+                chunk.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+            let end = chunk[whitespace_start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(chunk.len(), |i| whitespace_start + i + 1);
+            let n = std::cmp::min(buf.len(), end);
+            buf[..n].copy_from_slice(&chunk[..n]);
+            self.inner.consume(n);
+            self.output(&buf[..n]);
+            return Ok(n);
+        }
+    }
+}
+
+/// The number of chunks of bytes that may be queued for the CRC32 thread (see `ParallelCrc32`)
+const PARALLEL_CRC32_CHUNKS: usize = 4;
+
+/// Computes the CRC32 of the bytes read from, or written to, the inner reader or writer on a
+/// separate thread, so that the checksum is computed in parallel with parsing the bytes.  The
+/// bytes are copied and sent to the thread in chunks of `BUFFERSIZE` bytes.  Use `sum` to wait for
+/// the thread and return the CRC32.
+pub struct ParallelCrc32<T> {
+    inner: T,
+    // the bytes not yet sent to the thread
+    chunk: Vec<u8>,
+    sender: SyncSender<Vec<u8>>,
+    handle: JoinHandle<u32>,
+}
+
+impl<T> ParallelCrc32<T> {
+    pub fn new(inner: T) -> ParallelCrc32<T> {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(PARALLEL_CRC32_CHUNKS);
+        let handle = thread::spawn(move || {
+            let mut crc = Crc::new();
+            for chunk in receiver {
+                crc.update(&chunk);
+            }
+            crc.sum()
+        });
+        ParallelCrc32 { inner, chunk: Vec::with_capacity(BUFFERSIZE), sender, handle }
+    }
+
+    /// Waits for the thread to checksum the bytes read or written so far, and returns the CRC32
+    pub fn sum(self) -> u32 {
+        let ParallelCrc32 { chunk, sender, handle, .. } = self;
+        // The send only fails if the thread has panicked, which is raised by `join`
+        let _ignored = sender.send(chunk);
+        drop(sender);
+        handle.join().expect("The CRC32 thread panicked")
+    }
+
+    /// Queues the bytes to be checksummed, sending them to the thread once a chunk is full
+    fn update(&mut self, bytes: &[u8]) {
+        self.chunk.extend_from_slice(bytes);
+        if BUFFERSIZE <= self.chunk.len() {
+            let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(BUFFERSIZE));
+            let _ignored = self.sender.send(chunk);
+        }
+    }
+}
+
+impl<T: Read> Read for ParallelCrc32<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_read = self.inner.read(buf)?;
+        self.update(&buf[..num_read]);
+        Ok(num_read)
+    }
+}
+
+impl<T: Write> Write for ParallelCrc32<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num_written = self.inner.write(buf)?;
+        self.update(&buf[..num_written]);
+        Ok(num_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts the bytes written to the inner writer.
+pub struct ByteCountingWriter<W: Write> {
+    inner: W,
+    num_bytes: u64,
+}
+
+impl<W: Write> ByteCountingWriter<W> {
+    pub fn new(inner: W) -> ByteCountingWriter<W> {
+        ByteCountingWriter { inner, num_bytes: 0 }
+    }
+
+    /// The number of bytes written so far
+    pub fn num_bytes(&self) -> u64 {
+        self.num_bytes
+    }
+}
+
+impl<W: Write> Write for ByteCountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num_written = self.inner.write(buf)?;
+        self.num_bytes += num_written as u64;
+        Ok(num_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Optionally withholds the final newline of the bytes written to the inner writer.  When
+/// stripping, a newline at the end of a write is only written before the next non-empty write, so
+/// the output never ends with a newline.
+pub struct TrailingNewlineWriter<W: Write> {
+    inner: W,
+    strip: bool,
+    // true if a newline has been withheld
+    pending: bool,
+}
+
+impl<W: Write> TrailingNewlineWriter<W> {
+    pub fn new(inner: W, strip: bool) -> TrailingNewlineWriter<W> {
+        TrailingNewlineWriter { inner, strip, pending: false }
+    }
+}
+
+impl<W: Write> Write for TrailingNewlineWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.strip || buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        if self.pending {
+            self.inner.write_all(b"\n")?;
+            self.pending = false;
+        }
+        match buf.split_last() {
+            Some((b'\n', rest)) => {
+                self.inner.write_all(rest)?;
+                self.pending = true;
+                Ok(buf.len())
+            }
+            _ => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Multiplies the GF(2) 32x32 matrix by the vector
+fn gf2_matrix_times(matrix: &[u32; 32], mut vector: u32) -> u32 {
+    let mut sum: u32 = 0;
+    for row in matrix {
+        if vector == 0 {
+            break;
+        }
+        if vector & 1 == 1 {
+            sum ^= row;
+        }
+        vector >>= 1;
+    }
+    sum
+}
+
+/// Returns the square of the GF(2) 32x32 matrix
+fn gf2_matrix_square(matrix: &[u32; 32]) -> [u32; 32] {
+    let mut square = [0u32; 32];
+    for (square_row, row) in square.iter_mut().zip(matrix.iter()) {
+        *square_row = gf2_matrix_times(matrix, *row);
+    }
+    square
+}
+
+/// Combines the CRC32 of two byte sequences into the CRC32 of their concatenation, given the
+/// length of the second sequence.  This is zlib's `crc32_combine`, which allows the CRC32 of a
+/// BGZF file's uncompressed data to be computed from the CRC32s stored in its blocks' footers.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // The operator for one zero bit
+    let mut odd = [0u32; 32];
+    odd[0] = 0xedb8_8320; // the CRC32 polynomial
+    for (n, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (n - 1);
+    }
+    // The operators for two and four zero bits
+    let mut even = gf2_matrix_square(&odd);
+    odd = gf2_matrix_square(&even);
+
+    // Apply len2 zero bytes to crc1, squaring the operator for each bit of len2
+    let mut crc1 = crc1;
+    let mut len2 = len2;
+    loop {
+        even = gf2_matrix_square(&odd);
+        if len2 & 1 == 1 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+        odd = gf2_matrix_square(&even);
+        if len2 & 1 == 1 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+    crc1 ^ crc2
+}
+
 pub mod built_info {
     use lazy_static::lazy_static;
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -29,3 +359,102 @@ pub mod built_info {
         pub static ref VERSION: String = get_software_version();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, BufReader, Read, Write};
+
+    use libdeflater::Crc;
+
+    use super::{
+        crc32_combine, ByteCountingWriter, ParallelCrc32, TrailingBlankLines,
+        TrailingNewlineWriter, BUFFERSIZE,
+    };
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc::new();
+        crc.update(data);
+        crc.sum()
+    }
+
+    fn trim(data: &[u8], capacity: usize) -> Vec<u8> {
+        let mut reader = TrailingBlankLines::new(BufReader::with_capacity(capacity, data));
+        let mut output: Vec<u8> = vec![];
+        reader.read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_trailing_blank_lines() {
+        // use a tiny buffer to split the input across many reads
+        for capacity in [1, 2, 3, 64] {
+            assert_eq!(trim(b"", capacity), b"");
+            assert_eq!(trim(b"@r\nA\n+\nI\n", capacity), b"@r\nA\n+\nI\n");
+            assert_eq!(trim(b"@r\nA\n+\nI", capacity), b"@r\nA\n+\nI");
+            assert_eq!(trim(b"@r\nA\n+\nI\n\n", capacity), b"@r\nA\n+\nI\n");
+            assert_eq!(trim(b"@r\nA\n+\nI\n\n\n", capacity), b"@r\nA\n+\nI\n");
+            assert_eq!(trim(b"@r\nA\n+\nI\n\n \n\t", capacity), b"@r\nA\n+\nI\n");
+            assert_eq!(trim(b"@r\nA\n+\nI\r\n \r\n\n\t", capacity), b"@r\nA\n+\nI\r\n");
+            assert_eq!(trim(b"\n\n", capacity), b"");
+            // blank lines followed by data are kept
+            assert_eq!(trim(b"@r\n\n  \nA\n\n", capacity), b"@r\n\n  \nA\n");
+            assert_eq!(trim(b"@r\nA\n+\nI\n\n@s\n", capacity), b"@r\nA\n+\nI\n\n@s\n");
+            // the empty sequence and quality lines of a zero-length record are kept
+            assert_eq!(trim(b"@r\n\n+\n\n", capacity), b"@r\n\n+\n\n");
+            assert_eq!(trim(b"@r\n\n+\n\n\n", capacity), b"@r\n\n+\n\n");
+            assert_eq!(trim(b"@r\n\n+\n", capacity), b"@r\n\n+\n");
+            assert_eq!(trim(b"@r\n\n+", capacity), b"@r\n\n+");
+        }
+    }
+
+    #[test]
+    fn test_parallel_crc32() {
+        // span several chunks, with a partial chunk at the end
+        let data: Vec<u8> = (0..3 * BUFFERSIZE + 17).map(|i| (i * 7 % 251) as u8).collect();
+        for data in [&data[..], &data[..BUFFERSIZE], &[]] {
+            let mut reader = ParallelCrc32::new(BufReader::with_capacity(1000, data));
+            io::copy(&mut reader, &mut io::sink()).unwrap();
+            assert_eq!(reader.sum(), crc32(data));
+
+            let mut writer = ParallelCrc32::new(io::sink());
+            for bytes in data.chunks(1000) {
+                writer.write_all(bytes).unwrap();
+            }
+            assert_eq!(writer.sum(), crc32(data));
+        }
+    }
+
+    #[test]
+    fn test_byte_counting_writer() {
+        let mut output: Vec<u8> = vec![];
+        let mut writer = ByteCountingWriter::new(&mut output);
+        assert_eq!(writer.num_bytes(), 0);
+        writer.write_all(b"@r1\nACGT\n").unwrap();
+        writer.write_all(b"+\nIIII\n").unwrap();
+        assert_eq!(writer.num_bytes(), 16);
+        assert_eq!(output.len(), 16);
+    }
+
+    #[test]
+    fn test_trailing_newline_writer() {
+        for (strip, expected) in [(false, &b"@r1\nACGT\n\n"[..]), (true, &b"@r1\nACGT\n"[..])] {
+            let mut output: Vec<u8> = vec![];
+            let mut writer = TrailingNewlineWriter::new(&mut output, strip);
+            writer.write_all(b"@r1\n").unwrap();
+            writer.write_all(b"").unwrap();
+            writer.write_all(b"ACGT\n\n").unwrap();
+            writer.flush().unwrap();
+            assert_eq!(output, expected, "strip: {}", strip);
+        }
+    }
+
+    #[test]
+    fn test_crc32_combine() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        for split in [0, 1, 2, 3, 100, 4_999, 9_999, 10_000] {
+            let (first, second) = data.split_at(split);
+            let combined = crc32_combine(crc32(first), crc32(second), second.len() as u64);
+            assert_eq!(combined, crc32(&data), "split at {}", split);
+        }
+    }
+}
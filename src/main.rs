@@ -1,28 +1,55 @@
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
-use std::process::exit;
+use std::{path::PathBuf, process::exit};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use env_logger::Env;
+use fqme_lib::config::Config;
+use fqme_lib::tools::diff::{run as diff, Opts as DiffOpts};
 use fqme_lib::tools::extract::{run as extract, Opts as ExtractOpts};
 use fqme_lib::tools::index::{run as index, Opts as IndexOpts};
+use fqme_lib::tools::info::{run as info, Opts as InfoOpts};
+use fqme_lib::tools::locate::{run as locate, Opts as LocateOpts};
+use fqme_lib::tools::stats::{run as stats, Opts as StatsOpts};
+use fqme_lib::tools::upgrade::{run as upgrade, Opts as UpgradeOpts};
+use fqme_lib::tools::verify::{run as verify, Opts as VerifyOpts};
 use log::error;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    /// A TOML config file of defaults for the options of each command, which options given on the
+    /// command line override.  By default, `fqme.toml` in the current directory is used if it
+    /// exists.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
 // This is where sub-commands are added.  The value of each enum should be the corresponding option
 // struct
+// The options are parsed once, so the size of the largest variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
+    /// Compares two FASTQ indexes
+    Diff(DiffOpts),
     /// Extracts byte offset and length from a FASTQ index
     Extract(ExtractOpts),
     /// Index a FASTQ
     Index(IndexOpts),
+    /// Summarizes a FASTQ index
+    Info(InfoOpts),
+    /// Prints the record at an uncompressed byte offset in a bgzip'ed FASTQ
+    Locate(LocateOpts),
+    /// Computes statistics over a bgzip'ed FASTQ (reads the full FASTQ)
+    Stats(StatsOpts),
+    /// Upgrades a FASTQ index to the current format
+    Upgrade(UpgradeOpts),
+    /// Verifies that a bgzip'ed FASTQ matches its FASTQ index
+    Verify(VerifyOpts),
 }
 
 #[allow(clippy::too_many_lines)]
@@ -32,11 +59,34 @@ fn main() {
     }
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    // Options not given on the command line are taken from the config file, if any
+    let config = match Config::find(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{:#}", err);
+            exit(1);
+        }
+    };
+    if let Some((_, sub_matches)) = matches.subcommand() {
+        match &mut cli.command {
+            Commands::Extract(opts) => config.apply_extract(opts, sub_matches),
+            Commands::Index(opts) => config.apply_index(opts),
+            _ => (),
+        }
+    }
 
     let result = match &cli.command {
+        Commands::Diff(opts) => diff(opts),
         Commands::Extract(opts) => extract(opts),
         Commands::Index(opts) => index(opts),
+        Commands::Info(opts) => info(opts),
+        Commands::Locate(opts) => locate(opts),
+        Commands::Stats(opts) => stats(opts),
+        Commands::Upgrade(opts) => upgrade(opts),
+        Commands::Verify(opts) => verify(opts),
     };
 
     if let Err(err) = result {